@@ -1,4 +1,5 @@
 import Database from '@tauri-apps/plugin-sql';
+import { invoke } from '@tauri-apps/api/core';
 import { v7 as uuid } from 'uuid';
 import { encode } from 'js-base64';
 import * as Types from './types';
@@ -1683,17 +1684,6 @@ export const updateAutoCheckUpdate = async (props: {
   );
 };
 
-export const updateServiceRunningState = async (props: {
-  userID: string;
-  serviceRunningState: boolean;
-}) => {
-  const db = await initDb();
-  await db.execute(
-    `UPDATE AppStatus SET ServiceRunningState = ? WHERE UserID = ?`,
-    [props.serviceRunningState ? 1 : 0, props.userID],
-  );
-};
-
 export const queryLatencyTestSettings = async (props: {
   userID: string;
 }): Promise<{ url: string; timeout: number }> => {
@@ -1814,27 +1804,129 @@ export const updateSubscription = async (props: {
   }
 };
 
-export const updateAllSubscriptions = async (props: {
-  userID: string;
+export const updateSubscriptionInterval = async (props: {
+  subscriptionID: string;
+  interval: number;
 }): Promise<void> => {
   const db = await initDb();
+  await db.execute(
+    'UPDATE Subscriptions SET UpdateInterval = ? WHERE SubscriptionID = ?',
+    [props.interval, props.subscriptionID],
+  );
+};
 
-  // Get all subscriptions for the user
-  const subscriptions = await querySubscriptions(props);
+export const updateSubscriptionFetchOptions = async (props: {
+  subscriptionID: string;
+  viaProxy: boolean;
+  userAgent?: string;
+  headers?: Record<string, string>;
+  timeout: number;
+  allowInsecure: boolean;
+}): Promise<void> => {
+  const db = await initDb();
+  await db.execute(
+    `UPDATE Subscriptions
+     SET FetchViaProxy = ?, UserAgent = ?, Headers = ?, Timeout = ?, AllowInsecure = ?
+     WHERE SubscriptionID = ?`,
+    [
+      props.viaProxy ? 1 : 0,
+      props.userAgent || null,
+      props.headers ? JSON.stringify(props.headers) : null,
+      props.timeout,
+      props.allowInsecure ? 1 : 0,
+      props.subscriptionID,
+    ],
+  );
+};
 
-  // In a real implementation, you would:
-  // 1. Fetch content from each subscription URL
-  // 2. Parse the content to extract endpoints
-  // 3. Add or update endpoints in the database
-  // 4. Remove endpoints that are no longer in the subscription
+// Stores the rules applied to a subscription's entries at import time
+export const updateSubscriptionRules = async (props: {
+  subscriptionID: string;
+  includeRemarks?: string;
+  excludeRemarks?: string;
+  includeProtocols?: string;
+  excludeProtocols?: string;
+  renameTemplate?: string;
+  dedupe: boolean;
+}): Promise<void> => {
+  const db = await initDb();
+  await db.execute(
+    `UPDATE Subscriptions
+     SET IncludeRemarks = ?, ExcludeRemarks = ?, IncludeProtocols = ?, ExcludeProtocols = ?,
+         RenameTemplate = ?, Dedupe = ?
+     WHERE SubscriptionID = ?`,
+    [
+      props.includeRemarks || null,
+      props.excludeRemarks || null,
+      props.includeProtocols || null,
+      props.excludeProtocols || null,
+      props.renameTemplate || null,
+      props.dedupe ? 1 : 0,
+      props.subscriptionID,
+    ],
+  );
+};
 
-  // This is a placeholder implementation
-  // You'll need to add actual subscription fetching and parsing logic
+// Fetches a subscription and diffs it into its group
+export const refreshSubscription = async (props: {
+  subscriptionID: string;
+  force?: boolean;
+}): Promise<Types.SubscriptionUpdate> => {
+  return await invoke<Types.SubscriptionUpdate>('update_subscription', {
+    subscriptionId: props.subscriptionID,
+    force: props.force ?? false,
+  });
+};
 
-  // For now, we'll just log that update occurred
-  console.log(
-    `Updated subscriptions for user ${props.userID} at ${new Date().toISOString()}`,
-  );
+export const updateAllSubscriptions = async (props: {
+  userID: string;
+  force?: boolean;
+}): Promise<Types.SubscriptionUpdate[]> => {
+  return await invoke<Types.SubscriptionUpdate[]>('update_all_subscriptions', {
+    userId: props.userID,
+    force: props.force ?? false,
+  });
+};
+
+export const querySubscriptionUsage = async (props: {
+  userID: string;
+}): Promise<Types.SubscriptionUsage[]> => {
+  return await invoke<Types.SubscriptionUsage[]>('get_subscription_usage', {
+    userId: props.userID,
+  });
+};
+
+// Serves a group as a subscription to other devices on the LAN
+export const startShareServer = async (props: {
+  userID: string;
+  groupID: string;
+  bindAddress?: string;
+  port?: number;
+  resetToken?: boolean;
+}): Promise<Types.ShareServerStatus> => {
+  return await invoke<Types.ShareServerStatus>('start_share_server', {
+    userId: props.userID,
+    groupId: props.groupID,
+    bindAddress: props.bindAddress,
+    port: props.port,
+    resetToken: props.resetToken ?? false,
+  });
+};
+
+export const stopShareServer = async (props: {
+  userID: string;
+}): Promise<Types.ShareServerStatus> => {
+  return await invoke<Types.ShareServerStatus>('stop_share_server', {
+    userId: props.userID,
+  });
+};
+
+export const queryShareServerStatus = async (props: {
+  userID: string;
+}): Promise<Types.ShareServerStatus> => {
+  return await invoke<Types.ShareServerStatus>('get_share_server_status', {
+    userId: props.userID,
+  });
 };
 
 // Helper function to create or get EndpointsGroups for a subscription
@@ -1904,12 +1996,6 @@ export const parseSubscriptionAndCreateEndpoints = async (props: {
   link: string;
   subscriptionData: string;
 }): Promise<{ success: number; failed: number }> => {
-  const { VMess, Shadowsocks, Trojan, Hysteria2 } =
-    await import('~/lib/protocol');
-
-  let successCount = 0;
-  let failedCount = 0;
-
   // Ensure the endpoints group exists
   await ensureEndpointsGroup({
     userID: props.userID,
@@ -1919,291 +2005,19 @@ export const parseSubscriptionAndCreateEndpoints = async (props: {
     link: props.link,
   });
 
-  // Delete all existing endpoints in this group before adding new ones
-  await deleteEndpointsInGroup({ groupID: props.groupID });
-
-  // Parse subscription data - split by lines
-  const lines = props.subscriptionData
-    .split('\n')
-    .map((line) => line.trim())
-    .filter((line) => line.length > 0);
-
-  for (const line of lines) {
-    try {
-      let protocolFactory: any = null;
-      let protocol = '';
-      let endpointID = uuid();
-      let link = '';
-      let remark = '';
-      let stream = 'tcp';
-      let security = 'none';
-
-      // Determine protocol type and create protocol factory
-      if (/^vmess:\/\//i.test(line)) {
-        protocolFactory = new VMess(line);
-        protocol = 'vmess';
-      } else if (/^ss:\/\//i.test(line)) {
-        protocolFactory = new Shadowsocks(line);
-        protocol = 'shadowsocks';
-      } else if (/^trojan:\/\//i.test(line)) {
-        protocolFactory = new Trojan(line);
-        protocol = 'trojan';
-      } else if (/^hysteria2:\/\//i.test(line)) {
-        protocolFactory = new Hysteria2(line);
-        protocol = 'hysteria2';
-      } else {
-        console.warn(
-          `Unsupported protocol in line: ${line.substring(0, 50)}...`,
-        );
-        failedCount++;
-        continue;
-      }
-
-      // Get link and remark
-      link = protocolFactory.getLink();
-      remark = protocolFactory.getPs() || `Endpoint ${successCount + 1}`;
-
-      // Get stream and security from outbound
-      const outbound = protocolFactory.getOutbound();
-      stream = outbound.streamSettings?.network || 'tcp';
-      security = outbound.streamSettings?.security || 'none';
-
-      // Create base endpoint info directly with the correct subscription group
-      const db = await initDb();
-      await db.execute(
-        `
-        INSERT INTO Endpoints (
-          EndpointID, Link, Remark, Latency, GroupID, GroupName
-        ) VALUES (?, ?, ?, ?, ?, ?)
-        `,
-        [
-          endpointID,
-          link,
-          remark,
-          null,
-          props.groupID,
-          props.remark || 'Subscription Group',
-        ],
-      );
-
-      await db.execute(
-        `
-        INSERT INTO Outbounds (EndpointID, Protocol, Tag)
-        VALUES (?, ?, ?)
-        `,
-        [endpointID, protocol, null],
-      );
-
-      await db.execute(
-        `
-        INSERT INTO StreamSettings (EndpointID, Network, Security)
-        VALUES (?, ?, ?)
-        `,
-        [endpointID, stream, security],
-      );
-
-      // Add protocol-specific data
-      switch (protocol) {
-        case 'vmess': {
-          const settings = outbound.settings as any;
-          if (settings?.vnext?.[0]?.users?.[0]) {
-            const vnext = settings.vnext[0];
-            const user = vnext.users[0];
-            await addVmess({
-              endpointID,
-              vmess: {
-                address: vnext.address,
-                port: vnext.port,
-                uuid: user.id,
-                alterID: user.alterId || 0,
-                security: user.security || 'auto',
-                level: user.level || 0,
-              },
-            });
-          }
-          break;
-        }
-        case 'shadowsocks': {
-          const settings = outbound.settings as any;
-          if (settings?.servers?.[0]) {
-            const server = settings.servers[0];
-            await addShadowsocks({
-              endpointID,
-              shadowsocks: {
-                address: server.address,
-                port: server.port,
-                method: server.method,
-                password: server.password,
-                level: server.level || 0,
-              },
-            });
-          }
-          break;
-        }
-        case 'trojan': {
-          const settings = outbound.settings as any;
-          if (settings?.servers?.[0]) {
-            const server = settings.servers[0];
-            await addTrojan({
-              endpointID,
-              trojan: {
-                address: server.address,
-                port: server.port,
-                password: server.password,
-                level: server.level || 0,
-              },
-            });
-          }
-          break;
-        }
-        case 'hysteria2': {
-          const settings = outbound.settings as any;
-          if (settings?.servers?.[0]) {
-            const server = settings.servers[0];
-            await addHysteria2({
-              endpointID,
-              hysteria2: {
-                address: server.address,
-                port: server.port,
-              },
-            });
-          }
-          break;
-        }
-      }
-
-      // Add stream settings
-      const streamSettings = outbound.streamSettings;
-      if (streamSettings) {
-        switch (stream) {
-          case 'ws':
-            if (streamSettings.wsSettings) {
-              await addWebSocketStream({
-                endpointID,
-                stream: {
-                  path: streamSettings.wsSettings.path || '/',
-                  host: streamSettings.wsSettings.headers?.host || '',
-                },
-              });
-            }
-            break;
-          case 'h2':
-            if (streamSettings.httpSettings) {
-              await addHttp2Stream({
-                endpointID,
-                http: {
-                  host: Array.isArray(streamSettings.httpSettings.host)
-                    ? streamSettings.httpSettings.host[0] || ''
-                    : streamSettings.httpSettings.host || '',
-                  path: streamSettings.httpSettings.path || '/',
-                  method: 'PUT',
-                },
-              });
-            }
-            break;
-          case 'grpc':
-            if (streamSettings.grpcSettings) {
-              await addGrpcStream({
-                endpointID,
-                grpc: {
-                  serviceName: streamSettings.grpcSettings.serviceName || '',
-                },
-              });
-            }
-            break;
-          case 'kcp':
-            if (streamSettings.kcpSettings) {
-              await addKcpStream({
-                endpointID,
-                kcp: {
-                  mtu: streamSettings.kcpSettings.mtu || 1350,
-                  tti: streamSettings.kcpSettings.tti || 50,
-                  uplinkCapacity:
-                    streamSettings.kcpSettings.uplinkCapacity || 5,
-                  downlinkCapacity:
-                    streamSettings.kcpSettings.downlinkCapacity || 20,
-                  congestion: streamSettings.kcpSettings.congestion || false,
-                  readBufferSize:
-                    streamSettings.kcpSettings.readBufferSize || 2,
-                  writeBufferSize:
-                    streamSettings.kcpSettings.writeBufferSize || 2,
-                  header: streamSettings.kcpSettings.header?.type || 'none',
-                },
-              });
-            }
-            break;
-          case 'quic':
-            if (streamSettings.quicSettings) {
-              await addQuicStream({
-                endpointID,
-                quic: {
-                  key: streamSettings.quicSettings.key || '',
-                  security: streamSettings.quicSettings.security || 'none',
-                  header: streamSettings.quicSettings.header?.type || 'none',
-                },
-              });
-            }
-            break;
-          case 'tcp':
-            if (streamSettings.tcpSettings) {
-              await addTcpStream({
-                endpointID,
-                tcp: {
-                  header: streamSettings.tcpSettings.header?.type || 'none',
-                  requestHost:
-                    streamSettings.tcpSettings.header?.request?.headers
-                      ?.Host?.[0] || null,
-                  requestPath:
-                    streamSettings.tcpSettings.header?.request?.path?.[0] ||
-                    null,
-                },
-              });
-            }
-            break;
-          case 'hysteria2':
-            if (streamSettings.hysteria2Settings) {
-              await addHysteria2Stream({
-                endpointID,
-                hysteria2: {
-                  password: streamSettings.hysteria2Settings.password || '',
-                  type:
-                    streamSettings.hysteria2Settings.congestion?.type || 'bbr',
-                  uploadSpeed:
-                    streamSettings.hysteria2Settings.congestion?.up_mbps || 50,
-                  downloadSpeed:
-                    streamSettings.hysteria2Settings.congestion?.down_mbps ||
-                    100,
-                  enableUDP:
-                    streamSettings.hysteria2Settings.use_udp_extension || false,
-                },
-              });
-            }
-            break;
-        }
-      }
-
-      // Add security settings
-      if (security === 'tls' && streamSettings?.tlsSettings) {
-        await addTlsSecurity({
-          endpointID,
-          security: {
-            allowInsecure: streamSettings.tlsSettings.allowInsecure || false,
-            serverName: streamSettings.tlsSettings.serverName || '',
-          },
-        });
-      }
-
-      successCount++;
-    } catch (error) {
-      console.error(
-        `Failed to parse endpoint from line: ${line.substring(0, 50)}...`,
-        error,
-      );
-      failedCount++;
-    }
-  }
+  // Links are parsed and diffed into the group in one transaction
+  const result = await invoke<Types.ImportShareLinksResult>(
+    'import_share_links',
+    {
+      groupId: props.groupID,
+      data: props.subscriptionData,
+    },
+  );
+  result.errors.forEach((error) =>
+    console.warn(`Failed to parse endpoint: ${error}`),
+  );
 
-  return { success: successCount, failed: failedCount };
+  return { success: result.success, failed: result.failed };
 };
 
 export { Types };
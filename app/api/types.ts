@@ -78,13 +78,31 @@ export interface AppSettings {
   BypassDomains: string; // Default: '{"bypass":["127.0.0.1","192.168.0.0/16","10.0.0.0/8","FE80::/64","::1","FD00::/8,","localhost"]}'
   LatencyTestUrl: string;
   LatencyTestTimeout: number;
+  LatencyTestConcurrency: number; // Endpoints tested at once. Default: 16
   Language: string; // Default: 'en'
   PAC: string; // Default PAC string
+  TraySpeedInterval: number; // Seconds between tray speed updates, 0 disables. Default: 2
+  ThroughputTestUrl: string;
+  ThroughputTestDuration: number; // Seconds per endpoint. Default: 10
+  ThroughputTestMaxBytes: number; // Stops a test early. Default: 52428800
+  TrayEndpointSort: 'none' | 'latency' | 'throughput'; // Default: 'none'
+  HealthCheckInterval: number; // Seconds between checks of the active endpoint. Default: 30
+  HealthCheckFailureThreshold: number; // Consecutive failures before failing over. Default: 3
+  IpEchoServices: string; // JSON array of URLs answering with the caller's IP, tried in order
+  DnsLeakTestService: string; // bash.ws compatible leak test service. Default: 'https://bash.ws'
+  QuotaWarningPercent: number; // Notify when a subscription has less quota left. Default: 10
+  ExpiryWarningDays: number; // Notify this many days before a subscription expires. Default: 3
+  ShareServerEnabled: number; // 1 while a group is shared on the LAN. Default: 0
+  ShareServerGroupID?: string;
+  ShareServerAddress: string; // Bind address, 0.0.0.0 for every interface. Default: '127.0.0.1'
+  ShareServerPort: number; // 0 until the server first starts
+  ShareServerToken?: string;
 }
 
 // 3. AppStatus Table
 export interface AppStatus {
   ServiceRunningState: number;
+  ServiceState: 'stopped' | 'starting' | 'running' | 'degraded' | 'stopping' | 'failed'; // Default: 'stopped'
   V2rayCoreVersion: string;
   AppVersion: string;
   UserID: string; // Foreign key to AppSettings.UserID
@@ -103,6 +121,8 @@ export interface Endpoints {
   Link?: string;
   Remark: string;
   Latency?: string;
+  Throughput?: number; // Bytes per second
+  ThroughputTestedAt?: number; // Unix seconds
   SpeedTestType: string;
   GroupName: string;
   GroupID: string;
@@ -116,6 +136,8 @@ export interface EndpointsGroups {
   Remark?: string;
   Link?: string;
   SpeedTestType: string;
+  FailoverPolicy: 'off' | 'failover' | 'fastest'; // Default: 'off'
+  LatencyRefreshInterval: number; // Minutes between background latency tests, 0 disables. Default: 0
   UserID: string; // Foreign key to AppSettings.UserID
 }
 
@@ -293,7 +315,18 @@ export interface VmessVnext {
   EndpointID: string; // Foreign key to Endpoints.EndpointID
 }
 
-// 29. WsSettings Table
+// 29. VlessServers Table
+export interface VlessServers {
+  EndpointID: string; // Foreign key to Endpoints.EndpointID
+  Address: string;
+  Port: number;
+  UUID: string;
+  Encryption: string; // Default: 'none'
+  Flow?: string;
+  Level: number; // Default: 0
+}
+
+// 30. WsSettings Table
 export interface WsSettings {
   EndpointID: string; // Foreign key to Endpoints.EndpointID
   Host: string;
@@ -309,3 +342,93 @@ export interface StreamSettingsTypes {
   GrpcSettings: GrpcSettings;
   Hysteria2Settings: Hysteria2Settings;
 }
+
+// Result of the import_share_links command
+export interface ImportShareLinksResult {
+  success: number;
+  failed: number;
+  errors: string[];
+  filtered: string[]; // Entries dropped by the subscription rules
+  changes: {
+    added: number;
+    updated: number;
+    removed: number;
+    unchanged: number;
+  };
+}
+
+// Result of the share_endpoint command
+export interface SharedEndpoint {
+  link: string;
+  qrCode: string; // PNG data URL
+}
+
+// A share link found by the scan_qr_codes command
+export interface ScannedLink {
+  link: string;
+  remark: string;
+  protocol: string;
+  screenId: number;
+  screenIndex: number;
+  isPrimary: boolean;
+  region: { x: number; y: number; width: number; height: number };
+}
+
+// Subscriptions Table
+export interface Subscriptions {
+  SubscriptionID: string;
+  UserID: string;
+  Remark: string;
+  Url: string;
+  GroupID: string;
+  UpdateInterval: number; // Minutes between background updates, 0 disables. Default: 0
+  ETag?: string;
+  LastModified?: string;
+  LastCheckedAt?: number; // Unix seconds of the last update attempt
+  LastError?: string;
+  Upload?: number; // Bytes, from the subscription-userinfo header
+  Download?: number;
+  Total?: number;
+  Expire?: number; // Unix seconds
+  QuotaWarned: number; // 1 once the low quota notification was shown
+  ExpiryWarned: number; // 1 once the expiry notification was shown
+  FetchViaProxy: number; // 1 to fetch through the local socks inbound. Default: 0
+  UserAgent?: string; // Overrides the default browser user agent
+  Headers?: string; // JSON object of extra request headers
+  Timeout: number; // Seconds. Default: 30
+  AllowInsecure: number; // 1 to accept invalid certificates. Default: 0
+  IncludeRemarks?: string; // Regex, only matching remarks are imported
+  ExcludeRemarks?: string; // Regex, matching remarks are dropped
+  IncludeProtocols?: string;
+  ExcludeProtocols?: string;
+  RenameTemplate?: string; // e.g. "[{group}] {remark}"
+  Dedupe: number; // 1 to drop servers already imported elsewhere. Default: 0
+}
+
+// Result of the update_subscription command
+export interface SubscriptionUpdate {
+  subscriptionId: string;
+  notModified: boolean;
+  import?: ImportShareLinksResult;
+}
+
+// Result of the get_subscription_usage command
+export interface SubscriptionUsage {
+  subscriptionId: string;
+  remark: string;
+  upload?: number;
+  download?: number;
+  total?: number;
+  remaining?: number;
+  expire?: number;
+}
+
+// Result of the start_share_server, stop_share_server and get_share_server_status commands
+export interface ShareServerStatus {
+  running: boolean;
+  groupId?: string;
+  bindAddress: string;
+  port: number;
+  url?: string;
+  qrCode?: string; // PNG data URL of the subscription URL
+}
@@ -145,7 +145,7 @@ const AvatarButton = ({ user }: { user: Types.User }) => {
               await invoke('unset_pac_proxy');
               await updateAppStatus({
                 userID: localStorage.getItem('userID')!,
-                data: { LoginState: 0, ServiceRunningState: 0 },
+                data: { LoginState: 0 },
               });
               await updateProxyMode({
                 userID: localStorage.getItem('userID')!,
@@ -8,12 +8,11 @@ import {
   useDisclosure,
   Chip,
 } from '@heroui/react';
-import { deleteGroup, updateAppStatus } from '~/api';
+import { deleteGroup } from '~/api';
 import toast from 'react-hot-toast';
 import { useRevalidator } from 'react-router';
 import { useTranslation } from 'react-i18next';
 import { invoke } from '@tauri-apps/api/core';
-import { updateServiceRunningState } from '~/api';
 
 const DialogButton = (props: {
   groupID: string;
@@ -80,11 +79,6 @@ const DialogButton = (props: {
                   color="danger"
                   onPress={async () => {
                     const userID = localStorage.getItem('userID')!;
-                    // keep the execute order to update the tray
-                    await updateAppStatus({
-                      userID: userID,
-                      data: { ServiceRunningState: 0 },
-                    });
                     await deleteGroup({ groupID: props.groupID });
                     toast.success(
                       `${props.groupName} ${t('Group deleted successfully')}`,
@@ -94,6 +88,8 @@ const DialogButton = (props: {
                         userId: userID,
                       });
                       toast.success(t('V2ray-core proxy service has stopped'));
+                    } else {
+                      await invoke('tray_update', { userId: userID });
                     }
                     revalidator.revalidate();
                     onClose();
@@ -17,7 +17,6 @@ import type { PageRef } from './components';
 import {
   queryEndpoint,
   queryEndpoints,
-  updateServiceRunningState,
 } from '~/api';
 import { invoke } from '@tauri-apps/api/core';
 import toast from 'react-hot-toast';
@@ -53,10 +52,6 @@ const Dialog = NiceModal.create(
         ).filter((i) => i.Active === 1).length > 0;
       if (isSelected) {
         await invoke('stop_daemon');
-        await updateServiceRunningState({
-          userID: localStorage.getItem('userID')!,
-          serviceRunningState: false,
-        });
       }
 
       const injectConfig = await invoke('inject_config', {
@@ -6,7 +6,6 @@ import { deleteEndpoint } from '~/api';
 import { invoke } from '@tauri-apps/api/core';
 import { useTranslation } from 'react-i18next';
 import toast from 'react-hot-toast';
-import { updateServiceRunningState } from '~/api';
 
 export function MoreButton({
   endpointID,
@@ -3,7 +3,6 @@ import * as Share from './share-popover';
 import * as More from './more-popover';
 import { motion } from 'motion/react';
 import * as Types from '~/api/types';
-import { updateAppStatus } from '~/api';
 import { invoke } from '@tauri-apps/api/core';
 import { useTranslation } from 'react-i18next';
 
@@ -90,10 +89,6 @@ export const Page = (props: {
                 state ? 'stop_daemon' : 'start_daemon',
               );
               if (success) {
-                await updateAppStatus({
-                  userID: localStorage.getItem('userID')!,
-                  data: { ServiceRunningState: state ? 0 : 1 },
-                });
                 await invoke('tray_update', {
                   userId: localStorage.getItem('userID')!,
                 });
@@ -27,14 +27,12 @@ import toast from 'react-hot-toast';
 import { useRevalidator } from 'react-router';
 import { useTranslation } from 'react-i18next';
 import { listen } from '@tauri-apps/api/event';
-import { invoke } from '@tauri-apps/api/core';
 import { SVGProps } from 'react';
 import {
   querySubscriptions,
   addSubscription,
   deleteSubscription,
-  updateAllSubscriptions,
-  parseSubscriptionAndCreateEndpoints,
+  refreshSubscription,
   checkDuplicateSubscription,
 } from '~/api';
 import { v7 as uuid } from 'uuid';
@@ -318,36 +316,22 @@ const SubscriptionsDialog = forwardRef<
             { id: loadingToast },
           );
 
-          // Fetch and decode subscription data
-          const subscriptionData = await invoke<string>(
-            'fetch_subscription_data',
-            {
-              url: subscription.Url,
-            },
-          );
-
-          // Log the decoded data for debugging
-          console.log(
-            `Decoded subscription data for ${subscription.Remark}:`,
-            subscriptionData,
-          );
-
-          // Parse the subscription data and create endpoints
-          const result = await parseSubscriptionAndCreateEndpoints({
-            userID,
+          // Fetched conditionally and diffed into the group, so unchanged
+          // endpoints keep their IDs
+          const result = await refreshSubscription({
             subscriptionID: subscription.SubscriptionID,
-            groupID: subscription.GroupID,
-            remark: subscription.Remark,
-            link: subscription.Url,
-            subscriptionData,
           });
 
-          console.log(
-            `Parsed ${result.success} endpoints, ${result.failed} failed for ${subscription.Remark}`,
-          );
+          if (result.notModified || !result.import) {
+            console.log(`Subscription ${subscription.Remark} not modified`);
+          } else {
+            const { changes } = result.import;
+            console.log(
+              `Updated ${subscription.Remark}: ${changes.added} added, ${changes.updated} updated, ${changes.removed} removed, ${result.import.failed} failed`,
+            );
+          }
 
-          successCount += result.success;
-          errorCount += result.failed;
+          successCount++;
         } catch (error) {
           errorCount++;
           console.error(
@@ -357,9 +341,6 @@ const SubscriptionsDialog = forwardRef<
         }
       }
 
-      // Call the API to update all subscriptions (this currently just updates timestamps)
-      await updateAllSubscriptions({ userID });
-
       // Dismiss the loading toast and show summary
       toast.dismiss(loadingToast);
 
@@ -27,7 +27,6 @@ import {
   updateInbounds,
   queryEndpoints,
   queryAppStatus,
-  updateServiceRunningState,
 } from '~/api';
 
 export const loader = async () => {
@@ -122,12 +121,6 @@ const InboudSettigTabContent = (props: {
             body: t('V2ray service stopped due to port change'),
           });
 
-          // Update database status
-          await updateServiceRunningState({
-            userID,
-            serviceRunningState: false,
-          });
-
           // Refresh system tray menu
           await invoke('tray_update', {
             userId: userID,
@@ -0,0 +1,2 @@
+ALTER TABLE AppSettings
+    ADD DnsLeakTestService TEXT NOT NULL DEFAULT 'https://bash.ws';
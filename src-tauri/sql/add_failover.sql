@@ -0,0 +1,16 @@
+ALTER TABLE EndpointsGroups
+    ADD FailoverPolicy TEXT NOT NULL DEFAULT 'off';
+ALTER TABLE AppSettings
+    ADD HealthCheckInterval INTEGER NOT NULL DEFAULT 30;
+ALTER TABLE AppSettings
+    ADD HealthCheckFailureThreshold INTEGER NOT NULL DEFAULT 3;
+
+CREATE TABLE EndpointSwitches (
+    SwitchID       INTEGER PRIMARY KEY AUTOINCREMENT,
+    UserID         TEXT    NOT NULL,
+    GroupID        TEXT    NOT NULL,
+    FromEndpointID TEXT,
+    ToEndpointID   TEXT    NOT NULL,
+    Reason         TEXT    NOT NULL,
+    SwitchedAt     INTEGER NOT NULL
+);
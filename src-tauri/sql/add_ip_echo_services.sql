@@ -0,0 +1,2 @@
+ALTER TABLE AppSettings
+    ADD IpEchoServices TEXT NOT NULL DEFAULT '["https://api64.ipify.org","https://icanhazip.com","https://ifconfig.me/ip"]';
@@ -0,0 +1,2 @@
+ALTER TABLE EndpointsGroups
+    ADD LatencyRefreshInterval INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+ALTER TABLE AppSettings
+    ADD LatencyTestConcurrency INTEGER NOT NULL DEFAULT 16;
@@ -0,0 +1,2 @@
+ALTER TABLE AppStatus
+    ADD ServiceState TEXT NOT NULL DEFAULT 'stopped';
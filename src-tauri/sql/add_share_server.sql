@@ -0,0 +1,10 @@
+ALTER TABLE AppSettings
+    ADD ShareServerEnabled INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE AppSettings
+    ADD ShareServerGroupID TEXT;
+ALTER TABLE AppSettings
+    ADD ShareServerAddress TEXT NOT NULL DEFAULT '127.0.0.1';
+ALTER TABLE AppSettings
+    ADD ShareServerPort INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE AppSettings
+    ADD ShareServerToken TEXT;
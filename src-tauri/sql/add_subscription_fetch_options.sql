@@ -0,0 +1,10 @@
+ALTER TABLE Subscriptions
+    ADD FetchViaProxy INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE Subscriptions
+    ADD UserAgent TEXT;
+ALTER TABLE Subscriptions
+    ADD Headers TEXT;
+ALTER TABLE Subscriptions
+    ADD Timeout INTEGER NOT NULL DEFAULT 30;
+ALTER TABLE Subscriptions
+    ADD AllowInsecure INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,12 @@
+ALTER TABLE Subscriptions
+    ADD IncludeRemarks TEXT;
+ALTER TABLE Subscriptions
+    ADD ExcludeRemarks TEXT;
+ALTER TABLE Subscriptions
+    ADD IncludeProtocols TEXT;
+ALTER TABLE Subscriptions
+    ADD ExcludeProtocols TEXT;
+ALTER TABLE Subscriptions
+    ADD RenameTemplate TEXT;
+ALTER TABLE Subscriptions
+    ADD Dedupe INTEGER NOT NULL DEFAULT 0;
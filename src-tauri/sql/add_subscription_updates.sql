@@ -0,0 +1,10 @@
+ALTER TABLE Subscriptions
+    ADD UpdateInterval INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE Subscriptions
+    ADD ETag TEXT;
+ALTER TABLE Subscriptions
+    ADD LastModified TEXT;
+ALTER TABLE Subscriptions
+    ADD LastCheckedAt INTEGER;
+ALTER TABLE Subscriptions
+    ADD LastError TEXT;
@@ -0,0 +1,17 @@
+ALTER TABLE Subscriptions
+    ADD Upload INTEGER;
+ALTER TABLE Subscriptions
+    ADD Download INTEGER;
+ALTER TABLE Subscriptions
+    ADD Total INTEGER;
+ALTER TABLE Subscriptions
+    ADD Expire INTEGER;
+ALTER TABLE Subscriptions
+    ADD QuotaWarned INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE Subscriptions
+    ADD ExpiryWarned INTEGER NOT NULL DEFAULT 0;
+
+ALTER TABLE AppSettings
+    ADD QuotaWarningPercent INTEGER NOT NULL DEFAULT 10;
+ALTER TABLE AppSettings
+    ADD ExpiryWarningDays INTEGER NOT NULL DEFAULT 3;
@@ -0,0 +1,12 @@
+ALTER TABLE Endpoints
+    ADD Throughput INTEGER;
+ALTER TABLE Endpoints
+    ADD ThroughputTestedAt INTEGER;
+ALTER TABLE AppSettings
+    ADD ThroughputTestUrl TEXT NOT NULL DEFAULT 'https://speed.cloudflare.com/__down?bytes=104857600';
+ALTER TABLE AppSettings
+    ADD ThroughputTestDuration INTEGER NOT NULL DEFAULT 10;
+ALTER TABLE AppSettings
+    ADD ThroughputTestMaxBytes INTEGER NOT NULL DEFAULT 52428800;
+ALTER TABLE AppSettings
+    ADD TrayEndpointSort TEXT NOT NULL DEFAULT 'none';
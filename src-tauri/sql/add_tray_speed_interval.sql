@@ -0,0 +1,2 @@
+ALTER TABLE AppSettings
+    ADD TraySpeedInterval INTEGER NOT NULL DEFAULT 2;
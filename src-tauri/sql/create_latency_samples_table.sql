@@ -0,0 +1,10 @@
+CREATE TABLE LatencySamples (
+    SampleID   INTEGER PRIMARY KEY AUTOINCREMENT,
+    EndpointID TEXT    NOT NULL,
+    TestedAt   INTEGER NOT NULL,
+    Method     TEXT    NOT NULL,
+    Latency    INTEGER,
+    ErrorKind  TEXT
+);
+
+CREATE INDEX LatencySamples_endpointid_testedat ON LatencySamples (EndpointID, TestedAt);
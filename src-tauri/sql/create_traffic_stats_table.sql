@@ -0,0 +1,10 @@
+CREATE TABLE TrafficStats (
+    UserID     TEXT    NOT NULL,
+    Day        TEXT    NOT NULL,
+    Kind       TEXT    NOT NULL,
+    Tag        TEXT    NOT NULL,
+    EndpointID TEXT    NOT NULL DEFAULT '',
+    Uplink     INTEGER NOT NULL DEFAULT 0,
+    Downlink   INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (UserID, Day, Kind, Tag, EndpointID)
+);
@@ -0,0 +1,9 @@
+CREATE TABLE VlessServers (
+    EndpointID TEXT    NOT NULL PRIMARY KEY,
+    Address    TEXT    NOT NULL,
+    Port       INTEGER NOT NULL,
+    UUID       TEXT    NOT NULL,
+    Encryption TEXT    NOT NULL DEFAULT 'none',
+    Flow       TEXT,
+    Level      INTEGER NOT NULL DEFAULT 0
+);
@@ -0,0 +1,529 @@
+//! Connection log built from the core's access log.
+//!
+//! The core writes one line per connection to `Log.AccessPath` (or to stdout
+//! when no path is set). Lines are tailed while the daemon runs, parsed into
+//! [`AccessRecord`]s and kept in a bounded in-memory buffer that the frontend
+//! can query and aggregate per domain. The file is read on the blocking pool
+//! so a slow disk never stalls the async runtime.
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::Row;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, Manager, State};
+use tokio::sync::oneshot;
+
+use crate::utils;
+
+const MAX_RECORDS: usize = 5000;
+const TAIL_INTERVAL: Duration = Duration::from_millis(500);
+// How much of an existing log is read back when tailing starts
+const BACKFILL_BYTES: u64 = 256 * 1024;
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessRecord {
+    pub time: String,
+    pub source: String,
+    pub network: String,
+    pub host: String,
+    pub port: u16,
+    pub inbound_tag: Option<String>,
+    pub outbound_tag: Option<String>,
+    pub accepted: bool,
+    pub reason: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessLogFilter {
+    /// Case-insensitive substring of the destination host
+    pub host: Option<String>,
+    pub inbound_tag: Option<String>,
+    pub outbound_tag: Option<String>,
+    pub accepted: Option<bool>,
+    /// Only records at or after this time, in the core's `YYYY/MM/DD HH:MM:SS` format
+    pub since: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DomainSummary {
+    pub host: String,
+    pub connections: usize,
+    pub rejected: usize,
+    pub outbounds: HashMap<String, usize>,
+    pub last_seen: String,
+}
+
+pub struct AccessLogState {
+    records: Mutex<VecDeque<AccessRecord>>,
+    shutdown_tx: Mutex<Option<oneshot::Sender<()>>>,
+}
+
+impl AccessLogState {
+    pub fn new() -> Self {
+        AccessLogState {
+            records: Mutex::new(VecDeque::with_capacity(MAX_RECORDS)),
+            shutdown_tx: Mutex::new(None),
+        }
+    }
+
+    fn push(&self, record: AccessRecord) {
+        let mut records = self.records.lock().unwrap();
+        if records.len() == MAX_RECORDS {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+}
+
+/// Splits `host:port`, keeping IPv6 literals such as `[::1]:443` intact.
+fn split_host_port(address: &str) -> Option<(String, u16)> {
+    let (host, port) = address.rsplit_once(':')?;
+    let port = port.parse().ok()?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return None;
+    }
+    Some((host.to_string(), port))
+}
+
+/// Parses one access log line. The layout differs slightly between core
+/// versions, so everything after the destination is treated as optional:
+///
+/// `2024/01/02 15:04:05 127.0.0.1:51234 accepted tcp:example.com:443 [http-inbound -> proxy]`
+/// `2024/01/02 15:04:05 127.0.0.1:51234 rejected  tcp:example.com:443 some reason`
+pub fn parse_line(line: &str) -> Option<AccessRecord> {
+    let mut tokens = line.split_whitespace();
+    let date = tokens.next()?;
+    let clock = tokens.next()?;
+    if !date.contains('/') || !clock.contains(':') {
+        return None;
+    }
+    // Drop fractional seconds so records compare cleanly against `since`
+    let clock = clock.split('.').next().unwrap_or(clock);
+
+    let source = tokens.next()?;
+    let accepted = match tokens.next()? {
+        "accepted" => true,
+        "rejected" => false,
+        _ => return None,
+    };
+
+    let destination = tokens.next()?;
+    let (network, address) = match destination.split_once(':') {
+        Some((network @ ("tcp" | "udp"), rest)) => (network, rest),
+        _ => ("tcp", destination),
+    };
+    let (host, port) = split_host_port(address)?;
+
+    let rest = tokens.collect::<Vec<_>>().join(" ");
+    let (detour, reason) = match rest.strip_prefix('[') {
+        Some(bracketed) => match bracketed.split_once(']') {
+            Some((detour, reason)) => (Some(detour.trim().to_string()), reason.trim().to_string()),
+            None => (None, rest.clone()),
+        },
+        None => (None, rest.clone()),
+    };
+    // `email: user@example.com` is appended for authenticated inbounds
+    let reason = reason
+        .split("email:")
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_string();
+
+    let (inbound_tag, outbound_tag) = match detour {
+        Some(detour) => {
+            let separator = [" --> ", " -> ", " >> "]
+                .into_iter()
+                .find(|separator| detour.contains(separator));
+            match separator.and_then(|separator| detour.split_once(separator)) {
+                Some((inbound, outbound)) => (
+                    Some(
+                        inbound
+                            .trim_matches(|c| c == '[' || c == ']' || c == ' ')
+                            .to_string(),
+                    ),
+                    Some(
+                        outbound
+                            .trim_matches(|c| c == '[' || c == ']' || c == ' ')
+                            .to_string(),
+                    ),
+                ),
+                None => (None, Some(detour)),
+            }
+        }
+        None => (None, None),
+    };
+
+    Some(AccessRecord {
+        time: format!("{} {}", date, clock),
+        source: source
+            .trim_start_matches("tcp:")
+            .trim_start_matches("udp:")
+            .to_string(),
+        network: network.to_string(),
+        host,
+        port,
+        inbound_tag,
+        outbound_tag,
+        accepted,
+        reason: (!reason.is_empty()).then_some(reason),
+    })
+}
+
+/// Reads what was appended to `path` since `position`. Returns the offset
+/// the bytes start at, which is 0 when the file was truncated or rotated,
+/// and `None` while the file does not exist.
+fn read_appended(path: &str, position: u64) -> std::io::Result<Option<(u64, Vec<u8>)>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        // The core creates the file on its first connection
+        Err(_) => return Ok(None),
+    };
+    let length = file.metadata()?.len();
+    let start = if length < position { 0 } else { position };
+    let mut chunk = Vec::new();
+    if length > start {
+        file.seek(SeekFrom::Start(start))?;
+        file.read_to_end(&mut chunk)?;
+    }
+    Ok(Some((start, chunk)))
+}
+
+/// Feeds a line from the core's stdout, used when no access log path is set.
+pub fn ingest_line(app: &AppHandle, line: &str) {
+    if let Some(record) = parse_line(line) {
+        app.state::<AccessLogState>().push(record);
+    }
+}
+
+async fn access_log_path(app: &AppHandle) -> Result<Option<String>, String> {
+    let database_path = utils::get_database_path(app).to_string_lossy().to_string();
+    let database_url = format!("sqlite://{}", database_path);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect(&database_url)
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+
+    let row = sqlx::query(
+        "SELECT l.AccessPath FROM Log l JOIN AppStatus s ON s.UserID = l.UserID WHERE s.LoginState = 1 LIMIT 1",
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| format!("Failed to fetch access log path: {}", e))?;
+
+    Ok(row
+        .and_then(|row| row.get::<Option<String>, _>("AccessPath"))
+        .filter(|path| !path.is_empty() && path != "none"))
+}
+
+/// Starts tailing the configured access log, replacing any running tail.
+pub fn start_tail(app: &AppHandle) {
+    let (shutdown_tx, mut shutdown_rx) = oneshot::channel::<()>();
+    {
+        let state = app.state::<AccessLogState>();
+        let previous = state.shutdown_tx.lock().unwrap().replace(shutdown_tx);
+        if let Some(tx) = previous {
+            let _ = tx.send(());
+        }
+    }
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let path = match access_log_path(&app).await {
+            Ok(Some(path)) => path,
+            Ok(None) => {
+                info!("No access log path configured, reading the core's stdout instead");
+                return;
+            }
+            Err(e) => {
+                error!("Failed to start access log tail: {}", e);
+                return;
+            }
+        };
+
+        let metadata_path = path.clone();
+        let mut position = tokio::task::spawn_blocking(move || std::fs::metadata(metadata_path))
+            .await
+            .ok()
+            .and_then(|metadata| metadata.ok())
+            .map(|metadata| metadata.len().saturating_sub(BACKFILL_BYTES))
+            .unwrap_or(0);
+        // Skip the partial line a backfill offset usually lands in
+        let mut skip_first_line = position > 0;
+        let mut pending = String::new();
+        let mut ticker = tokio::time::interval(TAIL_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = &mut shutdown_rx => break,
+                _ = ticker.tick() => {}
+            }
+
+            let read_path = path.clone();
+            let read =
+                tokio::task::spawn_blocking(move || read_appended(&read_path, position)).await;
+            let (start, chunk) = match read {
+                Ok(Ok(Some(read))) => read,
+                Ok(Ok(None)) => continue,
+                Ok(Err(e)) => {
+                    warn!("Failed to read access log {}: {}", path, e);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Access log read task failed: {}", e);
+                    continue;
+                }
+            };
+            if start < position {
+                // Truncated or rotated
+                pending.clear();
+            }
+            position = start + chunk.len() as u64;
+            if chunk.is_empty() {
+                continue;
+            }
+            pending.push_str(&String::from_utf8_lossy(&chunk));
+
+            let state = app.state::<AccessLogState>();
+            while let Some(newline) = pending.find('\n') {
+                let line: String = pending.drain(..=newline).collect();
+                if skip_first_line {
+                    skip_first_line = false;
+                    continue;
+                }
+                if let Some(record) = parse_line(line.trim_end()) {
+                    state.push(record);
+                }
+            }
+        }
+        info!("Access log tail stopped");
+    });
+}
+
+pub fn stop_tail(app: &AppHandle) {
+    let state = app.state::<AccessLogState>();
+    let shutdown_tx = state.shutdown_tx.lock().unwrap().take();
+    if let Some(tx) = shutdown_tx {
+        let _ = tx.send(());
+    }
+}
+
+fn matches(record: &AccessRecord, filter: &AccessLogFilter) -> bool {
+    if let Some(host) = &filter.host {
+        if !record.host.to_lowercase().contains(&host.to_lowercase()) {
+            return false;
+        }
+    }
+    if filter.inbound_tag.is_some() && record.inbound_tag != filter.inbound_tag {
+        return false;
+    }
+    if filter.outbound_tag.is_some() && record.outbound_tag != filter.outbound_tag {
+        return false;
+    }
+    if filter
+        .accepted
+        .is_some_and(|accepted| accepted != record.accepted)
+    {
+        return false;
+    }
+    if let Some(since) = &filter.since {
+        if record.time.as_str() < since.as_str() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Returns the buffered connections matching `filter`, newest first.
+#[tauri::command]
+pub fn query_access_log(
+    state: State<'_, AccessLogState>,
+    filter: Option<AccessLogFilter>,
+) -> Vec<AccessRecord> {
+    let filter = filter.unwrap_or_default();
+    let records = state.records.lock().unwrap();
+    records
+        .iter()
+        .rev()
+        .filter(|record| matches(record, &filter))
+        .take(filter.limit.unwrap_or(MAX_RECORDS))
+        .cloned()
+        .collect()
+}
+
+/// Groups the buffered connections matching `filter` by destination host,
+/// counting how often each outbound handled it. Busiest hosts come first.
+#[tauri::command]
+pub fn get_access_log_domains(
+    state: State<'_, AccessLogState>,
+    filter: Option<AccessLogFilter>,
+) -> Vec<DomainSummary> {
+    let filter = filter.unwrap_or_default();
+    let records = state.records.lock().unwrap();
+
+    let mut domains: HashMap<&str, DomainSummary> = HashMap::new();
+    for record in records.iter().filter(|record| matches(record, &filter)) {
+        let summary = domains
+            .entry(&record.host)
+            .or_insert_with(|| DomainSummary {
+                host: record.host.clone(),
+                connections: 0,
+                rejected: 0,
+                outbounds: HashMap::new(),
+                last_seen: String::new(),
+            });
+        summary.connections += 1;
+        if !record.accepted {
+            summary.rejected += 1;
+        }
+        if let Some(outbound) = &record.outbound_tag {
+            *summary.outbounds.entry(outbound.clone()).or_default() += 1;
+        }
+        // Records are appended in order, so the latest one wins
+        summary.last_seen = record.time.clone();
+    }
+
+    let mut summaries: Vec<DomainSummary> = domains.into_values().collect();
+    summaries.sort_by(|a, b| b.connections.cmp(&a.connections).then(a.host.cmp(&b.host)));
+    if let Some(limit) = filter.limit {
+        summaries.truncate(limit);
+    }
+    summaries
+}
+
+#[tauri::command]
+pub fn clear_access_log(state: State<'_, AccessLogState>) {
+    state.records.lock().unwrap().clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> AccessRecord {
+        parse_line(line).unwrap_or_else(|| panic!("not parsed: {}", line))
+    }
+
+    #[test]
+    fn parses_accepted_connections() {
+        let record = parse(
+            "2024/01/02 15:04:05 127.0.0.1:51234 accepted tcp:www.google.com:443 [socks-inbound -> proxy]",
+        );
+        assert_eq!(record.time, "2024/01/02 15:04:05");
+        assert_eq!(record.source, "127.0.0.1:51234");
+        assert_eq!(record.network, "tcp");
+        assert_eq!(record.host, "www.google.com");
+        assert_eq!(record.port, 443);
+        assert_eq!(record.inbound_tag.as_deref(), Some("socks-inbound"));
+        assert_eq!(record.outbound_tag.as_deref(), Some("proxy"));
+        assert!(record.accepted);
+        assert_eq!(record.reason, None);
+    }
+
+    #[test]
+    fn parses_the_detour_layouts_of_each_core_version() {
+        // v2fly 4.x only names the outbound
+        let record =
+            parse("2022/03/10 10:00:00 127.0.0.1:61234 accepted tcp:github.com:443 [direct]");
+        assert_eq!(record.inbound_tag, None);
+        assert_eq!(record.outbound_tag.as_deref(), Some("direct"));
+
+        let record = parse(
+            "2023/10/05 10:21:33 127.0.0.1:56234 accepted tcp:api.github.com:443 [http-inbound >> proxy]",
+        );
+        assert_eq!(record.inbound_tag.as_deref(), Some("http-inbound"));
+        assert_eq!(record.outbound_tag.as_deref(), Some("proxy"));
+
+        let record = parse(
+            "2023/10/05 10:21:33 tcp:127.0.0.1:56235 accepted tcp:example.com:80 [socks-inbound --> block]",
+        );
+        assert_eq!(record.source, "127.0.0.1:56235");
+        assert_eq!(record.inbound_tag.as_deref(), Some("socks-inbound"));
+        assert_eq!(record.outbound_tag.as_deref(), Some("block"));
+    }
+
+    #[test]
+    fn parses_udp_and_ipv6_destinations() {
+        let record = parse("2024/01/02 15:04:05 127.0.0.1:50000 accepted udp:8.8.8.8:53 [dns-out]");
+        assert_eq!(record.network, "udp");
+        assert_eq!(record.host, "8.8.8.8");
+        assert_eq!(record.port, 53);
+
+        let record = parse(
+            "2024/01/02 15:04:05 [::1]:50001 accepted tcp:[2001:db8::1]:8443 [socks-inbound -> proxy]",
+        );
+        assert_eq!(record.source, "[::1]:50001");
+        assert_eq!(record.host, "2001:db8::1");
+        assert_eq!(record.port, 8443);
+    }
+
+    #[test]
+    fn drops_fractional_seconds_and_emails() {
+        let record = parse(
+            "2024/01/02 15:04:05.123456 127.0.0.1:51234 accepted tcp:example.com:443 [vmess-in -> direct] email:user@example.com",
+        );
+        assert_eq!(record.time, "2024/01/02 15:04:05");
+        assert_eq!(record.outbound_tag.as_deref(), Some("direct"));
+        assert_eq!(record.reason, None);
+    }
+
+    #[test]
+    fn keeps_the_reason_of_rejected_connections() {
+        let record = parse(
+            "2024/01/02 15:04:05 127.0.0.1:51234 rejected  tcp:ads.example.com:443 [socks-inbound -> block] blocked by routing rule",
+        );
+        assert!(!record.accepted);
+        assert_eq!(record.host, "ads.example.com");
+        assert_eq!(record.outbound_tag.as_deref(), Some("block"));
+        assert_eq!(record.reason.as_deref(), Some("blocked by routing rule"));
+    }
+
+    #[test]
+    fn ignores_other_lines() {
+        for line in [
+            "",
+            "V2Ray 5.16.1 (V2Fly, a community-driven edition of V2Ray.) Custom (go1.22.4 darwin/arm64)",
+            "2024/01/02 15:04:05 [Warning] core: V2Ray 5.16.1 started",
+            // A failed handshake has no destination
+            "2024/01/02 15:04:05 tcp:127.0.0.1:51234 rejected  proxy/socks: unknown Socks version: 67",
+            "2024/01/02 15:04:05 127.0.0.1:51234 accepted tcp:example.com [proxy]",
+            "2024/01/02",
+        ] {
+            assert!(parse_line(line).is_none(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn reads_appended_bytes_and_starts_over_after_truncation() {
+        let path = std::env::temp_dir().join(format!("access-log-{}.log", uuid::Uuid::new_v4()));
+        let path_str = path.to_string_lossy().to_string();
+        assert_eq!(read_appended(&path_str, 0).unwrap(), None);
+
+        std::fs::write(&path, "first\nsecond\n").unwrap();
+        assert_eq!(
+            read_appended(&path_str, 6).unwrap(),
+            Some((6, b"second\n".to_vec()))
+        );
+        assert_eq!(
+            read_appended(&path_str, 13).unwrap(),
+            Some((13, Vec::new()))
+        );
+
+        std::fs::write(&path, "new\n").unwrap();
+        assert_eq!(
+            read_appended(&path_str, 13).unwrap(),
+            Some((0, b"new\n".to_vec()))
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,398 @@
+//! Clash and Mihomo YAML profiles.
+//!
+//! Providers that only offer Clash profiles list their servers under
+//! `proxies`. Entries of the types we support (vmess, vless, ss, trojan and
+//! hysteria2) are mapped onto the same [`ShareLink`]s share links parse into,
+//! so they are imported, diffed and shared like any other endpoint. Other
+//! types, and options the bundled v2ray-core cannot express such as REALITY,
+//! shadowsocks plugins or Hysteria2 obfuscation, are skipped and reported.
+//!
+//! `rules` are not imported: the generated config has no user routing rules
+//! they could be merged into.
+
+use serde_yaml::Value;
+
+use crate::share_link::{self, Outbound, Profile, ShareLink, Tls, Transport};
+
+/// A scalar as text; ports and passwords are often written as numbers.
+fn text(value: Option<&Value>) -> Option<String> {
+    match value? {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(flag) => Some(flag.to_string()),
+        _ => None,
+    }
+    .filter(|text| !text.is_empty())
+}
+
+fn flag(value: Option<&Value>) -> bool {
+    match value {
+        Some(Value::Bool(flag)) => *flag,
+        Some(Value::String(text)) => matches!(text.as_str(), "true" | "1"),
+        Some(Value::Number(number)) => number.as_i64() == Some(1),
+        _ => false,
+    }
+}
+
+/// The first entry of a list, or the value itself.
+fn first(value: Option<&Value>) -> Option<&Value> {
+    match value? {
+        Value::Sequence(values) => values.first(),
+        value => Some(value),
+    }
+}
+
+/// Bandwidth in Mbps, written either as a number or as `"100 Mbps"`.
+fn mbps(value: Option<&Value>) -> Option<i64> {
+    let text = text(value)?;
+    let digits: String = text
+        .trim()
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().ok()
+}
+
+fn transport(proxy: &Value) -> Result<Transport, String> {
+    let options = |key: &str| proxy.get(key);
+    let network = text(proxy.get("network"));
+    let transport = match network.as_deref().unwrap_or("tcp") {
+        "tcp" => Transport::Tcp {
+            header_type: "none".to_string(),
+            host: None,
+            path: None,
+        },
+        "ws" => {
+            let ws = options("ws-opts");
+            Transport::Ws {
+                host: text(
+                    ws.and_then(|ws| ws.get("headers"))
+                        .and_then(|headers| headers.get("Host").or(headers.get("host"))),
+                )
+                .unwrap_or_default(),
+                path: text(ws.and_then(|ws| ws.get("path")).or(proxy.get("ws-path")))
+                    .unwrap_or_else(|| "/".to_string()),
+            }
+        }
+        "grpc" => Transport::Grpc {
+            service_name: text(options("grpc-opts").and_then(|grpc| grpc.get("grpc-service-name")))
+                .unwrap_or_default(),
+        },
+        "h2" => {
+            let h2 = options("h2-opts");
+            Transport::Http {
+                host: text(first(h2.and_then(|h2| h2.get("host"))))
+                    .or(text(proxy.get("server")))
+                    .unwrap_or_default(),
+                path: text(h2.and_then(|h2| h2.get("path"))).unwrap_or_else(|| "/".to_string()),
+            }
+        }
+        // Plain HTTP camouflage over TCP
+        "http" => {
+            let http = options("http-opts");
+            Transport::Tcp {
+                header_type: "http".to_string(),
+                host: text(first(
+                    http.and_then(|http| http.get("headers"))
+                        .and_then(|headers| headers.get("Host").or(headers.get("host"))),
+                )),
+                path: text(first(http.and_then(|http| http.get("path")))),
+            }
+        }
+        other => return Err(format!("Unsupported network: {}", other)),
+    };
+    Ok(transport)
+}
+
+/// TLS settings when `enabled`. vmess and vless name the SNI `servername`,
+/// the others `sni`.
+fn tls(proxy: &Value, enabled: bool) -> Option<Tls> {
+    enabled.then(|| Tls {
+        server_name: text(proxy.get("servername").or(proxy.get("sni"))).unwrap_or_default(),
+        allow_insecure: flag(proxy.get("skip-cert-verify")),
+        fingerprint: text(proxy.get("client-fingerprint")),
+    })
+}
+
+fn parse_proxy(proxy: &Value) -> Result<ShareLink, String> {
+    let kind = text(proxy.get("type")).ok_or("Missing type")?;
+    let address = text(proxy.get("server")).ok_or("Missing server")?;
+    let port = text(proxy.get("port"))
+        .and_then(|port| port.parse::<u16>().ok())
+        .filter(|port| *port != 0)
+        .ok_or("Invalid port")?;
+    if proxy.get("reality-opts").is_some() {
+        // reality needs Xray, the bundled core cannot connect
+        return Err("REALITY is not supported".to_string());
+    }
+    let required = |key: &str| text(proxy.get(key)).ok_or(format!("Missing {}", key));
+    let tls_enabled = flag(proxy.get("tls"));
+
+    let (outbound, transport, tls) = match kind.as_str() {
+        "vmess" => (
+            Outbound::Vmess {
+                address,
+                port,
+                uuid: required("uuid")?,
+                alter_id: text(proxy.get("alterId"))
+                    .and_then(|alter_id| alter_id.parse().ok())
+                    .unwrap_or(0),
+                security: text(proxy.get("cipher")).unwrap_or_else(|| "auto".to_string()),
+            },
+            transport(proxy)?,
+            tls(proxy, tls_enabled),
+        ),
+        "vless" => (
+            Outbound::Vless {
+                address,
+                port,
+                uuid: required("uuid")?,
+                encryption: text(proxy.get("encryption")).unwrap_or_else(|| "none".to_string()),
+                flow: text(proxy.get("flow")),
+            },
+            transport(proxy)?,
+            tls(proxy, tls_enabled),
+        ),
+        "ss" => {
+            if text(proxy.get("plugin")).is_some() {
+                return Err("Shadowsocks plugins are not supported".to_string());
+            }
+            (
+                Outbound::Shadowsocks {
+                    address,
+                    port,
+                    method: required("cipher")?.to_ascii_lowercase(),
+                    password: required("password")?,
+                },
+                Transport::Tcp {
+                    header_type: "none".to_string(),
+                    host: None,
+                    path: None,
+                },
+                None,
+            )
+        }
+        // trojan always runs over TLS
+        "trojan" => (
+            Outbound::Trojan {
+                address,
+                port,
+                password: required("password")?,
+            },
+            transport(proxy)?,
+            tls(proxy, true),
+        ),
+        "hysteria2" => {
+            if text(proxy.get("obfs")).is_some() {
+                return Err("Hysteria2 obfuscation is not supported".to_string());
+            }
+            (
+                Outbound::Hysteria2 {
+                    address,
+                    port,
+                    password: text(proxy.get("password").or(proxy.get("auth")))
+                        .ok_or("Missing password")?,
+                    upload_mbps: mbps(proxy.get("up")).unwrap_or(50),
+                    download_mbps: mbps(proxy.get("down")).unwrap_or(100),
+                },
+                Transport::Hysteria2,
+                tls(proxy, true),
+            )
+        }
+        other => return Err(format!("Unsupported type: {}", other)),
+    };
+
+    let mut share_link = ShareLink {
+        link: String::new(),
+        remark: share_link::remark_or_address(
+            text(proxy.get("name")),
+            outbound.address(),
+            outbound.port(),
+        ),
+        outbound,
+        transport,
+        tls,
+    };
+    // Stored like an imported link, so it can be shared and compared later
+    share_link.link = share_link.to_url();
+    Ok(share_link)
+}
+
+/// Parses `text` as a Clash profile. `None` when it is not YAML with a
+/// `proxies` list, so the other subscription formats can be tried.
+pub fn parse(text: &str) -> Option<Profile> {
+    let profile: Value = serde_yaml::from_str(text).ok()?;
+    let proxies = profile.get("proxies")?.as_sequence()?;
+
+    let mut share_links = Vec::new();
+    let mut skipped = Vec::new();
+    for proxy in proxies {
+        match parse_proxy(proxy) {
+            Ok(share_link) => share_links.push(share_link),
+            Err(e) => {
+                let name = self::text(proxy.get("name")).unwrap_or_else(|| "unnamed".to_string());
+                skipped.push(format!("{}: {}", name, e));
+            }
+        }
+    }
+    Some(Profile {
+        share_links,
+        skipped,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+    const FIXTURE: &str = include_str!("../tests/fixtures/clash.yaml");
+    const UUID: &str = "0f9a1e1c-3c4d-4b5e-8f6a-7b8c9d0e1f2a";
+
+    fn profile() -> Profile {
+        parse(FIXTURE).expect("Clash profile")
+    }
+
+    #[test]
+    fn maps_the_proxies() {
+        let profile = profile();
+        let remarks: Vec<_> = profile
+            .share_links
+            .iter()
+            .map(|share_link| share_link.remark.as_str())
+            .collect();
+        assert_eq!(remarks, ["vmess-ws", "vless-grpc", "ss", "trojan", "hy2"]);
+
+        let vmess = &profile.share_links[0];
+        assert_eq!(
+            vmess.outbound,
+            Outbound::Vmess {
+                address: "vmess.example.com".to_string(),
+                port: 443,
+                uuid: UUID.to_string(),
+                alter_id: 0,
+                security: "auto".to_string(),
+            }
+        );
+        assert_eq!(
+            vmess.transport,
+            Transport::Ws {
+                host: "cdn.example.com".to_string(),
+                path: "/ray".to_string(),
+            }
+        );
+        assert_eq!(
+            vmess.tls,
+            Some(Tls {
+                server_name: "vmess.example.com".to_string(),
+                allow_insecure: false,
+                fingerprint: None,
+            })
+        );
+
+        let vless = &profile.share_links[1];
+        assert_eq!(
+            vless.outbound,
+            Outbound::Vless {
+                address: "vless.example.com".to_string(),
+                port: 8443,
+                uuid: UUID.to_string(),
+                encryption: "none".to_string(),
+                flow: None,
+            }
+        );
+        assert_eq!(
+            vless.transport,
+            Transport::Grpc {
+                service_name: "tunnel".to_string(),
+            }
+        );
+        assert_eq!(
+            vless.tls,
+            Some(Tls {
+                server_name: "vless.example.com".to_string(),
+                allow_insecure: false,
+                fingerprint: Some("chrome".to_string()),
+            })
+        );
+
+        // Numeric passwords are kept as text and ciphers are lowercased
+        let ss = &profile.share_links[2];
+        assert_eq!(
+            ss.outbound,
+            Outbound::Shadowsocks {
+                address: "ss.example.com".to_string(),
+                port: 8388,
+                method: "aes-256-gcm".to_string(),
+                password: "12345678".to_string(),
+            }
+        );
+        assert_eq!(ss.tls, None);
+
+        // trojan always runs over TLS
+        let trojan = &profile.share_links[3];
+        assert_eq!(
+            trojan.outbound,
+            Outbound::Trojan {
+                address: "trojan.example.com".to_string(),
+                port: 443,
+                password: "secret".to_string(),
+            }
+        );
+        assert_eq!(
+            trojan.tls,
+            Some(Tls {
+                server_name: "trojan.example.com".to_string(),
+                allow_insecure: true,
+                fingerprint: None,
+            })
+        );
+
+        let hy2 = &profile.share_links[4];
+        assert_eq!(
+            hy2.outbound,
+            Outbound::Hysteria2 {
+                address: "hy2.example.com".to_string(),
+                port: 8443,
+                password: "secret".to_string(),
+                upload_mbps: 30,
+                download_mbps: 100,
+            }
+        );
+        assert_eq!(hy2.transport, Transport::Hysteria2);
+        assert!(hy2.tls.is_some());
+
+        for share_link in &profile.share_links {
+            assert_eq!(share_link::parse(&share_link.link).as_ref(), Ok(share_link));
+        }
+    }
+
+    #[test]
+    fn reports_each_skipped_proxy() {
+        assert_eq!(
+            profile().skipped,
+            [
+                "ss-plugin: Shadowsocks plugins are not supported",
+                "vless-reality: REALITY is not supported",
+                "tuic: Unsupported type: tuic",
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_other_documents_alone() {
+        let links = format!(
+            "vless://{}@vless.example.com:443?security=tls#a\ntrojan://secret@trojan.example.com:443#b\n",
+            UUID
+        );
+        // Both read as a YAML string, not as a mapping with proxies
+        assert!(parse(&links).is_none());
+        assert!(parse(&STANDARD.encode(&links)).is_none());
+        assert!(parse(include_str!("../tests/fixtures/sip008.json")).is_none());
+        assert!(parse("proxies: {}\n").is_none());
+
+        let (share_links, errors) = share_link::parse_subscription(&STANDARD.encode(&links));
+        assert_eq!(share_links.len(), 2);
+        assert!(errors.is_empty());
+    }
+}
@@ -0,0 +1,509 @@
+//! DNS leak test through the running proxy.
+//!
+//! Uses a bash.ws compatible service (`DnsLeakTestService`): a fresh test id
+//! is fetched, unique subdomains of `<id>.<service host>` are requested
+//! through the socks inbound, and the service then lists the resolvers that
+//! looked those names up. The same service is asked directly for the network
+//! of this machine; a resolver with the direct IP or in the direct ASN means
+//! queries left the tunnel. Public resolvers such as 8.8.8.8 reached through
+//! the proxy are not leaks. The `DNS` table is inspected to name the servers
+//! that resolve outside of it.
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+use std::time::Duration;
+use tauri::AppHandle;
+use tokio::task::JoinSet;
+
+use crate::proxy_check;
+use crate::utils;
+
+const LOOKUPS: usize = 10;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(3);
+const DEFAULT_SERVICE: &str = "https://bash.ws";
+
+/// One entry of the service's report.
+#[derive(Deserialize)]
+struct ServiceEntry {
+    ip: String,
+    #[serde(default)]
+    country_name: String,
+    #[serde(default)]
+    asn: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Resolver {
+    pub ip: String,
+    pub country: String,
+    pub asn: String,
+    /// Reached from the network of this machine instead of the proxy's
+    pub leaking: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DnsServerConfig {
+    pub server: String,
+    /// Resolves on this machine instead of through the proxy
+    pub local: bool,
+    /// Not listed in the `DNS` table, the core's fallback
+    pub implicit: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DnsLeakReport {
+    pub proxy_mode: Option<String>,
+    pub service: String,
+    pub test_id: String,
+    pub exit_ip: String,
+    pub exit_asn: Option<String>,
+    pub direct_ip: Option<String>,
+    pub direct_asn: Option<String>,
+    pub resolvers: Vec<Resolver>,
+    pub dns_servers: Vec<DnsServerConfig>,
+    pub leak: bool,
+    /// DNS servers that most likely sent the leaking queries
+    pub suspected_servers: Vec<String>,
+}
+
+/// Servers that resolve with the system resolver or a local DoH/TCP lookup.
+fn is_local_server(server: &str) -> bool {
+    server == "localhost" || server.contains("+local://")
+}
+
+/// Reads the servers of the user's `DNS` config. Without any, the core uses
+/// the system resolver.
+async fn dns_servers(pool: &SqlitePool, user_id: &str) -> Result<Vec<DnsServerConfig>, String> {
+    let value: Option<String> =
+        sqlx::query_scalar("SELECT Value FROM DNS WHERE UserID = ? LIMIT 1")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| format!("Failed to fetch DNS: {}", e))?;
+    let dns: serde_json::Value = value
+        .and_then(|value| serde_json::from_str(&value).ok())
+        .unwrap_or_default();
+
+    let mut servers: Vec<DnsServerConfig> = dns["servers"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|server| {
+            // Either "8.8.8.8" or {"address": "8.8.8.8", "port": 53, ...}
+            server
+                .as_str()
+                .or_else(|| server["address"].as_str())
+                .map(|server| server.to_string())
+        })
+        .map(|server| DnsServerConfig {
+            local: is_local_server(&server),
+            server,
+            implicit: false,
+        })
+        .collect();
+    if servers.is_empty() {
+        servers.push(DnsServerConfig {
+            server: "localhost".to_string(),
+            local: true,
+            implicit: true,
+        });
+    }
+    Ok(servers)
+}
+
+/// Fetches a fresh test id from the service.
+async fn start_test(client: &reqwest::Client, service: &str) -> Result<String, String> {
+    let test_id = client
+        .get(format!("{}/id", service))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to start DNS leak test: {}", e))?
+        .text()
+        .await
+        .map_err(|e| format!("Failed to read DNS leak test id: {}", e))?
+        .trim()
+        .to_string();
+    if test_id.is_empty() || !test_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!("Unexpected DNS leak test id: {}", test_id));
+    }
+    Ok(test_id)
+}
+
+async fn fetch_report(
+    client: &reqwest::Client,
+    service: &str,
+    test_id: &str,
+) -> Result<Vec<ServiceEntry>, String> {
+    client
+        .get(format!("{}/dnsleak/test/{}?json", service, test_id))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to fetch DNS leak test results: {}", e))?
+        .json()
+        .await
+        .map_err(|e| format!("Failed to parse DNS leak test results: {}", e))
+}
+
+/// Looks up unique names of a new test with `client` and returns the test
+/// id and the service's report.
+async fn leak_test(
+    client: &reqwest::Client,
+    service: &str,
+    service_host: &str,
+) -> Result<(String, Vec<ServiceEntry>), String> {
+    let test_id = start_test(client, service).await?;
+
+    // Only the lookups matter, the requests themselves are expected to fail
+    let mut lookups = JoinSet::new();
+    for index in 1..=LOOKUPS {
+        let url = format!("http://{}.{}.{}/", index, test_id, service_host);
+        let request = client.get(url).timeout(LOOKUP_TIMEOUT).send();
+        lookups.spawn(async move {
+            let _ = request.await;
+        });
+    }
+    while lookups.join_next().await.is_some() {}
+
+    let entries = fetch_report(client, service, &test_id).await?;
+    Ok((test_id, entries))
+}
+
+/// Asks the service, without lookups, where `client` connects from. The
+/// report of an empty test only holds the requester's "ip" entry.
+async fn requester(client: &reqwest::Client, service: &str) -> Result<ServiceEntry, String> {
+    let test_id = start_test(client, service).await?;
+    fetch_report(client, service, &test_id)
+        .await?
+        .into_iter()
+        .find(|entry| entry.kind == "ip")
+        .ok_or_else(|| "DNS leak test service did not report the requester".to_string())
+}
+
+/// Marks the resolvers of a report. A resolver leaks when it is the direct
+/// IP of this machine or belongs to the same ASN, i.e. the queries went out
+/// over the local network; any other resolver was reached through the proxy.
+fn resolvers(
+    entries: &[ServiceEntry],
+    direct_ip: Option<&str>,
+    direct_asn: Option<&str>,
+) -> Vec<Resolver> {
+    entries
+        .iter()
+        .filter(|entry| entry.kind == "dns")
+        .map(|entry| {
+            let leaking = direct_ip == Some(entry.ip.as_str())
+                || (!entry.asn.is_empty() && direct_asn == Some(entry.asn.as_str()));
+            Resolver {
+                ip: entry.ip.clone(),
+                country: entry.country_name.clone(),
+                asn: entry.asn.clone(),
+                leaking,
+            }
+        })
+        .collect()
+}
+
+/// Runs the leak test against the socks inbound of `user_id`.
+#[tauri::command]
+pub async fn run_dns_leak_test(app: AppHandle, user_id: String) -> Result<DnsLeakReport, String> {
+    let database_path = utils::get_database_path(&app).to_string_lossy().to_string();
+    let database_url = format!("sqlite://{}", database_path);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url)
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+
+    let socks_port: i64 =
+        sqlx::query_scalar("SELECT Port FROM Inbounds WHERE UserID = ? AND Tag = 'socks-inbound'")
+            .bind(&user_id)
+            .fetch_optional(&pool)
+            .await
+            .map_err(|e| format!("Failed to fetch socks port: {}", e))?
+            .ok_or("No socks inbound found for this user")?;
+    let socks_port = socks_port as u16;
+    let (service, proxy_mode): (Option<String>, Option<String>) =
+        sqlx::query_as("SELECT DnsLeakTestService, ProxyMode FROM AppSettings WHERE UserID = ?")
+            .bind(&user_id)
+            .fetch_optional(&pool)
+            .await
+            .map_err(|e| format!("Failed to fetch DNS leak test settings: {}", e))?
+            .unwrap_or_default();
+    let service = service
+        .filter(|service| !service.is_empty())
+        .unwrap_or_else(|| DEFAULT_SERVICE.to_string());
+    let service = service.trim_end_matches('/').to_string();
+    let service_host = reqwest::Url::parse(&service)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| host.to_string()))
+        .ok_or_else(|| format!("Invalid DNS leak test service: {}", service))?;
+
+    let proxy = reqwest::Proxy::all(format!("socks5h://127.0.0.1:{}", socks_port))
+        .map_err(|e| format!("Failed to configure proxy: {}", e))?;
+    let client = reqwest::Client::builder()
+        .proxy(proxy)
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .map_err(|e| format!("Failed to build HTTP client: {}", e))?;
+
+    let (test_id, entries) = leak_test(&client, &service, &service_host).await?;
+
+    // The service reports the address the requests came from as the "ip" entry
+    let exit = entries.iter().find(|entry| entry.kind == "ip");
+    let services = proxy_check::echo_services(&pool, &user_id).await;
+    let exit_ip = match exit {
+        Some(exit) => exit.ip.clone(),
+        None => proxy_check::fetch_ip(&services, Some(socks_port))
+            .await?
+            .0
+            .to_string(),
+    };
+    let exit_asn = exit
+        .map(|exit| exit.asn.clone())
+        .filter(|asn| !asn.is_empty());
+
+    // Echo services only tell the direct IP, the leak test service its ASN too
+    let direct_client = reqwest::Client::builder()
+        .no_proxy()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .map_err(|e| format!("Failed to build HTTP client: {}", e))?;
+    let (direct_ip, direct_asn) = match requester(&direct_client, &service).await {
+        Ok(direct) => (
+            Some(direct.ip),
+            Some(direct.asn).filter(|asn| !asn.is_empty()),
+        ),
+        Err(e) => {
+            warn!(
+                "Failed to look up the direct network, comparing IPs only: {}",
+                e
+            );
+            let direct_ip = proxy_check::fetch_ip(&services, None)
+                .await
+                .ok()
+                .map(|(ip, _)| ip.to_string());
+            (direct_ip, None)
+        }
+    };
+
+    let resolvers = resolvers(&entries, direct_ip.as_deref(), direct_asn.as_deref());
+    let leak = resolvers.iter().any(|resolver| resolver.leaking);
+
+    let dns_servers = dns_servers(&pool, &user_id).await?;
+    let suspected_servers = if leak {
+        let local: Vec<String> = dns_servers
+            .iter()
+            .filter(|server| server.local)
+            .map(|server| server.server.clone())
+            .collect();
+        // Without a local server, any configured server may be reached directly
+        if local.is_empty() {
+            dns_servers
+                .iter()
+                .map(|server| server.server.clone())
+                .collect()
+        } else {
+            local
+        }
+    } else {
+        Vec::new()
+    };
+
+    if leak {
+        warn!(
+            "DNS leak detected, suspected servers: {}",
+            suspected_servers.join(", ")
+        );
+    } else {
+        info!("No DNS leak detected");
+    }
+
+    Ok(DnsLeakReport {
+        proxy_mode,
+        service,
+        test_id,
+        exit_ip,
+        exit_asn,
+        direct_ip,
+        direct_asn,
+        resolvers,
+        dns_servers,
+        leak,
+        suspected_servers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+    use serde_json::json;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use warp::Filter;
+
+    const SERVICE_HOST: &str = "leaktest.test";
+    const EXIT_IP: &str = "203.0.113.7";
+    const DIRECT_IP: &str = "198.51.100.2";
+    const DIRECT_ASN: &str = "AS64511 Home ISP";
+
+    /// Stands in for the resolver: answers every name with the loopback
+    /// address and remembers what was asked.
+    #[derive(Clone, Default)]
+    struct StandInDns {
+        names: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Resolve for StandInDns {
+        fn resolve(&self, name: Name) -> Resolving {
+            self.names.lock().unwrap().push(name.as_str().to_string());
+            let addrs: Addrs = Box::new(std::iter::once(SocketAddr::from(([127, 0, 0, 1], 0))));
+            Box::pin(async move { Ok(addrs) })
+        }
+    }
+
+    /// Stands in for a bash.ws compatible service. Requests come from
+    /// `requester`, and the resolvers are only reported for tests whose
+    /// names reached the stand-in resolver.
+    async fn serve(dns: StandInDns, requester: (&str, &str), resolvers: &[(&str, &str)]) -> String {
+        let next_id = Arc::new(AtomicUsize::new(1));
+        let id = warp::path!("id")
+            .map(move || format!("test{}\n", next_id.fetch_add(1, Ordering::Relaxed)));
+
+        let requester = json!({
+            "ip": requester.0,
+            "country_name": "Nowhere",
+            "asn": requester.1,
+            "type": "ip",
+        });
+        let resolvers: Vec<serde_json::Value> = resolvers
+            .iter()
+            .map(|(ip, asn)| json!({ "ip": ip, "country_name": "Nowhere", "asn": asn, "type": "dns" }))
+            .collect();
+        let report = warp::path!("dnsleak" / "test" / String).map(move |test_id: String| {
+            let suffix = format!(".{}.{}", test_id, SERVICE_HOST);
+            let looked_up = dns
+                .names
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|name| name.ends_with(&suffix));
+            let mut entries = vec![requester.clone()];
+            if looked_up {
+                entries.extend(resolvers.iter().cloned());
+            }
+            warp::reply::json(&entries)
+        });
+
+        let (address, server) =
+            warp::serve(warp::get().and(id.or(report))).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        format!("http://{}:{}", SERVICE_HOST, address.port())
+    }
+
+    fn client(dns: &StandInDns) -> reqwest::Client {
+        reqwest::Client::builder()
+            .no_proxy()
+            .dns_resolver(Arc::new(dns.clone()))
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap()
+    }
+
+    /// Runs the test through a stand-in exit network and reports which of
+    /// `resolvers` leak, by IP.
+    async fn leaking(resolvers: &[(&str, &str)]) -> Vec<(String, bool)> {
+        let dns = StandInDns::default();
+        let proxied = serve(dns.clone(), (EXIT_IP, "AS64500 Exit Hosting"), resolvers).await;
+        let direct = serve(dns.clone(), (DIRECT_IP, DIRECT_ASN), &[]).await;
+        let client = client(&dns);
+
+        let (test_id, entries) = leak_test(&client, &proxied, SERVICE_HOST).await.unwrap();
+        let lookups = dns
+            .names
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|name| name.ends_with(&format!(".{}.{}", test_id, SERVICE_HOST)))
+            .count();
+        assert_eq!(lookups, LOOKUPS);
+        let exit = entries.iter().find(|entry| entry.kind == "ip").unwrap();
+        assert_eq!(exit.ip, EXIT_IP);
+
+        let direct = requester(&client, &direct).await.unwrap();
+        assert_eq!(direct.ip, DIRECT_IP);
+        assert_eq!(direct.asn, DIRECT_ASN);
+
+        super::resolvers(&entries, Some(&direct.ip), Some(&direct.asn))
+            .into_iter()
+            .map(|resolver| (resolver.ip, resolver.leaking))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn public_resolvers_behind_the_proxy_do_not_leak() {
+        let report = leaking(&[
+            ("8.8.8.8", "AS15169 Google LLC"),
+            ("1.1.1.1", "AS13335 Cloudflare, Inc."),
+            ("203.0.113.53", "AS64500 Exit Hosting"),
+        ])
+        .await;
+        assert_eq!(
+            report,
+            [
+                ("8.8.8.8".to_string(), false),
+                ("1.1.1.1".to_string(), false),
+                ("203.0.113.53".to_string(), false),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn resolvers_of_the_direct_network_leak() {
+        let report = leaking(&[
+            (DIRECT_IP, ""),
+            ("198.51.100.53", DIRECT_ASN),
+            ("8.8.8.8", "AS15169 Google LLC"),
+        ])
+        .await;
+        assert_eq!(
+            report,
+            [
+                (DIRECT_IP.to_string(), true),
+                ("198.51.100.53".to_string(), true),
+                ("8.8.8.8".to_string(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn compares_only_ips_without_the_direct_asn() {
+        let entries = vec![
+            ServiceEntry {
+                ip: "198.51.100.53".to_string(),
+                country_name: String::new(),
+                asn: String::new(),
+                kind: "dns".to_string(),
+            },
+            ServiceEntry {
+                ip: DIRECT_IP.to_string(),
+                country_name: String::new(),
+                asn: String::new(),
+                kind: "dns".to_string(),
+            },
+        ];
+        let leaking: Vec<bool> = resolvers(&entries, Some(DIRECT_IP), None)
+            .iter()
+            .map(|resolver| resolver.leaking)
+            .collect();
+        assert_eq!(leaking, [false, true]);
+    }
+}
@@ -0,0 +1,508 @@
+//! Health checks of the active endpoint and automatic switching.
+//!
+//! While the core runs, the active endpoint is probed through the local socks
+//! inbound every `HealthCheckInterval` seconds. What happens next depends on
+//! the `FailoverPolicy` of its group:
+//!
+//! - `off`: nothing, the checker stays idle.
+//! - `failover`: after `HealthCheckFailureThreshold` consecutive failures the
+//!   group is re-tested and the fastest reachable endpoint takes over.
+//! - `fastest`: as `failover`, and the group is also re-tested periodically,
+//!   switching whenever another endpoint is clearly faster.
+//!
+//! Every switch is recorded in `EndpointSwitches` and announced with a
+//! notification and an `endpoint-switched` event. So is the switch away from
+//! an active endpoint that a subscription update removed.
+
+use chrono::Utc;
+use log::{error, info, warn};
+use serde::Serialize;
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+use sqlx::Row;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_notification::NotificationExt;
+use tokio::sync::oneshot;
+
+use crate::latency;
+use crate::service_state;
+use crate::share_link::GroupChanges;
+use crate::sys_tray::SystemTrayManager;
+use crate::utils;
+use crate::v2ray_core;
+
+pub const ENDPOINT_SWITCHED_EVENT: &str = "endpoint-switched";
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+const FASTEST_RECHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+// A faster endpoint has to beat the active one by both margins, so that
+// measurement noise does not make the policy flap between two endpoints
+const FASTEST_MIN_GAIN_MS: i64 = 50;
+const FASTEST_MAX_RATIO: f64 = 0.8;
+
+pub struct HealthCheckerState {
+    shutdown_tx: Mutex<Option<oneshot::Sender<()>>>,
+}
+
+impl HealthCheckerState {
+    pub fn new() -> Self {
+        HealthCheckerState {
+            shutdown_tx: Mutex::new(None),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FailoverPolicy {
+    Off,
+    Failover,
+    Fastest,
+}
+
+impl FailoverPolicy {
+    fn parse(value: &str) -> Self {
+        match value {
+            "failover" => FailoverPolicy::Failover,
+            "fastest" => FailoverPolicy::Fastest,
+            _ => FailoverPolicy::Off,
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EndpointSwitch {
+    pub group_id: String,
+    pub from_endpoint_id: Option<String>,
+    pub to_endpoint_id: String,
+    pub to_remark: Option<String>,
+    /// "failover", "fastest" or "removed"
+    pub reason: String,
+    pub switched_at: i64,
+}
+
+/// Everything one check needs, re-read every time so settings changes apply
+/// without a restart.
+struct CheckContext {
+    user_id: String,
+    endpoint_id: String,
+    group_id: String,
+    policy: FailoverPolicy,
+    socks_port: Option<u16>,
+    test_url: String,
+    timeout: Duration,
+    interval: Duration,
+    threshold: u32,
+}
+
+async fn load_context(pool: &SqlitePool) -> Result<Option<CheckContext>, String> {
+    let row = sqlx::query(
+        "SELECT s.UserID, e.EndpointID, e.GroupID, g.FailoverPolicy,
+                a.LatencyTestUrl, a.LatencyTestTimeout, a.HealthCheckInterval, a.HealthCheckFailureThreshold,
+                (SELECT i.Port FROM Inbounds i WHERE i.UserID = s.UserID AND i.Tag = 'socks-inbound') AS SocksPort
+         FROM AppStatus s
+         JOIN AppSettings a ON a.UserID = s.UserID
+         JOIN EndpointsGroups g ON g.UserID = s.UserID
+         JOIN Endpoints e ON e.GroupID = g.GroupID AND e.Active = 1
+         WHERE s.LoginState = 1
+         LIMIT 1",
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("Failed to fetch health check settings: {}", e))?;
+
+    Ok(row.map(|row| {
+        let interval: i64 = row.get("HealthCheckInterval");
+        let threshold: i64 = row.get("HealthCheckFailureThreshold");
+        let timeout: i64 = row.get("LatencyTestTimeout");
+        CheckContext {
+            user_id: row.get("UserID"),
+            endpoint_id: row.get("EndpointID"),
+            group_id: row.get("GroupID"),
+            policy: FailoverPolicy::parse(&row.get::<String, _>("FailoverPolicy")),
+            socks_port: row
+                .get::<Option<i64>, _>("SocksPort")
+                .map(|port| port as u16),
+            test_url: row.get("LatencyTestUrl"),
+            timeout: Duration::from_millis(timeout as u64),
+            interval: if interval > 0 {
+                Duration::from_secs(interval as u64)
+            } else {
+                DEFAULT_INTERVAL
+            },
+            threshold: threshold.max(1) as u32,
+        }
+    }))
+}
+
+/// Re-tests the group, or waits a bounded time for a test already running,
+/// and returns its endpoints that answered, fastest first.
+async fn rank_group(
+    app: &AppHandle,
+    pool: &SqlitePool,
+    context: &CheckContext,
+) -> Result<Vec<(String, i64)>, String> {
+    // A failed or cancelled run still leaves the latest results behind
+    match latency::test_group_if_idle(app, &context.group_id, &context.user_id).await {
+        Ok(true) => {}
+        // A test the user started fills in the same results, let it finish
+        Ok(false) => {
+            // Even one endpoint after the other, a test ends well within this
+            let endpoints: i64 =
+                sqlx::query_scalar("SELECT COUNT(*) FROM Endpoints WHERE GroupID = ?")
+                    .bind(&context.group_id)
+                    .fetch_one(pool)
+                    .await
+                    .unwrap_or(1);
+            let limit = context.timeout * endpoints.max(1) as u32;
+            let idle = latency::wait_until_idle(app, &context.group_id);
+            if tokio::time::timeout(limit, idle).await.is_err() {
+                warn!(
+                    "Latency test of group {} still running after {:?}, ranking the latest results",
+                    context.group_id, limit
+                );
+            }
+        }
+        Err(e) => warn!("Latency test before switching endpoints failed: {}", e),
+    }
+
+    let rows = sqlx::query(
+        "SELECT EndpointID, CAST(Latency AS INTEGER) AS Latency FROM Endpoints
+         WHERE GroupID = ? AND Latency IS NOT NULL AND Latency != ''
+         ORDER BY CAST(Latency AS INTEGER)",
+    )
+    .bind(&context.group_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to fetch endpoint latencies: {}", e))?;
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.get("EndpointID"), row.get("Latency")))
+        .collect())
+}
+
+/// Switches from `from_endpoint_id` to `endpoint_id`, records the switch and
+/// announces it.
+async fn switch_to(
+    app: &AppHandle,
+    pool: &SqlitePool,
+    user_id: &str,
+    group_id: &str,
+    from_endpoint_id: &str,
+    endpoint_id: &str,
+    reason: &str,
+) -> Result<(), String> {
+    v2ray_core::switch_endpoint(app.clone(), endpoint_id.to_string(), user_id.to_string()).await?;
+
+    let switched_at = Utc::now().timestamp();
+    sqlx::query(
+        "INSERT INTO EndpointSwitches (UserID, GroupID, FromEndpointID, ToEndpointID, Reason, SwitchedAt) VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(user_id)
+    .bind(group_id)
+    .bind(from_endpoint_id)
+    .bind(endpoint_id)
+    .bind(reason)
+    .bind(switched_at)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to record endpoint switch: {}", e))?;
+
+    let to_remark: Option<String> =
+        sqlx::query_scalar("SELECT Remark FROM Endpoints WHERE EndpointID = ?")
+            .bind(endpoint_id)
+            .fetch_optional(pool)
+            .await
+            .unwrap_or(None);
+    info!(
+        "Switched from endpoint {} to {} ({})",
+        from_endpoint_id, endpoint_id, reason
+    );
+
+    let body = match reason {
+        "failover" => format!(
+            "The active endpoint stopped responding, switched to {}",
+            to_remark.as_deref().unwrap_or(endpoint_id)
+        ),
+        "removed" => format!(
+            "The active endpoint was removed from its group, switched to {}",
+            to_remark.as_deref().unwrap_or(endpoint_id)
+        ),
+        _ => format!(
+            "Switched to the faster endpoint {}",
+            to_remark.as_deref().unwrap_or(endpoint_id)
+        ),
+    };
+    if let Err(e) = app
+        .notification()
+        .builder()
+        .title("V2rayX")
+        .body(body)
+        .show()
+    {
+        warn!("Failed to show endpoint switch notification: {}", e);
+    }
+
+    if let Err(e) = app.emit(
+        ENDPOINT_SWITCHED_EVENT,
+        EndpointSwitch {
+            group_id: group_id.to_string(),
+            from_endpoint_id: Some(from_endpoint_id.to_string()),
+            to_endpoint_id: endpoint_id.to_string(),
+            to_remark,
+            reason: reason.to_string(),
+            switched_at,
+        },
+    ) {
+        warn!("Failed to emit endpoint switch: {}", e);
+    }
+
+    if let Some(tray_manager) = app.try_state::<SystemTrayManager>() {
+        tray_manager.update_menu(app, user_id.to_string()).await;
+    }
+    Ok(())
+}
+
+/// Moves the core, tray and UI over after an import removed the active
+/// endpoint and [`crate::share_link::sync_group`] made another one active.
+pub async fn follow_active_move(app: &AppHandle, pool: &SqlitePool, changes: &GroupChanges) {
+    let Some(moved) = &changes.active_moved else {
+        return;
+    };
+    let result = async {
+        let user_id: String =
+            sqlx::query_scalar("SELECT UserID FROM EndpointsGroups WHERE GroupID = ?")
+                .bind(&moved.group_id)
+                .fetch_one(pool)
+                .await
+                .map_err(|e| format!("Failed to fetch endpoint group: {}", e))?;
+        switch_to(
+            app,
+            pool,
+            &user_id,
+            &moved.group_id,
+            &moved.from_endpoint_id,
+            &moved.to_endpoint_id,
+            "removed",
+        )
+        .await
+    }
+    .await;
+    if let Err(e) = result {
+        error!(
+            "Failed to switch away from the removed active endpoint: {}",
+            e
+        );
+    }
+    if let Err(e) = app.emit("refresh", "endpoints") {
+        warn!("Failed to emit refresh event: {}", e);
+    }
+}
+
+/// Switches away from the failing active endpoint. Returns whether another
+/// endpoint was available.
+async fn fail_over(
+    app: &AppHandle,
+    pool: &SqlitePool,
+    context: &CheckContext,
+) -> Result<bool, String> {
+    let ranked = rank_group(app, pool, context).await?;
+    let Some((endpoint_id, _)) = ranked
+        .into_iter()
+        .find(|(endpoint_id, _)| *endpoint_id != context.endpoint_id)
+    else {
+        return Ok(false);
+    };
+    switch_to(
+        app,
+        pool,
+        &context.user_id,
+        &context.group_id,
+        &context.endpoint_id,
+        &endpoint_id,
+        "failover",
+    )
+    .await?;
+    Ok(true)
+}
+
+/// Switches to the fastest endpoint of the group if it clearly beats the
+/// active one.
+async fn prefer_fastest(
+    app: &AppHandle,
+    pool: &SqlitePool,
+    context: &CheckContext,
+) -> Result<(), String> {
+    let ranked = rank_group(app, pool, context).await?;
+    let Some((fastest_id, fastest)) = ranked.first().cloned() else {
+        return Ok(());
+    };
+    if fastest_id == context.endpoint_id {
+        return Ok(());
+    }
+
+    let current = ranked
+        .iter()
+        .find(|(endpoint_id, _)| *endpoint_id == context.endpoint_id)
+        .map(|(_, latency)| *latency);
+    let clearly_faster = match current {
+        Some(current) => {
+            current - fastest >= FASTEST_MIN_GAIN_MS
+                && (fastest as f64) <= current as f64 * FASTEST_MAX_RATIO
+        }
+        // The active endpoint did not answer the test at all
+        None => true,
+    };
+    if clearly_faster {
+        switch_to(
+            app,
+            pool,
+            &context.user_id,
+            &context.group_id,
+            &context.endpoint_id,
+            &fastest_id,
+            "fastest",
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Starts the health checker, replacing any running one.
+pub fn start_health_checker(app: &AppHandle) {
+    let (shutdown_tx, mut shutdown_rx) = oneshot::channel::<()>();
+    {
+        let state = app.state::<HealthCheckerState>();
+        let previous = state.shutdown_tx.lock().unwrap().replace(shutdown_tx);
+        if let Some(tx) = previous {
+            let _ = tx.send(());
+        }
+    }
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let database_path = utils::get_database_path(&app).to_string_lossy().to_string();
+        let database_url = format!("sqlite://{}", database_path);
+        let pool = match SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(&database_url)
+            .await
+        {
+            Ok(pool) => pool,
+            Err(e) => {
+                error!("Failed to start health checker: {}", e);
+                return;
+            }
+        };
+
+        let mut interval = DEFAULT_INTERVAL;
+        let mut failures = 0;
+        let mut last_fastest_check = Instant::now();
+        loop {
+            tokio::select! {
+                _ = &mut shutdown_rx => break,
+                _ = tokio::time::sleep(interval) => {}
+            }
+
+            let context = match load_context(&pool).await {
+                Ok(Some(context)) => context,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("{}", e);
+                    continue;
+                }
+            };
+            interval = context.interval;
+            if context.policy == FailoverPolicy::Off {
+                failures = 0;
+                continue;
+            }
+            let Some(socks_port) = context.socks_port else {
+                continue;
+            };
+
+            match latency::url_test_latency(socks_port, &context.test_url, context.timeout).await {
+                Ok(_) => {
+                    failures = 0;
+                    service_state::report_health(&app, true, None).await;
+                }
+                Err(e) => {
+                    failures += 1;
+                    warn!(
+                        "Health check of endpoint {} failed ({}/{}): {}",
+                        context.endpoint_id, failures, context.threshold, e
+                    );
+                    service_state::report_health(&app, false, Some(e.message)).await;
+                }
+            }
+
+            if failures >= context.threshold {
+                match fail_over(&app, &pool, &context).await {
+                    Ok(true) => failures = 0,
+                    Ok(false) => warn!("No other endpoint to fail over to"),
+                    Err(e) => error!("Failover failed: {}", e),
+                }
+            } else if failures == 0
+                && context.policy == FailoverPolicy::Fastest
+                && last_fastest_check.elapsed() >= FASTEST_RECHECK_INTERVAL
+            {
+                last_fastest_check = Instant::now();
+                if let Err(e) = prefer_fastest(&app, &pool, &context).await {
+                    error!("Switching to the fastest endpoint failed: {}", e);
+                }
+            }
+        }
+        info!("Health checker stopped");
+    });
+}
+
+pub fn stop_health_checker(app: &AppHandle) {
+    let state = app.state::<HealthCheckerState>();
+    let shutdown_tx = state.shutdown_tx.lock().unwrap().take();
+    if let Some(tx) = shutdown_tx {
+        let _ = tx.send(());
+    }
+}
+
+/// Returns the latest automatic endpoint switches of `user_id`, newest first.
+#[tauri::command]
+pub async fn get_endpoint_switches(
+    app: AppHandle,
+    user_id: String,
+    limit: Option<i64>,
+) -> Result<Vec<EndpointSwitch>, String> {
+    let database_path = utils::get_database_path(&app).to_string_lossy().to_string();
+    let database_url = format!("sqlite://{}", database_path);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url)
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+
+    let rows = sqlx::query(
+        "SELECT s.GroupID, s.FromEndpointID, s.ToEndpointID, e.Remark, s.Reason, s.SwitchedAt
+         FROM EndpointSwitches s
+         LEFT JOIN Endpoints e ON e.EndpointID = s.ToEndpointID
+         WHERE s.UserID = ?
+         ORDER BY s.SwitchedAt DESC, s.SwitchID DESC
+         LIMIT ?",
+    )
+    .bind(&user_id)
+    .bind(limit.unwrap_or(100))
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| format!("Failed to fetch endpoint switches: {}", e))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| EndpointSwitch {
+            group_id: row.get("GroupID"),
+            from_endpoint_id: row.get("FromEndpointID"),
+            to_endpoint_id: row.get("ToEndpointID"),
+            to_remark: row.get("Remark"),
+            reason: row.get("Reason"),
+            switched_at: row.get("SwitchedAt"),
+        })
+        .collect())
+}
@@ -0,0 +1,586 @@
+//! Endpoint latency testing.
+//!
+//! All endpoints of a group are tested concurrently on tokio, bounded by the
+//! user's `LatencyTestConcurrency` setting. Results are written as they come
+//! in and reported through a `latency-progress` event per endpoint, so the UI
+//! can fill them in without waiting for the whole group. A running test can
+//! be cancelled per group or altogether. Each result is also kept as a sample
+//! in the endpoint's history, see [`latency_history`].
+//!
+//! The "ping" test type sends an ICMP echo where unprivileged ICMP sockets
+//! are allowed and falls back to a TCP handshake elsewhere, reporting the
+//! method actually used. The "tcp" test type fetches `LatencyTestUrl` through
+//! the endpoint itself, using a throwaway core (see [`ProbeCore`]) that
+//! serves a batch of endpoints on local socks ports.
+
+use log::{info, warn};
+use reqwest::Client;
+use serde::Serialize;
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+use sqlx::Row;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::net::TcpStream;
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinSet;
+
+use crate::latency_history::{self, ErrorKind};
+use crate::ping::{self, PingError};
+use crate::telemetry;
+use crate::utils;
+use crate::v2ray_core::probe::ProbeCore;
+
+pub const LATENCY_PROGRESS_EVENT: &str = "latency-progress";
+pub const LATENCY_FINISHED_EVENT: &str = "latency-finished";
+
+const DEFAULT_CONCURRENCY: i64 = 16;
+// Endpoints sharing one probe core during real URL tests
+const URL_TEST_BATCH_SIZE: usize = 64;
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Cancellation switches of the tests currently running, by group.
+pub struct LatencyTestState {
+    runs: Mutex<HashMap<String, (u64, watch::Sender<bool>)>>,
+    next_run_id: AtomicU64,
+}
+
+impl LatencyTestState {
+    pub fn new() -> Self {
+        LatencyTestState {
+            runs: Mutex::new(HashMap::new()),
+            next_run_id: AtomicU64::new(0),
+        }
+    }
+
+    fn is_running(&self, group_id: &str) -> bool {
+        self.runs.lock().unwrap().contains_key(group_id)
+    }
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyProgress {
+    pub group_id: String,
+    pub endpoint_id: String,
+    /// Milliseconds, `None` when the endpoint could not be reached
+    pub latency: Option<i64>,
+    pub error: Option<String>,
+    /// What was measured: "icmp", "tcpConnect" or "url"
+    pub method: String,
+    pub completed: usize,
+    pub total: usize,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyFinished {
+    pub group_id: String,
+    pub completed: usize,
+    pub total: usize,
+    pub cancelled: bool,
+    /// Why a "ping" test fell back to TCP handshakes
+    pub fallback_reason: Option<String>,
+}
+
+/// Forgets a run in [`LatencyTestState`] when dropped, so no way out of
+/// [`run`] leaves its group marked as running. A newer run that has taken
+/// the group's place is left alone.
+struct RunRegistration<'a> {
+    app: &'a AppHandle,
+    group_id: &'a str,
+    run_id: u64,
+}
+
+impl Drop for RunRegistration<'_> {
+    fn drop(&mut self) {
+        let state = self.app.state::<LatencyTestState>();
+        let mut runs = state.runs.lock().unwrap();
+        if runs
+            .get(self.group_id)
+            .is_some_and(|(id, _)| *id == self.run_id)
+        {
+            runs.remove(self.group_id);
+        }
+    }
+}
+
+/// A failed test, with the category its history sample is stored under.
+#[derive(Debug)]
+pub struct LatencyError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl LatencyError {
+    pub fn new(kind: ErrorKind, message: String) -> Self {
+        LatencyError { kind, message }
+    }
+}
+
+impl fmt::Display for LatencyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+pub struct LatencyTarget {
+    pub endpoint_id: String,
+    pub address: Option<String>,
+    pub port: Option<u16>,
+}
+
+pub async fn load_targets(pool: &SqlitePool, group_id: &str) -> Result<Vec<LatencyTarget>, String> {
+    let rows = sqlx::query(
+        "SELECT e.EndpointID, e.Remark,
+         CASE o.Protocol
+            WHEN 'vmess' THEN (SELECT v.Address FROM VmessVnext v JOIN VmessUsers u ON v.VnextID = u.VnextID WHERE u.EndpointID = e.EndpointID)
+            WHEN 'shadowsocks' THEN (SELECT Address FROM Shadowsocks WHERE EndpointID = e.EndpointID)
+            WHEN 'trojan' THEN (SELECT Address FROM TrojanServers WHERE EndpointID = e.EndpointID)
+            WHEN 'vless' THEN (SELECT Address FROM VlessServers WHERE EndpointID = e.EndpointID)
+            WHEN 'hysteria2' THEN (SELECT Address FROM Hysteria2 WHERE EndpointID = e.EndpointID)
+            ELSE NULL
+         END AS Address,
+         CASE o.Protocol
+            WHEN 'vmess' THEN (SELECT v.Port FROM VmessVnext v JOIN VmessUsers u ON v.VnextID = u.VnextID WHERE u.EndpointID = e.EndpointID)
+            WHEN 'shadowsocks' THEN (SELECT Port FROM Shadowsocks WHERE EndpointID = e.EndpointID)
+            WHEN 'trojan' THEN (SELECT Port FROM TrojanServers WHERE EndpointID = e.EndpointID)
+            WHEN 'vless' THEN (SELECT Port FROM VlessServers WHERE EndpointID = e.EndpointID)
+            WHEN 'hysteria2' THEN (SELECT Port FROM Hysteria2 WHERE EndpointID = e.EndpointID)
+            ELSE NULL
+         END AS Port
+         FROM Endpoints e
+         LEFT JOIN Outbounds o ON e.EndpointID = o.EndpointID
+         WHERE e.GroupID = ?",
+    )
+    .bind(group_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to fetch endpoints: {}", e))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| LatencyTarget {
+            endpoint_id: row.get("EndpointID"),
+            address: row.get("Address"),
+            port: row.get::<Option<i64>, _>("Port").map(|port| port as u16),
+        })
+        .collect())
+}
+
+/// Resolves `address` without blocking and returns the time the first
+/// successful TCP handshake took, in milliseconds.
+pub async fn tcp_connect_latency(
+    address: &str,
+    port: u16,
+    timeout: Duration,
+) -> Result<i64, LatencyError> {
+    let addrs: Vec<_> = tokio::time::timeout(timeout, tokio::net::lookup_host((address, port)))
+        .await
+        .map_err(|_| {
+            LatencyError::new(
+                ErrorKind::Dns,
+                format!("Address resolution timed out: {}", address),
+            )
+        })?
+        .map_err(|e| {
+            LatencyError::new(ErrorKind::Dns, format!("Address resolution failed: {}", e))
+        })?
+        .collect();
+    if addrs.is_empty() {
+        return Err(LatencyError::new(
+            ErrorKind::Dns,
+            format!("Could not resolve address: {}:{}", address, port),
+        ));
+    }
+
+    let mut last_error = None;
+    for addr in addrs {
+        let start = Instant::now();
+        let error = match tokio::time::timeout(timeout, TcpStream::connect(addr)).await {
+            Ok(Ok(_)) => return Ok(start.elapsed().as_millis() as i64),
+            Ok(Err(e)) => LatencyError::new(
+                if e.kind() == std::io::ErrorKind::ConnectionRefused {
+                    ErrorKind::Refused
+                } else {
+                    ErrorKind::Other
+                },
+                format!("TCP connection to {} failed: {}", addr, e),
+            ),
+            Err(_) => LatencyError::new(
+                ErrorKind::Timeout,
+                format!("TCP connection to {} timed out", addr),
+            ),
+        };
+        last_error = Some(error);
+    }
+    // There was at least one address
+    Err(last_error.unwrap())
+}
+
+/// Fetches `test_url` through the local socks port of a probe core and
+/// returns the round trip in milliseconds. Any HTTP response counts, it
+/// proves the request made it through the endpoint.
+pub async fn url_test_latency(
+    socks_port: u16,
+    test_url: &str,
+    timeout: Duration,
+) -> Result<i64, LatencyError> {
+    let proxy =
+        reqwest::Proxy::all(format!("socks5h://127.0.0.1:{}", socks_port)).map_err(|e| {
+            LatencyError::new(
+                ErrorKind::Probe,
+                format!("Failed to configure proxy: {}", e),
+            )
+        })?;
+    let client = Client::builder()
+        .proxy(proxy)
+        .timeout(timeout)
+        .build()
+        .map_err(|e| {
+            LatencyError::new(
+                ErrorKind::Probe,
+                format!("Failed to build HTTP client: {}", e),
+            )
+        })?;
+
+    let start = Instant::now();
+    client.get(test_url).send().await.map_err(|e| {
+        LatencyError::new(
+            if e.is_timeout() {
+                ErrorKind::Timeout
+            } else {
+                ErrorKind::Other
+            },
+            format!("Request through endpoint failed: {}", e),
+        )
+    })?;
+    Ok(start.elapsed().as_millis() as i64)
+}
+
+/// Tests every endpoint of `group_id` and stores the results. A test of the
+/// group already in progress is cancelled. Returns an error if the run was
+/// cancelled before all endpoints were tested.
+pub async fn test_group(app: &AppHandle, group_id: &str, user_id: &str) -> Result<(), String> {
+    run(app, group_id, user_id, true).await.map(|_| ())
+}
+
+/// Like [`test_group`], but leaves a test of the group already in progress
+/// alone and returns `false` instead of starting another.
+pub async fn test_group_if_idle(
+    app: &AppHandle,
+    group_id: &str,
+    user_id: &str,
+) -> Result<bool, String> {
+    run(app, group_id, user_id, false).await
+}
+
+/// Waits until no test of `group_id` is running.
+pub async fn wait_until_idle(app: &AppHandle, group_id: &str) {
+    while app.state::<LatencyTestState>().is_running(group_id) {
+        tokio::time::sleep(IDLE_POLL_INTERVAL).await;
+    }
+}
+
+async fn run(
+    app: &AppHandle,
+    group_id: &str,
+    user_id: &str,
+    replace: bool,
+) -> Result<bool, String> {
+    let database_path = utils::get_database_path(app).to_string_lossy().to_string();
+    let database_url = format!("sqlite://{}", database_path);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url)
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+
+    let speed_test_type: String = sqlx::query_scalar(
+        "SELECT SpeedTestType FROM EndpointsGroups WHERE GroupID = ? AND UserID = ?",
+    )
+    .bind(group_id)
+    .bind(user_id)
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| format!("Failed to fetch group info: {}", e))?;
+    let speed_test_type = speed_test_type.to_lowercase();
+
+    let (test_url, timeout, concurrency): (String, i64, i64) = sqlx::query_as(
+        "SELECT LatencyTestUrl, LatencyTestTimeout, LatencyTestConcurrency FROM AppSettings WHERE UserID = ?",
+    )
+    .bind(user_id)
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| format!("Failed to fetch latency test settings: {}", e))?;
+    let timeout = Duration::from_millis(timeout as u64);
+    let concurrency = if concurrency > 0 {
+        concurrency
+    } else {
+        DEFAULT_CONCURRENCY
+    };
+
+    let url_test = match speed_test_type.as_str() {
+        "ping" | "connect" => false,
+        "tcp" => true,
+        _ => return Err(format!("Unknown speed test type: {}", speed_test_type)),
+    };
+    // Plain ICMP where the system allows it, a TCP handshake otherwise
+    let mut fallback_reason = None;
+    let icmp = speed_test_type == "ping"
+        && match ping::availability() {
+            Ok(()) => true,
+            Err(reason) => {
+                info!("Falling back to TCP connect for ping: {}", reason);
+                fallback_reason = Some(reason);
+                false
+            }
+        };
+
+    let targets = load_targets(&pool, group_id).await?;
+    let total = targets.len();
+    // Real URL tests share one probe core per batch, handshakes need none
+    let batch_size = if url_test {
+        URL_TEST_BATCH_SIZE
+    } else {
+        total.max(1)
+    };
+
+    // Checked and registered under one lock, so a run that yields cannot race
+    // another run of the same group into starting twice
+    let (cancel_tx, mut cancel_rx) = watch::channel(false);
+    let registration = {
+        let state = app.state::<LatencyTestState>();
+        let mut runs = state.runs.lock().unwrap();
+        if !replace && runs.contains_key(group_id) {
+            return Ok(false);
+        }
+        let run_id = state.next_run_id.fetch_add(1, Ordering::Relaxed);
+        if let Some((_, previous)) = runs.insert(group_id.to_string(), (run_id, cancel_tx)) {
+            let _ = previous.send(true);
+        }
+        RunRegistration {
+            app,
+            group_id,
+            run_id,
+        }
+    };
+
+    let semaphore = Arc::new(Semaphore::new(concurrency as usize));
+    let mut targets = targets.into_iter().peekable();
+    let mut completed = 0;
+    let mut spawned: usize = 0;
+    let mut cancelled = false;
+    while targets.peek().is_some() && !cancelled {
+        let batch: Vec<LatencyTarget> = targets.by_ref().take(batch_size).collect();
+
+        let probe = if url_test {
+            let endpoint_ids: Vec<String> = batch
+                .iter()
+                .map(|target| target.endpoint_id.clone())
+                .collect();
+            Some(ProbeCore::start(app, user_id, &endpoint_ids).await)
+        } else {
+            None
+        };
+
+        let mut tasks = JoinSet::new();
+        for target in batch {
+            let semaphore = semaphore.clone();
+            let test_url = test_url.clone();
+            let socks_port = match &probe {
+                Some(Ok(probe)) => Some(
+                    probe
+                        .socks_port(&target.endpoint_id)
+                        .map_err(|e| LatencyError::new(ErrorKind::Probe, e)),
+                ),
+                Some(Err(e)) => Some(Err(LatencyError::new(ErrorKind::Probe, e.clone()))),
+                None => None,
+            };
+            // Only tells the replies of concurrent pings apart
+            let sequence = spawned as u16;
+            spawned += 1;
+            tasks.spawn(async move {
+                // The semaphore is never closed, so acquiring cannot fail
+                let _permit = semaphore.acquire_owned().await;
+                let (method, result) = match (socks_port, target.address, target.port) {
+                    (Some(socks_port), _, _) => (
+                        "url",
+                        match socks_port {
+                            Ok(socks_port) => {
+                                url_test_latency(socks_port, &test_url, timeout).await
+                            }
+                            Err(e) => Err(e),
+                        },
+                    ),
+                    (None, Some(address), Some(port)) => {
+                        let pinged = if icmp {
+                            match ping::icmp_ping_latency(&address, sequence, timeout).await {
+                                Ok(latency) => Some(Ok(latency)),
+                                Err(PingError::Failed(e)) => Some(Err(e)),
+                                // e.g. ICMPv6 is not permitted while ICMPv4 is
+                                Err(PingError::Unavailable(reason)) => {
+                                    info!("Pinging {} over TCP instead: {}", address, reason);
+                                    None
+                                }
+                            }
+                        } else {
+                            None
+                        };
+                        match pinged {
+                            Some(result) => ("icmp", result),
+                            None => (
+                                "tcpConnect",
+                                tcp_connect_latency(&address, port, timeout).await,
+                            ),
+                        }
+                    }
+                    _ => (
+                        if url_test { "url" } else { "tcpConnect" },
+                        Err(LatencyError::new(
+                            ErrorKind::NoAddress,
+                            "Endpoint has no address".to_string(),
+                        )),
+                    ),
+                };
+                (target.endpoint_id, method, result)
+            });
+        }
+
+        loop {
+            let joined = tokio::select! {
+                joined = tasks.join_next() => joined,
+                _ = cancel_rx.changed() => {
+                    cancelled = true;
+                    tasks.abort_all();
+                    break;
+                }
+            };
+            let Some(joined) = joined else {
+                break;
+            };
+            let (endpoint_id, method, result) = match joined {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    warn!("Latency test task failed: {}", e);
+                    continue;
+                }
+            };
+            completed += 1;
+
+            // Failed endpoints are stored as NULL, which the UI shows as a timeout
+            let latency = result.as_ref().ok().copied();
+            if let Err(e) = sqlx::query("UPDATE Endpoints SET Latency = ? WHERE EndpointID = ?")
+                .bind(latency.map(|latency| latency.to_string()))
+                .bind(&endpoint_id)
+                .execute(&pool)
+                .await
+            {
+                warn!("Failed to update endpoint latency: {}", e);
+            }
+            if let Err(e) =
+                latency_history::record_sample(&pool, &endpoint_id, method, &result).await
+            {
+                warn!("{}", e);
+            }
+            telemetry::track_connection_attempt(latency.is_some());
+            telemetry::track_feature_usage("latency_test");
+
+            if let Err(e) = app.emit(
+                LATENCY_PROGRESS_EVENT,
+                LatencyProgress {
+                    group_id: group_id.to_string(),
+                    endpoint_id,
+                    latency,
+                    error: result.err().map(|e| e.message),
+                    method: method.to_string(),
+                    completed,
+                    total,
+                },
+            ) {
+                warn!("Failed to emit latency progress: {}", e);
+            }
+        }
+        // Dropping the probe stops its core
+    }
+
+    drop(registration);
+
+    match latency_history::prune(&pool).await {
+        Ok(0) => {}
+        Ok(pruned) => info!("Pruned {} latency samples", pruned),
+        Err(e) => warn!("{}", e),
+    }
+
+    let _ = app.emit(
+        LATENCY_FINISHED_EVENT,
+        LatencyFinished {
+            group_id: group_id.to_string(),
+            completed,
+            total,
+            cancelled,
+            fallback_reason,
+        },
+    );
+
+    if cancelled {
+        Err("Latency test cancelled".to_string())
+    } else {
+        Ok(true)
+    }
+}
+
+/// Cancels the running latency test of `group_id`, or every running test.
+#[tauri::command]
+pub fn cancel_latency_test(state: State<'_, LatencyTestState>, group_id: Option<String>) {
+    let mut runs = state.runs.lock().unwrap();
+    match group_id {
+        Some(group_id) => {
+            if let Some((_, tx)) = runs.remove(&group_id) {
+                let _ = tx.send(true);
+            }
+        }
+        None => {
+            for (_, (_, tx)) in runs.drain() {
+                let _ = tx.send(true);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    #[tokio::test]
+    async fn measures_a_handshake() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(tcp_connect_latency("127.0.0.1", port, TIMEOUT)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn tells_refused_connections_apart() {
+        let port = portpicker::pick_unused_port().unwrap();
+        let error = tcp_connect_latency("127.0.0.1", port, TIMEOUT)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Refused, "{}", error);
+    }
+
+    #[tokio::test]
+    async fn tells_resolution_failures_apart() {
+        // .invalid never resolves (RFC 2606)
+        let error = tcp_connect_latency("endpoint.invalid", 443, TIMEOUT)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Dns, "{}", error);
+    }
+}
@@ -0,0 +1,269 @@
+//! Latency history per endpoint.
+//!
+//! `Endpoints.Latency` only holds the latest result. Every test also appends
+//! a row to `LatencySamples`, so the frontend can tell a consistently slow
+//! endpoint from a fast but unreliable one through [`get_latency_stats`].
+//! ICMP, TCP handshake and URL latencies are not comparable, so samples are
+//! aggregated per method.
+//! Samples older than [`RETENTION_DAYS`] are pruned after each test run.
+
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+use sqlx::Row;
+use std::collections::HashMap;
+use tauri::AppHandle;
+
+use crate::latency::LatencyError;
+use crate::utils;
+
+pub const RETENTION_DAYS: i64 = 30;
+// Keeps one endpoint tested in a tight loop from growing the table unbounded
+const MAX_SAMPLES_PER_ENDPOINT: i64 = 1000;
+const DEFAULT_WINDOW_HOURS: i64 = 24;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyStats {
+    pub endpoint_id: String,
+    /// "icmp", "tcpConnect" or "url"
+    pub method: String,
+    pub samples: usize,
+    pub successes: usize,
+    /// Between 0 and 1
+    pub success_rate: f64,
+    /// Milliseconds over the successful samples, `None` without any
+    pub median: Option<i64>,
+    pub p95: Option<i64>,
+    /// Mean difference between consecutive successful samples
+    pub jitter: Option<f64>,
+    pub last_latency: Option<i64>,
+    pub last_error_kind: Option<String>,
+    pub last_tested_at: Option<i64>,
+}
+
+/// Coarse category of a failed test, decided where the error is created.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    Timeout,
+    Dns,
+    Refused,
+    NoAddress,
+    Probe,
+    Other,
+}
+
+impl ErrorKind {
+    /// The value stored in `LatencySamples.ErrorKind`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::Dns => "dns",
+            ErrorKind::Refused => "refused",
+            ErrorKind::NoAddress => "noAddress",
+            ErrorKind::Probe => "probe",
+            ErrorKind::Other => "other",
+        }
+    }
+}
+
+pub async fn record_sample(
+    pool: &SqlitePool,
+    endpoint_id: &str,
+    method: &str,
+    result: &Result<i64, LatencyError>,
+) -> Result<(), String> {
+    sqlx::query(
+        "INSERT INTO LatencySamples (EndpointID, TestedAt, Method, Latency, ErrorKind) VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(endpoint_id)
+    .bind(Utc::now().timestamp())
+    .bind(method)
+    .bind(result.as_ref().ok())
+    .bind(result.as_ref().err().map(|e| e.kind.as_str()))
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to record latency sample: {}", e))?;
+    Ok(())
+}
+
+/// Drops expired samples, samples of deleted endpoints and anything beyond
+/// the per-endpoint cap.
+pub async fn prune(pool: &SqlitePool) -> Result<u64, String> {
+    let cutoff = Utc::now().timestamp() - RETENTION_DAYS * 24 * 60 * 60;
+    let result = sqlx::query(
+        "DELETE FROM LatencySamples
+         WHERE TestedAt < ?
+            OR EndpointID NOT IN (SELECT EndpointID FROM Endpoints)
+            OR SampleID IN (
+                SELECT SampleID FROM (
+                    SELECT SampleID,
+                           ROW_NUMBER() OVER (PARTITION BY EndpointID ORDER BY TestedAt DESC, SampleID DESC) AS Position
+                    FROM LatencySamples
+                ) WHERE Position > ?
+            )",
+    )
+    .bind(cutoff)
+    .bind(MAX_SAMPLES_PER_ENDPOINT)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to prune latency samples: {}", e))?;
+    Ok(result.rows_affected())
+}
+
+/// Nearest-rank percentile of an ascending slice.
+fn percentile(sorted: &[i64], percent: usize) -> Option<i64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    sorted.get(rank - 1).copied()
+}
+
+struct Sample {
+    tested_at: i64,
+    latency: Option<i64>,
+    error_kind: Option<String>,
+}
+
+fn aggregate(endpoint_id: String, method: String, samples: &[Sample]) -> LatencyStats {
+    // Samples are in test order, which jitter depends on
+    let latencies: Vec<i64> = samples.iter().filter_map(|sample| sample.latency).collect();
+    let jitter = (latencies.len() > 1).then(|| {
+        let total: i64 = latencies
+            .windows(2)
+            .map(|pair| (pair[1] - pair[0]).abs())
+            .sum();
+        total as f64 / (latencies.len() - 1) as f64
+    });
+
+    let mut sorted = latencies.clone();
+    sorted.sort_unstable();
+    let last = samples.last();
+
+    LatencyStats {
+        endpoint_id,
+        method,
+        samples: samples.len(),
+        successes: latencies.len(),
+        success_rate: if samples.is_empty() {
+            0.0
+        } else {
+            latencies.len() as f64 / samples.len() as f64
+        },
+        median: percentile(&sorted, 50),
+        p95: percentile(&sorted, 95),
+        jitter,
+        last_latency: last.and_then(|sample| sample.latency),
+        last_error_kind: last.and_then(|sample| sample.error_kind.clone()),
+        last_tested_at: last.map(|sample| sample.tested_at),
+    }
+}
+
+/// Aggregates the samples of the last `window_hours` (24 by default) for
+/// every endpoint of `group_id` that has any, once per method. `method`
+/// limits the stats to one method.
+#[tauri::command]
+pub async fn get_latency_stats(
+    app: AppHandle,
+    group_id: String,
+    window_hours: Option<i64>,
+    method: Option<String>,
+) -> Result<Vec<LatencyStats>, String> {
+    let database_path = utils::get_database_path(&app).to_string_lossy().to_string();
+    let database_url = format!("sqlite://{}", database_path);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url)
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+
+    let since =
+        Utc::now().timestamp() - window_hours.unwrap_or(DEFAULT_WINDOW_HOURS).max(0) * 60 * 60;
+    let rows = sqlx::query(
+        "SELECT s.EndpointID, s.Method, s.TestedAt, s.Latency, s.ErrorKind
+         FROM LatencySamples s
+         JOIN Endpoints e ON e.EndpointID = s.EndpointID
+         WHERE e.GroupID = ? AND s.TestedAt >= ? AND (? IS NULL OR s.Method = ?)
+         ORDER BY s.TestedAt, s.SampleID",
+    )
+    .bind(&group_id)
+    .bind(since)
+    .bind(&method)
+    .bind(&method)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| format!("Failed to fetch latency samples: {}", e))?;
+
+    let mut by_endpoint: HashMap<(String, String), Vec<Sample>> = HashMap::new();
+    for row in rows {
+        by_endpoint
+            .entry((row.get("EndpointID"), row.get("Method")))
+            .or_default()
+            .push(Sample {
+                tested_at: row.get("TestedAt"),
+                latency: row.get("Latency"),
+                error_kind: row.get("ErrorKind"),
+            });
+    }
+
+    let mut stats: Vec<LatencyStats> = by_endpoint
+        .into_iter()
+        .map(|((endpoint_id, method), samples)| aggregate(endpoint_id, method, &samples))
+        .collect();
+    stats.sort_by(|a, b| {
+        a.endpoint_id
+            .cmp(&b.endpoint_id)
+            .then_with(|| a.method.cmp(&b.method))
+    });
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(tested_at: i64, latency: Option<i64>) -> Sample {
+        Sample {
+            tested_at,
+            latency,
+            error_kind: latency
+                .is_none()
+                .then(|| ErrorKind::Timeout.as_str().to_string()),
+        }
+    }
+
+    #[test]
+    fn aggregates_the_samples_of_one_method() {
+        let samples = [
+            sample(1, Some(100)),
+            sample(2, Some(140)),
+            sample(3, None),
+            sample(4, Some(120)),
+        ];
+        let stats = aggregate("endpoint".to_string(), "url".to_string(), &samples);
+        assert_eq!(stats.method, "url");
+        assert_eq!(stats.samples, 4);
+        assert_eq!(stats.successes, 3);
+        assert_eq!(stats.success_rate, 0.75);
+        assert_eq!(stats.median, Some(120));
+        assert_eq!(stats.p95, Some(140));
+        // |140 - 100| and |120 - 140|
+        assert_eq!(stats.jitter, Some(30.0));
+        assert_eq!(stats.last_latency, Some(120));
+        assert_eq!(stats.last_error_kind, None);
+        assert_eq!(stats.last_tested_at, Some(4));
+    }
+
+    #[test]
+    fn keeps_the_last_error_kind() {
+        let stats = aggregate(
+            "endpoint".to_string(),
+            "icmp".to_string(),
+            &[sample(1, Some(20)), sample(2, None)],
+        );
+        assert_eq!(stats.last_error_kind.as_deref(), Some("timeout"));
+        assert_eq!(stats.jitter, None);
+        assert_eq!(stats.median, Some(20));
+    }
+}
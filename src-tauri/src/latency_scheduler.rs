@@ -0,0 +1,201 @@
+//! Background latency refresh.
+//!
+//! Groups with a `LatencyRefreshInterval` (in minutes) are re-tested on that
+//! schedule so the tray labels stay current without anyone clicking
+//! "connection-test". Runs are skipped while a test of the same group is
+//! still in progress, and the whole schedule pauses on battery power or a
+//! metered connection where the platform lets us tell. Those checks read
+//! sysfs or run `pmset`/`busctl`, so they run on the blocking pool.
+
+use log::{info, warn};
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+use sqlx::Row;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager};
+
+use crate::latency;
+use crate::sys_tray::SystemTrayManager;
+use crate::utils;
+
+const TICK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Whether the machine runs on battery. `false` where it cannot be detected.
+#[cfg(target_os = "linux")]
+fn on_battery() -> bool {
+    let Ok(supplies) = std::fs::read_dir("/sys/class/power_supply") else {
+        return false;
+    };
+    let mut has_battery = false;
+    for supply in supplies.flatten() {
+        let read = |name: &str| {
+            std::fs::read_to_string(supply.path().join(name))
+                .map(|value| value.trim().to_string())
+                .unwrap_or_default()
+        };
+        match read("type").as_str() {
+            // Any connected charger means we are not on battery
+            "Mains" | "USB" if read("online") == "1" => return false,
+            // Peripherals such as mice report batteries too
+            "Battery" if read("scope") != "Device" => has_battery = true,
+            _ => {}
+        }
+    }
+    has_battery
+}
+
+#[cfg(target_os = "macos")]
+fn on_battery() -> bool {
+    std::process::Command::new("pmset")
+        .args(["-g", "batt"])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).contains("'Battery Power'"))
+        .unwrap_or(false)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn on_battery() -> bool {
+    false
+}
+
+/// Whether NetworkManager considers the connection metered. `false` where it
+/// cannot be detected.
+#[cfg(target_os = "linux")]
+fn on_metered_connection() -> bool {
+    let output = std::process::Command::new("busctl")
+        .args([
+            "get-property",
+            "org.freedesktop.NetworkManager",
+            "/org/freedesktop/NetworkManager",
+            "org.freedesktop.NetworkManager",
+            "Metered",
+        ])
+        .output();
+    match output {
+        // Prints `u <NMMetered>`: 1 is yes, 3 is a guessed yes
+        Ok(output) if output.status.success() => matches!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            "u 1" | "u 3"
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn on_metered_connection() -> bool {
+    false
+}
+
+/// Groups of the logged-in user that have a refresh interval.
+async fn scheduled_groups(pool: &SqlitePool) -> Result<Vec<(String, String, Duration)>, String> {
+    let rows = sqlx::query(
+        "SELECT g.GroupID, g.UserID, g.LatencyRefreshInterval
+         FROM EndpointsGroups g
+         JOIN AppStatus s ON s.UserID = g.UserID
+         WHERE s.LoginState = 1 AND g.LatencyRefreshInterval > 0",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to fetch scheduled groups: {}", e))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let minutes: i64 = row.get("LatencyRefreshInterval");
+            (
+                row.get("GroupID"),
+                row.get("UserID"),
+                Duration::from_secs(minutes as u64 * 60),
+            )
+        })
+        .collect())
+}
+
+/// Starts the scheduler for the lifetime of the app.
+pub fn start(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let database_path = utils::get_database_path(&app).to_string_lossy().to_string();
+        let database_url = format!("sqlite://{}", database_path);
+        let pool = match SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(&database_url)
+            .await
+        {
+            Ok(pool) => pool,
+            Err(e) => {
+                warn!("Failed to start latency scheduler: {}", e);
+                return;
+            }
+        };
+
+        // Counted from startup, a fresh start does not test everything at once
+        let mut last_runs: HashMap<String, Instant> = HashMap::new();
+        let mut paused = false;
+        let mut ticker = tokio::time::interval(TICK_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+
+            let groups = match scheduled_groups(&pool).await {
+                Ok(groups) => groups,
+                Err(e) => {
+                    warn!("{}", e);
+                    continue;
+                }
+            };
+            if groups.is_empty() {
+                continue;
+            }
+
+            let should_pause =
+                tokio::task::spawn_blocking(|| on_battery() || on_metered_connection())
+                    .await
+                    .unwrap_or(false);
+            if should_pause != paused {
+                paused = should_pause;
+                info!(
+                    "Scheduled latency tests {}",
+                    if paused {
+                        "paused on battery or metered connection"
+                    } else {
+                        "resumed"
+                    }
+                );
+            }
+            if paused {
+                continue;
+            }
+
+            for (group_id, user_id, interval) in groups {
+                let last_run = *last_runs
+                    .entry(group_id.clone())
+                    .or_insert_with(Instant::now);
+                if last_run.elapsed() < interval {
+                    continue;
+                }
+                last_runs.insert(group_id.clone(), Instant::now());
+
+                let app = app.clone();
+                tauri::async_runtime::spawn(async move {
+                    match latency::test_group_if_idle(&app, &group_id, &user_id).await {
+                        Ok(true) => info!("Scheduled latency test of group {} finished", group_id),
+                        Ok(false) => {
+                            info!(
+                                "Skipped scheduled latency test of group {}, a test is already running",
+                                group_id
+                            );
+                            return;
+                        }
+                        Err(e) => {
+                            warn!("Scheduled latency test of group {} failed: {}", group_id, e)
+                        }
+                    }
+                    if let Some(tray_manager) = app.try_state::<SystemTrayManager>() {
+                        tray_manager.update_menu(&app, user_id).await;
+                    }
+                });
+            }
+        }
+    });
+}
@@ -7,11 +7,31 @@
 // #[macro_use]
 extern crate rust_i18n;
 
+mod access_log;
+mod clash;
 mod commands;
+mod dns_leak;
+mod failover;
+mod latency;
+mod latency_history;
+mod latency_scheduler;
 mod migrations;
+mod ping;
+mod port_check;
 mod proxy;
+mod proxy_check;
+mod qr_code;
+mod service_state;
+mod share_link;
+mod share_server;
+mod sing_box;
+mod sip008;
+mod subscription;
+mod subscription_rules;
 mod sys_tray;
 mod telemetry;
+mod throughput;
+mod traffic;
 mod utils;
 mod v2ray_core;
 
@@ -64,6 +84,15 @@ pub fn run() {
         .manage(Mutex::new(proxy::PacServerShutdownHandle {
             shutdown_tx: None,
         }))
+        .manage(Mutex::new(traffic::TrafficCollectorHandle {
+            shutdown_tx: None,
+        }))
+        .manage(access_log::AccessLogState::new())
+        .manage(service_state::ServiceStateMachine::new())
+        .manage(latency::LatencyTestState::new())
+        .manage(throughput::ThroughputTestState::new())
+        .manage(failover::HealthCheckerState::new())
+        .manage(share_server::ShareServerState::new())
         .plugin(tauri_plugin_sql::Builder::new().build())
         .plugin(
             tauri_plugin_sql::Builder::default()
@@ -98,11 +127,35 @@ pub fn run() {
             v2ray_core::stop_daemon,
             v2ray_core::stop_v2ray_daemon,
             v2ray_core::check_daemon_status,
+            v2ray_core::switch_endpoint,
             proxy::setup_pac_proxy,
             proxy::unset_pac_proxy,
             proxy::setup_global_proxy,
             proxy::unset_global_proxy,
             sys_tray::tray_update,
+            traffic::get_traffic_stats,
+            access_log::query_access_log,
+            access_log::get_access_log_domains,
+            access_log::clear_access_log,
+            service_state::get_service_state,
+            port_check::check_inbound_ports,
+            port_check::reassign_inbound_port,
+            latency::cancel_latency_test,
+            latency_history::get_latency_stats,
+            throughput::test_endpoints_throughput,
+            throughput::cancel_throughput_test,
+            failover::get_endpoint_switches,
+            dns_leak::run_dns_leak_test,
+            share_link::import_share_links,
+            share_link::share_endpoint,
+            share_link::import_local_links,
+            qr_code::scan_qr_codes,
+            subscription::update_subscription,
+            subscription::update_all_subscriptions,
+            subscription::get_subscription_usage,
+            share_server::start_share_server,
+            share_server::stop_share_server,
+            share_server::get_share_server_status,
         ])
         .setup({
             commands::clear_v2ray_core_service();
@@ -160,6 +213,9 @@ pub fn run() {
                     
                     commands::reset_proxy_v2ray_status(app.app_handle()).await;
                     app.manage(sys_tray::init_tray(app.app_handle().clone()).await.unwrap());
+                    latency_scheduler::start(app.app_handle());
+                    subscription::start(app.app_handle());
+                    share_server::start(app.app_handle());
                     
                     // Start daily summary task now that async runtime is available
                     if telemetry::is_initialized() {
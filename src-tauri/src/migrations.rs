@@ -22,5 +22,126 @@ pub fn get_migrations() -> Vec<Migration> {
         description: "create subscriptions table and add salt field in user table",
         sql: MIGRATION_2_SQL,
         kind: MigrationKind::Up,
+    },
+    Migration {
+        version: 3,
+        description: "create traffic stats table",
+        sql: include_str!("../sql/create_traffic_stats_table.sql"),
+        kind: MigrationKind::Up,
+    },
+    Migration {
+        version: 4,
+        description: "add tray speed interval to app settings",
+        sql: include_str!("../sql/add_tray_speed_interval.sql"),
+        kind: MigrationKind::Up,
+    },
+    Migration {
+        version: 5,
+        description: "add service state to app status",
+        sql: include_str!("../sql/add_service_state.sql"),
+        kind: MigrationKind::Up,
+    },
+    Migration {
+        version: 6,
+        description: "add latency test concurrency to app settings",
+        sql: include_str!("../sql/add_latency_test_concurrency.sql"),
+        kind: MigrationKind::Up,
+    },
+    Migration {
+        version: 7,
+        description: "create latency samples table",
+        sql: include_str!("../sql/create_latency_samples_table.sql"),
+        kind: MigrationKind::Up,
+    },
+    Migration {
+        version: 8,
+        description: "add throughput test results and settings",
+        sql: include_str!("../sql/add_throughput_test.sql"),
+        kind: MigrationKind::Up,
+    },
+    Migration {
+        version: 9,
+        description: "add failover policy, health check settings and switch history",
+        sql: include_str!("../sql/add_failover.sql"),
+        kind: MigrationKind::Up,
+    },
+    Migration {
+        version: 10,
+        description: "add latency refresh interval to endpoint groups",
+        sql: include_str!("../sql/add_latency_refresh_interval.sql"),
+        kind: MigrationKind::Up,
+    },
+    Migration {
+        version: 11,
+        description: "add ip echo services to app settings",
+        sql: include_str!("../sql/add_ip_echo_services.sql"),
+        kind: MigrationKind::Up,
+    },
+    Migration {
+        version: 12,
+        description: "add dns leak test service to app settings",
+        sql: include_str!("../sql/add_dns_leak_test_service.sql"),
+        kind: MigrationKind::Up,
+    },
+    Migration {
+        version: 13,
+        description: "create vless servers table",
+        sql: include_str!("../sql/create_vless_servers_table.sql"),
+        kind: MigrationKind::Up,
+    },
+    Migration {
+        version: 14,
+        description: "add update settings to subscriptions",
+        sql: include_str!("../sql/add_subscription_updates.sql"),
+        kind: MigrationKind::Up,
+    },
+    Migration {
+        version: 15,
+        description: "add subscription userinfo",
+        sql: include_str!("../sql/add_subscription_userinfo.sql"),
+        kind: MigrationKind::Up,
+    },
+    Migration {
+        version: 16,
+        description: "add fetch options to subscriptions",
+        sql: include_str!("../sql/add_subscription_fetch_options.sql"),
+        kind: MigrationKind::Up,
+    },
+    Migration {
+        version: 17,
+        description: "add filter rules to subscriptions",
+        sql: include_str!("../sql/add_subscription_rules.sql"),
+        kind: MigrationKind::Up,
+    },
+    Migration {
+        version: 18,
+        description: "add share server settings",
+        sql: include_str!("../sql/add_share_server.sql"),
+        kind: MigrationKind::Up,
     }]
 }
+
+/// An in-memory database with every migration applied, for tests.
+#[cfg(test)]
+pub async fn memory_pool() -> sqlx::SqlitePool {
+    migrated_pool("sqlite::memory:").await
+}
+
+/// The database at `database_url` with every migration applied, for tests.
+#[cfg(test)]
+pub async fn migrated_pool(database_url: &str) -> sqlx::SqlitePool {
+    // One connection, since every connection opens its own in-memory database
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect(database_url)
+        .await
+        .unwrap();
+    // Each in a transaction like the migrator runs them, which migration 2
+    // relies on: it commits the transaction itself
+    for migration in get_migrations() {
+        let mut tx = pool.begin().await.unwrap();
+        sqlx::raw_sql(migration.sql).execute(&mut *tx).await.unwrap();
+        let _ = tx.commit().await;
+    }
+    pool
+}
@@ -0,0 +1,244 @@
+//! ICMP echo without root.
+//!
+//! Linux and macOS hand out ICMP sockets of type `SOCK_DGRAM` to regular
+//! users; on Linux only to groups within `net.ipv4.ping_group_range`. The
+//! kernel fills in the identifier and filters replies, so a plain send and
+//! receive is enough. Where the socket cannot be opened, [`availability`]
+//! explains why and callers fall back to a TCP handshake.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::latency::LatencyError;
+use crate::latency_history::ErrorKind as LatencyErrorKind;
+
+#[cfg(unix)]
+use socket2::{Domain, Protocol, Socket, Type};
+#[cfg(unix)]
+use std::io::ErrorKind;
+#[cfg(unix)]
+use std::io::Read;
+#[cfg(unix)]
+use std::time::Instant;
+
+#[cfg(unix)]
+const ECHO_REQUEST_V4: u8 = 8;
+#[cfg(unix)]
+const ECHO_REPLY_V4: u8 = 0;
+#[cfg(unix)]
+const ECHO_REQUEST_V6: u8 = 128;
+#[cfg(unix)]
+const ECHO_REPLY_V6: u8 = 129;
+#[cfg(unix)]
+const PAYLOAD: &[u8] = b"v2rayx-ping";
+
+pub enum PingError {
+    /// No ICMP socket could be opened, the reason is meant for the user
+    Unavailable(String),
+    Failed(LatencyError),
+}
+
+#[cfg(unix)]
+fn open_socket(ipv6: bool) -> std::io::Result<Socket> {
+    if ipv6 {
+        Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::ICMPV6))
+    } else {
+        Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::ICMPV4))
+    }
+}
+
+#[cfg(unix)]
+fn explain(error: std::io::Error) -> String {
+    match error.kind() {
+        ErrorKind::PermissionDenied => {
+            if cfg!(target_os = "linux") {
+                "Unprivileged ICMP is not permitted, net.ipv4.ping_group_range does not include this user's group".to_string()
+            } else {
+                "Unprivileged ICMP is not permitted on this system".to_string()
+            }
+        }
+        _ => format!("ICMP sockets are unavailable: {}", error),
+    }
+}
+
+/// Whether an unprivileged IPv4 ICMP socket can be opened, with the reason
+/// when it cannot.
+#[cfg(unix)]
+pub fn availability() -> Result<(), String> {
+    open_socket(false).map(|_| ()).map_err(explain)
+}
+
+#[cfg(not(unix))]
+pub fn availability() -> Result<(), String> {
+    Err("ICMP ping is not supported on this platform".to_string())
+}
+
+#[cfg(unix)]
+fn checksum(packet: &[u8]) -> u16 {
+    let mut sum: u32 = packet
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(unix)]
+fn ping_blocking(addr: SocketAddr, sequence: u16, timeout: Duration) -> Result<i64, PingError> {
+    let ipv6 = addr.is_ipv6();
+    let socket = open_socket(ipv6).map_err(|e| PingError::Unavailable(explain(e)))?;
+    echo(&socket, addr, sequence, timeout).map_err(PingError::Failed)
+}
+
+#[cfg(unix)]
+fn echo(
+    socket: &Socket,
+    addr: SocketAddr,
+    sequence: u16,
+    timeout: Duration,
+) -> Result<i64, LatencyError> {
+    let failed = |message| LatencyError::new(LatencyErrorKind::Other, message);
+    let timed_out = || {
+        LatencyError::new(
+            LatencyErrorKind::Timeout,
+            format!("ICMP echo to {} timed out", addr.ip()),
+        )
+    };
+    let ipv6 = addr.is_ipv6();
+    socket
+        .connect(&addr.into())
+        .map_err(|e| failed(format!("ICMP connect to {} failed: {}", addr.ip(), e)))?;
+
+    let mut packet = vec![
+        if ipv6 {
+            ECHO_REQUEST_V6
+        } else {
+            ECHO_REQUEST_V4
+        },
+        0,
+        0,
+        0,
+        // The identifier is replaced by the kernel
+        0,
+        0,
+    ];
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend_from_slice(PAYLOAD);
+    // The kernel computes the ICMPv6 checksum itself
+    if !ipv6 {
+        let sum = checksum(&packet);
+        packet[2..4].copy_from_slice(&sum.to_be_bytes());
+    }
+
+    let start = Instant::now();
+    socket
+        .send(&packet)
+        .map_err(|e| failed(format!("ICMP echo to {} failed: {}", addr.ip(), e)))?;
+
+    let mut reader = socket;
+    let mut buffer = [0u8; 1024];
+    loop {
+        let remaining = timeout.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            return Err(timed_out());
+        }
+        socket
+            .set_read_timeout(Some(remaining))
+            .map_err(|e| failed(format!("Failed to set ICMP timeout: {}", e)))?;
+        let received = match reader.read(&mut buffer) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err(timed_out());
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(failed(format!("ICMP echo to {} failed: {}", addr.ip(), e))),
+        };
+        let elapsed = start.elapsed().as_millis() as i64;
+
+        // macOS keeps the IPv4 header on datagram ICMP sockets, Linux strips it
+        let reply = match buffer[..received].first() {
+            Some(first) if !ipv6 && first >> 4 == 4 => {
+                let header_length = ((first & 0x0f) as usize) * 4;
+                buffer[..received].get(header_length..).unwrap_or_default()
+            }
+            _ => &buffer[..received],
+        };
+        if reply.len() < 8 {
+            continue;
+        }
+        let expected = if ipv6 { ECHO_REPLY_V6 } else { ECHO_REPLY_V4 };
+        if reply[0] == expected && reply[6..8] == sequence.to_be_bytes() {
+            return Ok(elapsed);
+        }
+        // Anything else is a stale or unrelated reply, keep waiting
+    }
+}
+
+/// Resolves `address` and sends a single ICMP echo to its first address.
+/// Returns the round trip in milliseconds.
+pub async fn icmp_ping_latency(
+    address: &str,
+    sequence: u16,
+    timeout: Duration,
+) -> Result<i64, PingError> {
+    let unresolved = |message| PingError::Failed(LatencyError::new(LatencyErrorKind::Dns, message));
+    let addr: SocketAddr = tokio::time::timeout(timeout, tokio::net::lookup_host((address, 0)))
+        .await
+        .map_err(|_| unresolved(format!("Address resolution timed out: {}", address)))?
+        .map_err(|e| unresolved(format!("Address resolution failed: {}", e)))?
+        .next()
+        .ok_or_else(|| unresolved(format!("Could not resolve address: {}", address)))?;
+
+    #[cfg(unix)]
+    {
+        tokio::task::spawn_blocking(move || ping_blocking(addr, sequence, timeout))
+            .await
+            .map_err(|e| {
+                PingError::Failed(LatencyError::new(
+                    LatencyErrorKind::Other,
+                    format!("ICMP ping task failed: {}", e),
+                ))
+            })?
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (addr, sequence);
+        Err(PingError::Unavailable(
+            availability().err().unwrap_or_default(),
+        ))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    // Echo request of Windows' ping.exe, identifier 1 and sequence 1
+    const WINDOWS_ECHO: [u8; 40] =
+        *b"\x08\x00\x4d\x5a\x00\x01\x00\x01abcdefghijklmnopqrstuvwabcdefghi";
+
+    #[test]
+    fn matches_a_captured_echo_request() {
+        let mut packet = WINDOWS_ECHO;
+        packet[2..4].fill(0);
+        assert_eq!(checksum(&packet), 0x4d5a);
+    }
+
+    #[test]
+    fn a_valid_packet_sums_to_zero() {
+        assert_eq!(checksum(&WINDOWS_ECHO), 0);
+    }
+
+    #[test]
+    fn pads_odd_lengths() {
+        // The payload has an odd length, like the one `echo` sends
+        let mut packet = vec![ECHO_REQUEST_V4, 0, 0, 0, 0, 0, 0x12, 0x34];
+        packet.extend_from_slice(PAYLOAD);
+        assert_eq!(packet.len() % 2, 1);
+        let sum = checksum(&packet);
+        packet[2..4].copy_from_slice(&sum.to_be_bytes());
+        assert_eq!(checksum(&packet), 0);
+    }
+}
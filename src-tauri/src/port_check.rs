@@ -0,0 +1,334 @@
+//! Inbound port conflict detection.
+//!
+//! The core exits right away when one of its inbound ports is taken, so the
+//! ports are probed before every start. Conflicts are reported together with
+//! the process holding the port where the platform lets us find it, and an
+//! inbound can be moved to a free port picked by `portpicker`.
+
+use log::{info, warn};
+use serde::Serialize;
+use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::Row;
+use std::collections::HashSet;
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Manager};
+
+use crate::proxy;
+use crate::utils;
+use crate::v2ray_core::{self, DaemonState};
+
+pub const PORT_CONFLICT_EVENT: &str = "inbound-port-conflict";
+
+const MAX_PICK_ATTEMPTS: usize = 20;
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PortOwner {
+    pub pid: u32,
+    pub name: String,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PortConflict {
+    pub tag: String,
+    pub protocol: String,
+    pub listen: String,
+    pub port: u16,
+    pub reason: String,
+    pub owner: Option<PortOwner>,
+}
+
+/// Summarises conflicts for logs and error messages.
+pub fn describe(conflicts: &[PortConflict]) -> String {
+    conflicts
+        .iter()
+        .map(|conflict| match &conflict.owner {
+            Some(owner) => format!(
+                "{} port {} is used by {} (pid {})",
+                conflict.tag, conflict.port, owner.name, owner.pid
+            ),
+            None => format!(
+                "{} port {}: {}",
+                conflict.tag, conflict.port, conflict.reason
+            ),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn probe(tag: &str, protocol: &str, listen: &str, port: u16) -> Option<PortConflict> {
+    let host = if listen.is_empty() { "0.0.0.0" } else { listen };
+    match TcpListener::bind((host, port)) {
+        // The listener is dropped right away, freeing the port for the core
+        Ok(_) => None,
+        Err(e) => Some(PortConflict {
+            tag: tag.to_string(),
+            protocol: protocol.to_string(),
+            listen: listen.to_string(),
+            port,
+            reason: e.to_string(),
+            owner: find_port_owner(port),
+        }),
+    }
+}
+
+/// Probes the inbounds of a generated config file.
+pub fn probe_config_inbounds(config_path: &Path) -> Vec<PortConflict> {
+    let config: serde_json::Value = match std::fs::read_to_string(config_path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+    {
+        Some(config) => config,
+        None => return Vec::new(),
+    };
+
+    config["inbounds"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|inbound| {
+            let port = inbound["port"].as_u64()? as u16;
+            probe(
+                inbound["tag"].as_str().unwrap_or_default(),
+                inbound["protocol"].as_str().unwrap_or_default(),
+                inbound["listen"].as_str().unwrap_or_default(),
+                port,
+            )
+        })
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+fn find_port_owner(port: u16) -> Option<PortOwner> {
+    use std::fs;
+
+    // Find the socket inodes listening on the port
+    let mut sockets = Vec::new();
+    for table in ["/proc/net/tcp", "/proc/net/tcp6"] {
+        let Ok(content) = fs::read_to_string(table) else {
+            continue;
+        };
+        for line in content.lines().skip(1) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 10 {
+                continue;
+            }
+            let local_port = fields[1]
+                .rsplit(':')
+                .next()
+                .and_then(|port| u16::from_str_radix(port, 16).ok());
+            // 0A is TCP_LISTEN
+            if local_port == Some(port) && fields[3] == "0A" {
+                sockets.push(format!("socket:[{}]", fields[9]));
+            }
+        }
+    }
+    if sockets.is_empty() {
+        return None;
+    }
+
+    // Then the process holding one of them. File descriptors of other
+    // users' processes are unreadable, those owners stay unknown.
+    for entry in fs::read_dir("/proc").ok()?.flatten() {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        else {
+            continue;
+        };
+        let Ok(fds) = fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            let Ok(target) = fs::read_link(fd.path()) else {
+                continue;
+            };
+            if sockets
+                .iter()
+                .any(|socket| target.as_os_str() == socket.as_str())
+            {
+                let name = fs::read_to_string(entry.path().join("comm"))
+                    .map(|name| name.trim().to_string())
+                    .unwrap_or_default();
+                return Some(PortOwner { pid, name });
+            }
+        }
+    }
+    None
+}
+
+#[cfg(target_os = "macos")]
+fn find_port_owner(port: u16) -> Option<PortOwner> {
+    let output = std::process::Command::new("lsof")
+        .args(["-nP", &format!("-iTCP:{}", port), "-sTCP:LISTEN", "-Fpc"])
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let pid = stdout
+        .lines()
+        .find_map(|line| line.strip_prefix('p'))?
+        .parse()
+        .ok()?;
+    let name = stdout
+        .lines()
+        .find_map(|line| line.strip_prefix('c'))
+        .unwrap_or_default()
+        .to_string();
+    Some(PortOwner { pid, name })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn find_port_owner(_port: u16) -> Option<PortOwner> {
+    None
+}
+
+/// Probes every inbound port of `user_id`. While the daemon is running its
+/// own ports show up as taken by the core.
+#[tauri::command]
+pub async fn check_inbound_ports(
+    app: AppHandle,
+    user_id: String,
+) -> Result<Vec<PortConflict>, String> {
+    let database_path = utils::get_database_path(&app).to_string_lossy().to_string();
+    let database_url = format!("sqlite://{}", database_path);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url)
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+
+    let rows = sqlx::query("SELECT Tag, Protocol, Listen, Port FROM Inbounds WHERE UserID = ?")
+        .bind(&user_id)
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| format!("Failed to fetch inbounds: {}", e))?;
+
+    Ok(rows
+        .iter()
+        .filter_map(|row| {
+            let port: i64 = row.get("Port");
+            probe(
+                &row.get::<String, _>("Tag"),
+                &row.get::<String, _>("Protocol"),
+                &row.get::<String, _>("Listen"),
+                port as u16,
+            )
+        })
+        .collect())
+}
+
+/// Moves the inbound `tag` to a free port that no other inbound of the user
+/// claims, then regenerates the config and the system proxy settings that
+/// point at it. Returns the new port.
+#[tauri::command]
+pub async fn reassign_inbound_port(
+    app: AppHandle,
+    user_id: String,
+    tag: String,
+) -> Result<u16, String> {
+    let database_path = utils::get_database_path(&app).to_string_lossy().to_string();
+    let database_url = format!("sqlite://{}", database_path);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url)
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+
+    let used_ports: HashSet<u16> =
+        sqlx::query_scalar::<_, i64>("SELECT Port FROM Inbounds WHERE UserID = ?")
+            .bind(&user_id)
+            .fetch_all(&pool)
+            .await
+            .map_err(|e| format!("Failed to fetch inbound ports: {}", e))?
+            .into_iter()
+            .map(|port| port as u16)
+            .collect();
+
+    // Keep the Inbounds_userid_port unique constraint intact
+    let new_port = (0..MAX_PICK_ATTEMPTS)
+        .filter_map(|_| portpicker::pick_unused_port())
+        .find(|port| !used_ports.contains(port))
+        .ok_or("Could not find a free TCP port")?;
+
+    let result = sqlx::query("UPDATE Inbounds SET Port = ? WHERE UserID = ? AND Tag = ?")
+        .bind(new_port as i64)
+        .bind(&user_id)
+        .bind(&tag)
+        .execute(&pool)
+        .await
+        .map_err(|e| format!("Failed to update inbound port: {}", e))?;
+    if result.rows_affected() == 0 {
+        return Err(format!("Inbound '{}' not found", tag));
+    }
+    info!("Moved inbound '{}' to port {}", tag, new_port);
+
+    let active_endpoint: Option<String> =
+        sqlx::query_scalar("SELECT EndpointID FROM Endpoints WHERE Active = 1 LIMIT 1")
+            .fetch_optional(&pool)
+            .await
+            .map_err(|e| format!("Failed to fetch active endpoint: {}", e))?;
+    if let Some(endpoint_id) = active_endpoint {
+        if !v2ray_core::inject_config(app.clone(), endpoint_id, user_id.clone()).await {
+            return Err("Failed to regenerate the config".to_string());
+        }
+
+        // Inbound ports are only read at startup
+        let daemon_state = app.state::<Arc<Mutex<DaemonState>>>();
+        let is_running = daemon_state.lock().unwrap().child.is_some();
+        if is_running {
+            let window = app
+                .get_webview_window("main")
+                .ok_or("Main window not found")?;
+            v2ray_core::stop_daemon(daemon_state.clone(), window.clone()).await?;
+            v2ray_core::start_daemon(daemon_state, window).await?;
+        }
+    }
+
+    if let Err(e) = proxy::reapply_system_proxy(&app, &user_id).await {
+        warn!("Failed to point the system proxy at the new port: {}", e);
+    }
+
+    Ok(new_port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_a_port_in_use() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let conflict = probe("socks-inbound", "socks", "127.0.0.1", port).expect("a conflict");
+        assert_eq!(conflict.tag, "socks-inbound");
+        assert_eq!(conflict.port, port);
+        assert!(!conflict.reason.is_empty());
+        #[cfg(target_os = "linux")]
+        assert_eq!(
+            conflict.owner.map(|owner| owner.pid),
+            Some(std::process::id())
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn finds_the_owner_of_a_listening_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let owner = find_port_owner(port).expect("an owner");
+        assert_eq!(owner.pid, std::process::id());
+        assert!(!owner.name.is_empty());
+    }
+
+    #[test]
+    fn accepts_a_free_port() {
+        let port = portpicker::pick_unused_port().unwrap();
+        assert!(probe("socks-inbound", "socks", "127.0.0.1", port).is_none());
+    }
+}
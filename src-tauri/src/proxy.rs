@@ -4,6 +4,7 @@
 )]
 
 use log::{error, info};
+use sqlx::sqlite::SqlitePoolOptions;
 use std::fs;
 use std::io;
 use std::sync::Arc;
@@ -710,3 +711,74 @@ pub fn unset_global_proxy() -> Result<String, String> {
 
     Ok("Global proxy unset successfully".into())
 }
+
+/// Re-applies the system proxy of the user's current `ProxyMode` so it
+/// follows inbound port changes. Manual mode leaves the system untouched.
+pub async fn reapply_system_proxy(app_handle: &AppHandle, user_id: &str) -> Result<(), String> {
+    let database_path = crate::utils::get_database_path(app_handle)
+        .to_string_lossy()
+        .to_string();
+    let database_url = format!("sqlite://{}", database_path);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url)
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+
+    let (proxy_mode, custom_rules, bypass_domains, http_listen, http_port, socks_port): (
+        String,
+        String,
+        String,
+        Option<String>,
+        Option<i64>,
+        Option<i64>,
+    ) = sqlx::query_as(
+        "SELECT
+            a.ProxyMode,
+            a.PAC,
+            a.BypassDomains,
+            (SELECT i.Listen FROM Inbounds i WHERE i.UserID = a.UserID AND i.Tag = 'http-inbound'),
+            (SELECT i.Port FROM Inbounds i WHERE i.UserID = a.UserID AND i.Tag = 'http-inbound'),
+            (SELECT i.Port FROM Inbounds i WHERE i.UserID = a.UserID AND i.Tag = 'socks-inbound')
+        FROM AppSettings a
+        WHERE a.UserID = ?",
+    )
+    .bind(user_id)
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| format!("Failed to fetch proxy settings: {}", e))?;
+
+    let http_port = http_port.unwrap_or_default() as u16;
+    let socks_port = socks_port.unwrap_or_default() as u16;
+    match proxy_mode.as_str() {
+        "pac" => {
+            unset_pac_proxy(app_handle.state::<Mutex<PacServerShutdownHandle>>())?;
+            setup_pac_proxy(
+                app_handle.clone(),
+                custom_rules,
+                http_port,
+                socks_port,
+                app_handle.state::<Mutex<PacServerShutdownHandle>>(),
+            )
+            .await?;
+        }
+        "global" => {
+            let bypass_domains: Vec<String> =
+                serde_json::from_str::<serde_json::Value>(&bypass_domains)
+                    .ok()
+                    .and_then(|value| value.get("bypass").and_then(|v| v.as_array()).cloned())
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                    .collect();
+            setup_global_proxy(
+                http_listen.unwrap_or_else(|| "127.0.0.1".to_string()),
+                http_port,
+                socks_port,
+                bypass_domains,
+            )?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
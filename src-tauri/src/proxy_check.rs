@@ -0,0 +1,309 @@
+//! Verification that traffic actually leaves through the active endpoint.
+//!
+//! The exit IP is fetched from the first working IP echo service of the
+//! user's `IpEchoServices`, once through the socks inbound and once
+//! directly. The proxied exit IP is then compared with the endpoint address,
+//! resolved if it is a hostname. Endpoints reached through a CDN (ws, grpc or
+//! h2 fronted by another host) exit from addresses unrelated to their own,
+//! so for those, and for servers that exit elsewhere, it is enough that the
+//! proxied IP differs from the direct one.
+
+use log::{info, warn};
+use serde::Serialize;
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+use sqlx::Row;
+use std::net::IpAddr;
+use std::time::Duration;
+use tauri::AppHandle;
+
+use crate::service_state;
+use crate::utils;
+
+const ECHO_TIMEOUT: Duration = Duration::from_secs(5);
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_ECHO_SERVICES: [&str; 3] = [
+    "https://api64.ipify.org",
+    "https://icanhazip.com",
+    "https://ifconfig.me/ip",
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ProxyVerdict {
+    /// The exit IP is the endpoint address or one it resolves to
+    Matched,
+    /// The endpoint sits behind a CDN and traffic does leave through the proxy
+    Cdn,
+    /// Traffic leaves through the proxy, but from an address the endpoint
+    /// does not resolve to
+    Relayed,
+    /// The proxied and direct exit IPs are the same, traffic bypasses the proxy
+    Direct,
+    /// The exit IP does not match and the direct IP could not be fetched
+    Unknown,
+    NoActiveEndpoint,
+    NoAddress,
+}
+
+impl ProxyVerdict {
+    pub fn is_healthy(&self) -> bool {
+        matches!(
+            self,
+            ProxyVerdict::Matched | ProxyVerdict::Cdn | ProxyVerdict::Relayed
+        )
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProxyCheckResult {
+    pub verdict: ProxyVerdict,
+    pub healthy: bool,
+    pub socks_port: u16,
+    pub exit_ip: String,
+    pub direct_ip: Option<String>,
+    /// The echo service that answered through the proxy
+    pub echo_service: String,
+    pub endpoint_id: Option<String>,
+    pub protocol: Option<String>,
+    pub address: Option<String>,
+    pub resolved_addresses: Vec<String>,
+    /// The host the CDN is reached through, when the endpoint uses one
+    pub cdn_host: Option<String>,
+}
+
+struct ActiveEndpoint {
+    endpoint_id: String,
+    protocol: String,
+    address: Option<String>,
+    cdn_host: Option<String>,
+}
+
+/// Parses an echo response, mapping IPv4-mapped IPv6 addresses back to IPv4.
+fn parse_ip(text: &str) -> Option<IpAddr> {
+    text.trim()
+        .parse::<IpAddr>()
+        .ok()
+        .map(|ip| ip.to_canonical())
+}
+
+pub(crate) async fn echo_services(pool: &SqlitePool, user_id: &str) -> Vec<String> {
+    let configured: Option<String> =
+        sqlx::query_scalar("SELECT IpEchoServices FROM AppSettings WHERE UserID = ?")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await
+            .unwrap_or(None);
+    let services: Vec<String> = configured
+        .and_then(|services| serde_json::from_str(&services).ok())
+        .unwrap_or_default();
+    if services.is_empty() {
+        DEFAULT_ECHO_SERVICES
+            .iter()
+            .map(|service| service.to_string())
+            .collect()
+    } else {
+        services
+    }
+}
+
+/// Asks the echo services in order, returning the first IP and the service
+/// that gave it. Goes through the socks port when one is given.
+pub(crate) async fn fetch_ip(
+    services: &[String],
+    socks_port: Option<u16>,
+) -> Result<(IpAddr, String), String> {
+    let mut builder = reqwest::Client::builder().timeout(ECHO_TIMEOUT);
+    builder = match socks_port {
+        // socks5h lets the endpoint resolve the echo service too
+        Some(port) => builder.proxy(
+            reqwest::Proxy::all(format!("socks5h://127.0.0.1:{}", port))
+                .map_err(|e| format!("Failed to configure proxy: {}", e))?,
+        ),
+        None => builder.no_proxy(),
+    };
+    let client = builder
+        .build()
+        .map_err(|e| format!("Failed to build HTTP client: {}", e))?;
+
+    let mut errors = Vec::new();
+    for service in services {
+        let text = match client.get(service).send().await {
+            Ok(response) => response.text().await,
+            Err(e) => Err(e),
+        };
+        match text {
+            Ok(text) => match parse_ip(&text) {
+                Some(ip) => return Ok((ip, service.clone())),
+                None => errors.push(format!("{}: unexpected response", service)),
+            },
+            Err(e) => errors.push(format!("{}: {}", service, e)),
+        }
+    }
+    Err(format!(
+        "No IP echo service answered ({})",
+        errors.join(", ")
+    ))
+}
+
+async fn resolve(address: &str) -> Vec<IpAddr> {
+    if let Some(ip) = parse_ip(address.trim_start_matches('[').trim_end_matches(']')) {
+        return vec![ip];
+    }
+    match tokio::time::timeout(RESOLVE_TIMEOUT, tokio::net::lookup_host((address, 0))).await {
+        Ok(Ok(addrs)) => addrs.map(|addr| addr.ip().to_canonical()).collect(),
+        Ok(Err(e)) => {
+            warn!("Failed to resolve endpoint address {}: {}", address, e);
+            Vec::new()
+        }
+        Err(_) => {
+            warn!("Resolving endpoint address {} timed out", address);
+            Vec::new()
+        }
+    }
+}
+
+async fn active_endpoint(pool: &SqlitePool) -> Result<Option<ActiveEndpoint>, String> {
+    let row = sqlx::query(
+        "SELECT e.EndpointID, o.Protocol,
+         CASE o.Protocol
+            WHEN 'vmess' THEN (SELECT v.Address FROM VmessVnext v JOIN VmessUsers u ON v.VnextID = u.VnextID WHERE u.EndpointID = e.EndpointID)
+            WHEN 'shadowsocks' THEN (SELECT Address FROM Shadowsocks WHERE EndpointID = e.EndpointID)
+            WHEN 'trojan' THEN (SELECT Address FROM TrojanServers WHERE EndpointID = e.EndpointID)
+            WHEN 'vless' THEN (SELECT Address FROM VlessServers WHERE EndpointID = e.EndpointID)
+            WHEN 'hysteria2' THEN (SELECT Address FROM Hysteria2 WHERE EndpointID = e.EndpointID)
+            ELSE NULL
+         END AS Address,
+         s.Network,
+         (SELECT Host FROM WsSettings WHERE EndpointID = e.EndpointID) AS WsHost,
+         (SELECT Host FROM \"Http/2Settings\" WHERE EndpointID = e.EndpointID) AS H2Host,
+         (SELECT ServerName FROM TlsSettings WHERE EndpointID = e.EndpointID) AS ServerName
+         FROM Endpoints e
+         JOIN Outbounds o ON e.EndpointID = o.EndpointID
+         LEFT JOIN StreamSettings s ON s.EndpointID = e.EndpointID
+         WHERE e.Active = 1",
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("Failed to fetch active endpoint: {}", e))?;
+
+    Ok(row.map(|row| {
+        let address: Option<String> = row
+            .get::<Option<String>, _>("Address")
+            .filter(|address| !address.is_empty());
+        let network: Option<String> = row.get("Network");
+        // A host other than the address means the address is a CDN edge
+        let front_host = match network.as_deref() {
+            Some("ws") => row.get::<Option<String>, _>("WsHost"),
+            Some("h2" | "http") => row.get::<Option<String>, _>("H2Host"),
+            Some("grpc") => row.get::<Option<String>, _>("ServerName"),
+            _ => None,
+        };
+        let cdn_host = front_host
+            .map(|host| host.trim().to_string())
+            .filter(|host| !host.is_empty() && Some(host) != address.as_ref());
+        ActiveEndpoint {
+            endpoint_id: row.get("EndpointID"),
+            protocol: row.get("Protocol"),
+            address,
+            cdn_host,
+        }
+    }))
+}
+
+/// Checks where traffic through the socks inbound of `user_id` exits and
+/// reports the outcome to the service state.
+pub async fn check(app: &AppHandle, user_id: &str) -> Result<ProxyCheckResult, String> {
+    let database_path = utils::get_database_path(app).to_string_lossy().to_string();
+    let database_url = format!("sqlite://{}", database_path);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url)
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+
+    let socks_port: Option<i64> =
+        sqlx::query_scalar("SELECT Port FROM Inbounds WHERE UserID = ? AND Tag = 'socks-inbound'")
+            .bind(user_id)
+            .fetch_optional(&pool)
+            .await
+            .map_err(|e| format!("Failed to fetch socks port: {}", e))?
+            .flatten();
+    let socks_port = match socks_port {
+        Some(port) => port as u16,
+        None => return Err("No socks inbound found for this user".to_string()),
+    };
+
+    let services = echo_services(&pool, user_id).await;
+    let (proxied, direct) = tokio::join!(
+        fetch_ip(&services, Some(socks_port)),
+        fetch_ip(&services, None)
+    );
+    let (exit_ip, echo_service) = match proxied {
+        Ok(proxied) => proxied,
+        Err(e) => {
+            let reason = format!("Failed to get IP via SOCKS5 proxy: {}", e);
+            service_state::report_health(app, false, Some(reason.clone())).await;
+            return Err(reason);
+        }
+    };
+    let direct_ip = direct.ok().map(|(ip, _)| ip);
+
+    let endpoint = active_endpoint(&pool).await?;
+    let resolved = match endpoint
+        .as_ref()
+        .and_then(|endpoint| endpoint.address.as_deref())
+    {
+        Some(address) => resolve(address).await,
+        None => Vec::new(),
+    };
+
+    let verdict = match &endpoint {
+        None => ProxyVerdict::NoActiveEndpoint,
+        Some(endpoint) if endpoint.address.is_none() => ProxyVerdict::NoAddress,
+        Some(_) if resolved.contains(&exit_ip) => ProxyVerdict::Matched,
+        Some(endpoint) => match direct_ip {
+            Some(direct_ip) if direct_ip == exit_ip => ProxyVerdict::Direct,
+            Some(_) if endpoint.cdn_host.is_some() => ProxyVerdict::Cdn,
+            Some(_) => ProxyVerdict::Relayed,
+            None => ProxyVerdict::Unknown,
+        },
+    };
+    let healthy = verdict.is_healthy();
+
+    let reason = match verdict {
+        _ if healthy => None,
+        ProxyVerdict::NoActiveEndpoint => Some("No active endpoint".to_string()),
+        ProxyVerdict::NoAddress => Some("No address found for the active endpoint".to_string()),
+        ProxyVerdict::Direct => Some(format!(
+            "Traffic exits from the local IP {}, not through the proxy",
+            exit_ip
+        )),
+        _ => Some(format!(
+            "Proxy IP {} does not belong to the active endpoint",
+            exit_ip
+        )),
+    };
+    if let Some(reason) = &reason {
+        info!("Proxy check: {}", reason);
+    }
+    service_state::report_health(app, healthy, reason).await;
+
+    Ok(ProxyCheckResult {
+        verdict,
+        healthy,
+        socks_port,
+        exit_ip: exit_ip.to_string(),
+        direct_ip: direct_ip.map(|ip| ip.to_string()),
+        echo_service,
+        endpoint_id: endpoint
+            .as_ref()
+            .map(|endpoint| endpoint.endpoint_id.clone()),
+        protocol: endpoint.as_ref().map(|endpoint| endpoint.protocol.clone()),
+        address: endpoint
+            .as_ref()
+            .and_then(|endpoint| endpoint.address.clone()),
+        resolved_addresses: resolved.iter().map(|ip| ip.to_string()).collect(),
+        cdn_host: endpoint.and_then(|endpoint| endpoint.cdn_host),
+    })
+}
@@ -0,0 +1,173 @@
+//! QR codes for share links.
+//!
+//! Codes are rendered to a PNG data URL the webview can show directly, with
+//! the four module quiet zone scanners expect. Scanning captures every
+//! screen and decodes the codes natively, so it works while the main window
+//! is hidden and without shipping screenshots to the webview.
+
+use base64::{engine::general_purpose::STANDARD as BASE64_STANDARD, Engine};
+use image::{DynamicImage, GrayImage, ImageOutputFormat, Luma};
+use log::warn;
+use qrcode::{Color, QrCode};
+use screenshots::Screen;
+use serde::Serialize;
+use std::io::Cursor;
+
+use crate::share_link;
+
+const MODULE_PIXELS: u32 = 8;
+const QUIET_ZONE: u32 = 4;
+
+/// Renders `text` as a QR code, returned as a `data:image/png;base64,` URL.
+pub fn encode_png(text: &str) -> Result<String, String> {
+    let code =
+        QrCode::new(text.as_bytes()).map_err(|e| format!("Failed to encode QR code: {}", e))?;
+    let width = code.width() as u32;
+    let colors = code.to_colors();
+
+    let size = (width + QUIET_ZONE * 2) * MODULE_PIXELS;
+    let image = GrayImage::from_fn(size, size, |x, y| {
+        let (x, y) = (x / MODULE_PIXELS, y / MODULE_PIXELS);
+        let dark = (QUIET_ZONE..QUIET_ZONE + width).contains(&x)
+            && (QUIET_ZONE..QUIET_ZONE + width).contains(&y)
+            && colors[((y - QUIET_ZONE) * width + x - QUIET_ZONE) as usize] == Color::Dark;
+        Luma([if dark { 0 } else { 255 }])
+    });
+
+    let mut buffer = Vec::new();
+    DynamicImage::ImageLuma8(image)
+        .write_to(&mut Cursor::new(&mut buffer), ImageOutputFormat::Png)
+        .map_err(|e| format!("Failed to render QR code: {}", e))?;
+    Ok(format!(
+        "data:image/png;base64,{}",
+        BASE64_STANDARD.encode(&buffer)
+    ))
+}
+
+/// Bounding box of a code, in pixels of the screen capture.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Region {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScannedLink {
+    pub link: String,
+    pub remark: String,
+    pub protocol: String,
+    pub screen_id: u32,
+    /// Position of the screen in `Screen::all()`
+    pub screen_index: usize,
+    pub is_primary: bool,
+    pub region: Region,
+}
+
+/// Decodes every QR code in `image`.
+pub fn decode(image: &GrayImage) -> Vec<(Region, String)> {
+    let mut prepared = rqrr::PreparedImage::prepare_from_greyscale(
+        image.width() as usize,
+        image.height() as usize,
+        |x, y| image.get_pixel(x as u32, y as u32)[0],
+    );
+    prepared
+        .detect_grids()
+        .into_iter()
+        .filter_map(|grid| {
+            let xs = grid.bounds.iter().map(|point| point.x);
+            let ys = grid.bounds.iter().map(|point| point.y);
+            let (left, right) = (xs.clone().min()?, xs.max()?);
+            let (top, bottom) = (ys.clone().min()?, ys.max()?);
+            let region = Region {
+                x: left,
+                y: top,
+                width: (right - left) as u32,
+                height: (bottom - top) as u32,
+            };
+            match grid.decode() {
+                Ok((_, content)) => Some((region, content)),
+                Err(e) => {
+                    warn!("Failed to decode QR code at {},{}: {}", left, top, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Captures every screen and returns the share links found in QR codes.
+/// Codes holding anything other than a supported share link are skipped.
+pub fn scan_screens() -> Result<Vec<ScannedLink>, String> {
+    let screens = Screen::all().map_err(|e| format!("Failed to list screens: {}", e))?;
+    if screens.is_empty() {
+        return Err("No screen found".to_string());
+    }
+
+    let mut links = Vec::new();
+    for (screen_index, screen) in screens.iter().enumerate() {
+        let info = &screen.display_info;
+        let capture = match screen.capture() {
+            Ok(capture) => capture,
+            Err(e) => {
+                warn!("Failed to capture screen {}: {}", info.id, e);
+                continue;
+            }
+        };
+        let image = DynamicImage::ImageRgba8(capture).to_luma8();
+        for (region, content) in decode(&image) {
+            match share_link::parse(&content) {
+                Ok(share_link) => links.push(ScannedLink {
+                    link: share_link.link,
+                    remark: share_link.remark,
+                    protocol: share_link.outbound.protocol().to_string(),
+                    screen_id: info.id,
+                    screen_index,
+                    is_primary: info.is_primary,
+                    region,
+                }),
+                Err(e) => warn!("QR code on screen {} is not a share link: {}", info.id, e),
+            }
+        }
+    }
+    Ok(links)
+}
+
+/// Scans all screens for share link QR codes.
+#[tauri::command]
+pub async fn scan_qr_codes() -> Result<Vec<ScannedLink>, String> {
+    tauri::async_runtime::spawn_blocking(scan_screens)
+        .await
+        .map_err(|e| format!("Failed to scan screens: {}", e))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let link =
+            "trojan://secret@trojan.example.com:443?security=tls&sni=trojan.example.com#Tokyo%2001";
+        let data_url = encode_png(link).unwrap();
+        let png = BASE64_STANDARD
+            .decode(data_url.strip_prefix("data:image/png;base64,").unwrap())
+            .unwrap();
+        let image = image::load_from_memory(&png).unwrap().to_luma8();
+
+        let decoded = decode(&image);
+        assert_eq!(decoded.len(), 1);
+        let (region, content) = &decoded[0];
+        assert_eq!(content, link);
+        // The code without its quiet zone, give or take a module
+        let margin = (QUIET_ZONE * MODULE_PIXELS) as i32;
+        let size = image.width() - QUIET_ZONE * MODULE_PIXELS * 2;
+        assert!((region.x - margin).abs() <= MODULE_PIXELS as i32);
+        assert!((region.y - margin).abs() <= MODULE_PIXELS as i32);
+        assert!(region.width.abs_diff(size) <= MODULE_PIXELS);
+        assert!(region.height.abs_diff(size) <= MODULE_PIXELS);
+    }
+}
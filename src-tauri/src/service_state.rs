@@ -0,0 +1,268 @@
+//! Lifecycle state of the v2ray-core service.
+//!
+//! Every change goes through [`transition`], which validates the move, keeps
+//! `AppStatus.ServiceState` (and the legacy `ServiceRunningState` flag the
+//! frontend still reads) in sync, refreshes the tray and emits a single
+//! `service-state-changed` event.
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePoolOptions;
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::sys_tray::SystemTrayManager;
+use crate::utils;
+
+pub const SERVICE_STATE_EVENT: &str = "service-state-changed";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ServiceState {
+    Stopped,
+    Starting,
+    Running,
+    /// The core is up but the proxy does not route through the active endpoint
+    Degraded,
+    Stopping,
+    /// The core could not be started or exited on its own
+    Failed,
+}
+
+impl ServiceState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServiceState::Stopped => "stopped",
+            ServiceState::Starting => "starting",
+            ServiceState::Running => "running",
+            ServiceState::Degraded => "degraded",
+            ServiceState::Stopping => "stopping",
+            ServiceState::Failed => "failed",
+        }
+    }
+
+    /// Whether the core process is serving traffic.
+    pub fn is_active(&self) -> bool {
+        matches!(self, ServiceState::Running | ServiceState::Degraded)
+    }
+
+    pub fn can_transition_to(&self, next: ServiceState) -> bool {
+        use ServiceState::*;
+        matches!(
+            (self, next),
+            (Stopped, Starting)
+                | (Starting, Running | Failed | Stopping)
+                | (Running, Degraded | Stopping | Failed)
+                | (Degraded, Running | Stopping | Failed)
+                | (Stopping, Stopped | Failed)
+                | (Failed, Starting | Stopping | Stopped)
+        )
+    }
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceStateChange {
+    pub previous: ServiceState,
+    pub current: ServiceState,
+    pub reason: Option<String>,
+}
+
+pub struct ServiceStateMachine {
+    state: Mutex<ServiceState>,
+}
+
+impl ServiceStateMachine {
+    pub fn new() -> Self {
+        ServiceStateMachine {
+            state: Mutex::new(ServiceState::Stopped),
+        }
+    }
+
+    pub fn current(&self) -> ServiceState {
+        *self.state.lock().unwrap()
+    }
+}
+
+pub fn current(app: &AppHandle) -> ServiceState {
+    app.state::<ServiceStateMachine>().current()
+}
+
+/// Moves the service to `next`. Moving to the current state is a no-op;
+/// moves the state machine does not allow are rejected without side effects.
+pub async fn transition(
+    app: &AppHandle,
+    next: ServiceState,
+    reason: Option<String>,
+) -> Result<ServiceState, String> {
+    let previous = {
+        let machine = app.state::<ServiceStateMachine>();
+        let mut state = machine.state.lock().unwrap();
+        let previous = *state;
+        if previous == next {
+            return Ok(previous);
+        }
+        if !previous.can_transition_to(next) {
+            return Err(format!(
+                "Invalid service state transition: {} -> {}",
+                previous.as_str(),
+                next.as_str()
+            ));
+        }
+        *state = next;
+        previous
+    };
+    info!(
+        "Service state: {} -> {}{}",
+        previous.as_str(),
+        next.as_str(),
+        reason
+            .as_ref()
+            .map(|reason| format!(" ({})", reason))
+            .unwrap_or_default()
+    );
+
+    publish(app, previous, next, reason).await;
+    Ok(previous)
+}
+
+/// Forces the service to `Stopped`, used at startup when the persisted state
+/// belongs to a previous run.
+pub async fn reset(app: &AppHandle) {
+    let previous = {
+        let machine = app.state::<ServiceStateMachine>();
+        let mut state = machine.state.lock().unwrap();
+        std::mem::replace(&mut *state, ServiceState::Stopped)
+    };
+    publish(app, previous, ServiceState::Stopped, None).await;
+}
+
+/// Records the result of a connectivity check. Only applies while the core
+/// is up, a check against a stopped core says nothing about its state.
+pub async fn report_health(app: &AppHandle, healthy: bool, reason: Option<String>) {
+    if !current(app).is_active() {
+        return;
+    }
+    let next = if healthy {
+        ServiceState::Running
+    } else {
+        ServiceState::Degraded
+    };
+    if let Err(e) = transition(app, next, reason).await {
+        warn!("{}", e);
+    }
+}
+
+async fn publish(
+    app: &AppHandle,
+    previous: ServiceState,
+    current: ServiceState,
+    reason: Option<String>,
+) {
+    if let Err(e) = persist(app, current).await {
+        warn!("Failed to persist service state: {}", e);
+    }
+
+    if let Some(tray_manager) = app.try_state::<SystemTrayManager>() {
+        tray_manager.set_service_state(current);
+        tray_manager.update_menu(app, String::new()).await;
+    }
+
+    if let Err(e) = app.emit(
+        SERVICE_STATE_EVENT,
+        ServiceStateChange {
+            previous,
+            current,
+            reason,
+        },
+    ) {
+        warn!("Failed to emit service state: {}", e);
+    }
+}
+
+async fn persist(app: &AppHandle, state: ServiceState) -> Result<(), String> {
+    let database_path = utils::get_database_path(app).to_string_lossy().to_string();
+    let database_url = format!("sqlite://{}", database_path);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect(&database_url)
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+
+    sqlx::query(
+        "UPDATE AppStatus SET ServiceState = ?, ServiceRunningState = ? WHERE LoginState = 1;",
+    )
+    .bind(state.as_str())
+    .bind(if state.is_active() { 1 } else { 0 })
+    .execute(&pool)
+    .await
+    .map_err(|e| format!("Failed to update service state: {}", e))?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_service_state(app: AppHandle) -> ServiceState {
+    current(&app)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ServiceState::{self, *};
+
+    const ALL: [ServiceState; 6] = [Stopped, Starting, Running, Degraded, Stopping, Failed];
+
+    const ALLOWED: [(ServiceState, ServiceState); 15] = [
+        (Stopped, Starting),
+        (Starting, Running),
+        (Starting, Failed),
+        (Starting, Stopping),
+        (Running, Degraded),
+        (Running, Stopping),
+        (Running, Failed),
+        (Degraded, Running),
+        (Degraded, Stopping),
+        (Degraded, Failed),
+        (Stopping, Stopped),
+        (Stopping, Failed),
+        (Failed, Starting),
+        (Failed, Stopping),
+        (Failed, Stopped),
+    ];
+
+    #[test]
+    fn allows_only_the_listed_transitions() {
+        for from in ALL {
+            for to in ALL {
+                assert_eq!(
+                    from.can_transition_to(to),
+                    ALLOWED.contains(&(from, to)),
+                    "{} -> {}",
+                    from.as_str(),
+                    to.as_str()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn forbids_staying_in_the_same_state() {
+        // `transition` treats these as no-ops before asking the rules
+        for state in ALL {
+            assert!(!state.can_transition_to(state), "{}", state.as_str());
+        }
+    }
+
+    #[test]
+    fn running_needs_a_start() {
+        assert!(!Stopped.can_transition_to(Running));
+        assert!(!Failed.can_transition_to(Running));
+        assert!(!Stopping.can_transition_to(Running));
+    }
+
+    #[test]
+    fn only_running_and_degraded_are_active() {
+        for state in ALL {
+            assert_eq!(state.is_active(), matches!(state, Running | Degraded));
+        }
+    }
+}
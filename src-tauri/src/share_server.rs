@@ -0,0 +1,527 @@
+//! Serves a group as a subscription to other devices on the LAN.
+//!
+//! An optional HTTP server, like the PAC server in `proxy.rs`, answering
+//! `GET /subscription/<token>` with the share links of the chosen group,
+//! base64-encoded the way most providers serve their subscriptions. The
+//! links are read on every request, so edits to the group show up on the
+//! next refresh. Any other path or token gets a 404. The token is random and
+//! only changes when it is reset, so devices keep a working URL across
+//! restarts.
+//!
+//! The settings live in `AppSettings`, and a server that was running when
+//! the app quit is started again on launch.
+
+use base64::{engine::general_purpose::STANDARD as BASE64_STANDARD, Engine};
+use log::{error, info, warn};
+use serde::Serialize;
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+use sqlx::Row;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::Mutex;
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Manager};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+use warp::http::StatusCode;
+use warp::{Filter, Reply};
+
+use crate::qr_code;
+use crate::share_link;
+use crate::utils;
+
+pub struct ShareServerState {
+    server: Mutex<Option<(oneshot::Sender<()>, JoinHandle<()>)>>,
+    /// Held while the server is stopped and started again, so that two
+    /// starts cannot interleave and leave one server running unowned
+    lifecycle: tokio::sync::Mutex<()>,
+}
+
+impl ShareServerState {
+    pub fn new() -> Self {
+        ShareServerState {
+            server: Mutex::new(None),
+            lifecycle: tokio::sync::Mutex::new(()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareServerStatus {
+    pub running: bool,
+    pub group_id: Option<String>,
+    pub bind_address: String,
+    pub port: u16,
+    /// The subscription URL, once the server has been started
+    pub url: Option<String>,
+    /// The URL as a QR code, a PNG data URL
+    pub qr_code: Option<String>,
+}
+
+struct Settings {
+    group_id: Option<String>,
+    address: String,
+    port: u16,
+    token: Option<String>,
+}
+
+fn database_url(app: &AppHandle) -> String {
+    format!(
+        "sqlite://{}",
+        utils::get_database_path(app).to_string_lossy()
+    )
+}
+
+async fn load_settings(pool: &SqlitePool, user_id: &str) -> Result<Settings, String> {
+    let row = sqlx::query(
+        "SELECT ShareServerGroupID, ShareServerAddress, ShareServerPort, ShareServerToken
+         FROM AppSettings WHERE UserID = ?",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("Failed to fetch share server settings: {}", e))?
+    .ok_or("No settings found for the user")?;
+
+    Ok(Settings {
+        group_id: row.get("ShareServerGroupID"),
+        address: row.get("ShareServerAddress"),
+        port: u16::try_from(row.get::<i64, _>("ShareServerPort")).unwrap_or(0),
+        token: row.get("ShareServerToken"),
+    })
+}
+
+/// The share links of `group_id`, one per line and base64-encoded.
+async fn subscription(pool: &SqlitePool, group_id: &str) -> Result<String, String> {
+    let endpoint_ids: Vec<String> = sqlx::query_scalar(
+        "SELECT EndpointID FROM Endpoints WHERE GroupID = ? ORDER BY EndpointID",
+    )
+    .bind(group_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to fetch endpoints: {}", e))?;
+
+    let mut links = Vec::new();
+    for endpoint_id in endpoint_ids {
+        match share_link::load_endpoint(pool, &endpoint_id).await {
+            Ok(share_link) => links.push(share_link.link),
+            Err(e) => warn!(
+                "Leaving endpoint {} out of the shared group: {}",
+                endpoint_id, e
+            ),
+        }
+    }
+    Ok(BASE64_STANDARD.encode(links.join("\n")))
+}
+
+async fn respond(database_url: &str, group_id: &str) -> warp::reply::Response {
+    let result: Result<String, String> = async {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(database_url)
+            .await
+            .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+        subscription(&pool, group_id).await
+    }
+    .await;
+
+    match result {
+        Ok(body) => warp::reply::with_header(body, "Content-Type", "text/plain; charset=utf-8")
+            .into_response(),
+        Err(e) => {
+            error!("Failed to serve shared group {}: {}", group_id, e);
+            warp::reply::with_status(
+                "Failed to load the group",
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .into_response()
+        }
+    }
+}
+
+/// The address other devices reach the server at. A wildcard bind address
+/// is replaced with the address of the interface holding the default route.
+fn reachable_ip(address: IpAddr) -> IpAddr {
+    if !address.is_unspecified() {
+        return address;
+    }
+    // Connecting a UDP socket only looks up the route, nothing is sent
+    UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|socket| {
+            socket.connect((Ipv4Addr::new(8, 8, 8, 8), 80))?;
+            socket.local_addr()
+        })
+        .map(|local| local.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))
+}
+
+async fn status(
+    app: &AppHandle,
+    pool: &SqlitePool,
+    user_id: &str,
+) -> Result<ShareServerStatus, String> {
+    let settings = load_settings(pool, user_id).await?;
+    let running = app
+        .state::<ShareServerState>()
+        .server
+        .lock()
+        .unwrap()
+        .is_some();
+
+    let url = match (&settings.token, settings.address.parse::<IpAddr>()) {
+        (Some(token), Ok(address)) if settings.port != 0 => Some(format!(
+            "http://{}/subscription/{}",
+            SocketAddr::new(reachable_ip(address), settings.port),
+            token
+        )),
+        _ => None,
+    };
+    let qr_code = url.as_deref().map(qr_code::encode_png).transpose()?;
+
+    Ok(ShareServerStatus {
+        running,
+        group_id: settings.group_id,
+        bind_address: settings.address,
+        port: settings.port,
+        url,
+        qr_code,
+    })
+}
+
+/// Binds the subscription route on `address`. The returned future serves
+/// requests until `shutdown` completes.
+fn bind(
+    database_url: String,
+    group_id: String,
+    address: SocketAddr,
+    token: String,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<(SocketAddr, impl Future<Output = ()> + Send + 'static), String> {
+    let route = warp::get()
+        .and(warp::path!("subscription" / String))
+        .and(warp::addr::remote())
+        .and_then(move |requested: String, remote: Option<SocketAddr>| {
+            let database_url = database_url.clone();
+            let group_id = group_id.clone();
+            let authorized = requested == token;
+            async move {
+                if !authorized {
+                    return Err(warp::reject::not_found());
+                }
+                info!(
+                    "Serving shared group {} to {}",
+                    group_id,
+                    remote.map_or("unknown".to_string(), |remote| remote.to_string())
+                );
+                Ok::<_, warp::Rejection>(respond(&database_url, &group_id).await)
+            }
+        });
+
+    warp::serve(route)
+        .try_bind_with_graceful_shutdown(address, shutdown)
+        .map_err(|e| format!("Failed to listen on {}: {}", address, e))
+}
+
+/// Binds the server and keeps it running in the background until [`stop`].
+fn serve(
+    app: &AppHandle,
+    group_id: String,
+    address: IpAddr,
+    port: u16,
+    token: String,
+) -> Result<(), String> {
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let (serve_addr, server_future) = bind(
+        database_url(app),
+        group_id,
+        SocketAddr::new(address, port),
+        token,
+        async {
+            shutdown_rx.await.ok();
+        },
+    )?;
+    let handle = tauri::async_runtime::spawn(server_future);
+
+    let previous = app
+        .state::<ShareServerState>()
+        .server
+        .lock()
+        .unwrap()
+        .replace((shutdown_tx, handle));
+    if let Some((tx, _)) = previous {
+        let _ = tx.send(());
+    }
+    info!("Share server listening on {}", serve_addr);
+    Ok(())
+}
+
+/// The saved token, or a new one when there is none yet or it is reset.
+fn token(saved: Option<String>, reset: bool) -> String {
+    saved
+        .filter(|_| !reset)
+        .unwrap_or_else(|| Uuid::new_v4().simple().to_string())
+}
+
+/// Stops the running server, waiting until its port is released.
+async fn stop(app: &AppHandle) {
+    let server = app
+        .state::<ShareServerState>()
+        .server
+        .lock()
+        .unwrap()
+        .take();
+    if let Some((tx, handle)) = server {
+        let _ = tx.send(());
+        let _ = handle.await;
+        info!("Share server stopped");
+    }
+}
+
+/// Starts the server the logged in user left running, if any.
+pub fn start(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let result: Result<(), String> = async {
+            let pool = SqlitePoolOptions::new()
+                .max_connections(1)
+                .connect(&database_url(&app))
+                .await
+                .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+            let row = sqlx::query(
+                "SELECT a.ShareServerGroupID, a.ShareServerAddress, a.ShareServerPort, a.ShareServerToken
+                 FROM AppSettings a
+                 JOIN AppStatus s ON s.UserID = a.UserID
+                 WHERE s.LoginState = 1 AND a.ShareServerEnabled = 1
+                   AND a.ShareServerGroupID IS NOT NULL AND a.ShareServerToken IS NOT NULL
+                 LIMIT 1",
+            )
+            .fetch_optional(&pool)
+            .await
+            .map_err(|e| format!("Failed to fetch share server settings: {}", e))?;
+            let Some(row) = row else {
+                return Ok(());
+            };
+
+            let address: String = row.get("ShareServerAddress");
+            let address = address
+                .parse()
+                .map_err(|e| format!("Invalid bind address {}: {}", address, e))?;
+            let port = u16::try_from(row.get::<i64, _>("ShareServerPort"))
+                .ok()
+                .filter(|port| *port != 0)
+                .ok_or("No share server port saved")?;
+            let state = app.state::<ShareServerState>();
+            let _lifecycle = state.lifecycle.lock().await;
+            serve(
+                &app,
+                row.get("ShareServerGroupID"),
+                address,
+                port,
+                row.get("ShareServerToken"),
+            )
+        }
+        .await;
+        if let Err(e) = result {
+            error!("Failed to start the share server: {}", e);
+        }
+    });
+}
+
+/// Shares `group_id` with other devices and remembers it for the next
+/// launch. `bind_address` defaults to the previous one, `127.0.0.1` at
+/// first, so the group only leaves this machine once a LAN or wildcard
+/// address is chosen. Without a `port` the previous one is kept, or a free
+/// one picked. `reset_token` invalidates the URL handed out so far.
+#[tauri::command]
+pub async fn start_share_server(
+    app: AppHandle,
+    user_id: String,
+    group_id: String,
+    bind_address: Option<String>,
+    port: Option<u16>,
+    reset_token: bool,
+) -> Result<ShareServerStatus, String> {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url(&app))
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+
+    sqlx::query_scalar::<_, String>(
+        "SELECT GroupID FROM EndpointsGroups WHERE GroupID = ? AND UserID = ?",
+    )
+    .bind(&group_id)
+    .bind(&user_id)
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| format!("Failed to fetch group: {}", e))?
+    .ok_or("Group not found")?;
+
+    let settings = load_settings(&pool, &user_id).await?;
+    let address_text = bind_address
+        .map(|address| address.trim().to_string())
+        .filter(|address| !address.is_empty())
+        .unwrap_or(settings.address);
+    let address: IpAddr = address_text
+        .parse()
+        .map_err(|e| format!("Invalid bind address {}: {}", address_text, e))?;
+    let port = port
+        .filter(|port| *port != 0)
+        .or(Some(settings.port).filter(|port| *port != 0))
+        .or_else(portpicker::pick_unused_port)
+        .ok_or("Could not find a free TCP port")?;
+    let token = token(settings.token, reset_token);
+
+    let state = app.state::<ShareServerState>();
+    let _lifecycle = state.lifecycle.lock().await;
+    stop(&app).await;
+    serve(&app, group_id.clone(), address, port, token.clone())?;
+
+    sqlx::query(
+        "UPDATE AppSettings
+         SET ShareServerEnabled = 1, ShareServerGroupID = ?, ShareServerAddress = ?,
+             ShareServerPort = ?, ShareServerToken = ?
+         WHERE UserID = ?",
+    )
+    .bind(&group_id)
+    .bind(address.to_string())
+    .bind(port as i64)
+    .bind(&token)
+    .bind(&user_id)
+    .execute(&pool)
+    .await
+    .map_err(|e| format!("Failed to save share server settings: {}", e))?;
+
+    status(&app, &pool, &user_id).await
+}
+
+/// Stops sharing, also on the next launch. The token is kept, so starting
+/// the server again brings back the same URL.
+#[tauri::command]
+pub async fn stop_share_server(
+    app: AppHandle,
+    user_id: String,
+) -> Result<ShareServerStatus, String> {
+    {
+        let state = app.state::<ShareServerState>();
+        let _lifecycle = state.lifecycle.lock().await;
+        stop(&app).await;
+    }
+
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url(&app))
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+    sqlx::query("UPDATE AppSettings SET ShareServerEnabled = 0 WHERE UserID = ?")
+        .bind(&user_id)
+        .execute(&pool)
+        .await
+        .map_err(|e| format!("Failed to save share server settings: {}", e))?;
+
+    status(&app, &pool, &user_id).await
+}
+
+#[tauri::command]
+pub async fn get_share_server_status(
+    app: AppHandle,
+    user_id: String,
+) -> Result<ShareServerStatus, String> {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url(&app))
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+    status(&app, &pool, &user_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINK: &str =
+        "trojan://secret@trojan.example.com:443?security=tls&sni=trojan.example.com#Shared";
+
+    /// A database file holding one group with one endpoint.
+    async fn database() -> (String, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(format!("share-server-{}.db", Uuid::new_v4()));
+        let database_url = format!("sqlite://{}?mode=rwc", path.to_string_lossy());
+        let pool = crate::migrations::migrated_pool(&database_url).await;
+        let mut conn = pool.acquire().await.unwrap();
+        share_link::insert_endpoint(
+            &mut conn,
+            "endpoint",
+            "group",
+            "Group",
+            &share_link::parse(LINK).unwrap(),
+        )
+        .await
+        .unwrap();
+        (database_url, path)
+    }
+
+    async fn get(address: SocketAddr, path: &str) -> (u16, String) {
+        // Not through a proxy from the environment
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let response = client
+            .get(format!("http://{}{}", address, path))
+            .send()
+            .await
+            .unwrap();
+        (response.status().as_u16(), response.text().await.unwrap())
+    }
+
+    fn start(database_url: &str, token: &str) -> (SocketAddr, oneshot::Sender<()>) {
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let (address, server) = bind(
+            database_url.to_string(),
+            "group".to_string(),
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            token.to_string(),
+            async {
+                shutdown_rx.await.ok();
+            },
+        )
+        .unwrap();
+        tokio::spawn(server);
+        (address, shutdown_tx)
+    }
+
+    #[tokio::test]
+    async fn serves_the_group_to_the_token_only() {
+        let (database_url, path) = database().await;
+        let token = token(None, false);
+        let (address, _shutdown) = start(&database_url, &token);
+
+        let (status, body) = get(address, &format!("/subscription/{}", token)).await;
+        assert_eq!(status, 200);
+        let expected = share_link::parse(LINK).unwrap().to_url();
+        assert_eq!(BASE64_STANDARD.decode(body).unwrap(), expected.as_bytes());
+
+        for path in [
+            "/subscription/wrong".to_string(),
+            format!("/subscription/{}x", token),
+            format!("/subscription/{}/more", token),
+            format!("/{}", token),
+            "/".to_string(),
+        ] {
+            assert_eq!(get(address, &path).await.0, 404, "{}", path);
+        }
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn reset_token_invalidates_the_old_url() {
+        let (database_url, path) = database().await;
+        let old = token(None, false);
+        assert_eq!(token(Some(old.clone()), false), old);
+
+        let new = token(Some(old.clone()), true);
+        assert_ne!(new, old);
+        let (address, _shutdown) = start(&database_url, &new);
+        assert_eq!(get(address, &format!("/subscription/{}", old)).await.0, 404);
+        assert_eq!(get(address, &format!("/subscription/{}", new)).await.0, 200);
+        let _ = std::fs::remove_file(path);
+    }
+}
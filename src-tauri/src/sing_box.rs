@@ -0,0 +1,310 @@
+//! sing-box JSON subscriptions.
+//!
+//! A sing-box config lists its servers under `outbounds`. vmess, vless,
+//! shadowsocks, trojan and hysteria2 outbounds are mapped onto
+//! [`ShareLink`]s with their `tls` and `transport` objects. Outbounds that
+//! are not servers (`direct`, `block`, `dns`, `selector`, `urltest`) are
+//! ignored. Every other outbound that cannot be mapped, because of its type,
+//! a malformed field or an option the bundled v2ray-core lacks such as
+//! REALITY, is reported without dropping the rest.
+
+use serde_json::Value;
+
+use crate::share_link::{self, Outbound, Profile, ShareLink, Tls, Transport};
+
+/// Outbounds that route traffic rather than describe a server.
+const NON_SERVER_TYPES: [&str; 5] = ["direct", "block", "dns", "selector", "urltest"];
+
+fn string(value: &Value, key: &str) -> Option<String> {
+    value[key]
+        .as_str()
+        .filter(|text| !text.is_empty())
+        .map(String::from)
+}
+
+fn tls(outbound: &Value) -> Result<Option<Tls>, String> {
+    let tls = &outbound["tls"];
+    if !tls["enabled"].as_bool().unwrap_or(false) {
+        return Ok(None);
+    }
+    if tls["reality"]["enabled"].as_bool().unwrap_or(false) {
+        // reality needs Xray, the bundled core cannot connect
+        return Err("REALITY is not supported".to_string());
+    }
+    Ok(Some(Tls {
+        server_name: string(tls, "server_name").unwrap_or_default(),
+        allow_insecure: tls["insecure"].as_bool().unwrap_or(false),
+        fingerprint: string(&tls["utls"], "fingerprint")
+            .filter(|_| tls["utls"]["enabled"].as_bool().unwrap_or(false)),
+    }))
+}
+
+/// Maps the `transport` object. sing-box's `http` transport is HTTP/2 with
+/// TLS and HTTP/1.1 camouflage without.
+fn transport(outbound: &Value, tls: bool) -> Result<Transport, String> {
+    let transport = &outbound["transport"];
+    let host = match &transport["host"] {
+        Value::Array(hosts) => hosts.first().and_then(Value::as_str).map(String::from),
+        host => host.as_str().map(String::from),
+    };
+    Ok(match transport["type"].as_str() {
+        None => Transport::Tcp {
+            header_type: "none".to_string(),
+            host: None,
+            path: None,
+        },
+        Some("ws") => Transport::Ws {
+            host: string(&transport["headers"], "Host")
+                .or(string(&transport["headers"], "host"))
+                .unwrap_or_default(),
+            path: string(transport, "path").unwrap_or_else(|| "/".to_string()),
+        },
+        Some("grpc") => Transport::Grpc {
+            service_name: string(transport, "service_name").unwrap_or_default(),
+        },
+        Some("http") if tls => Transport::Http {
+            host: host.or(string(outbound, "server")).unwrap_or_default(),
+            path: string(transport, "path").unwrap_or_else(|| "/".to_string()),
+        },
+        Some("http") => Transport::Tcp {
+            header_type: "http".to_string(),
+            host,
+            path: string(transport, "path"),
+        },
+        Some("quic") => Transport::Quic {
+            security: "none".to_string(),
+            key: String::new(),
+            header_type: "none".to_string(),
+        },
+        Some(other) => return Err(format!("Unsupported transport: {}", other)),
+    })
+}
+
+fn parse_outbound(kind: &str, outbound: &Value) -> Result<ShareLink, String> {
+    let address = string(outbound, "server").ok_or("Missing server")?;
+    let port = outbound["server_port"]
+        .as_u64()
+        .and_then(|port| u16::try_from(port).ok())
+        .filter(|port| *port != 0)
+        .ok_or("Invalid server_port")?;
+    let required = |key: &str| string(outbound, key).ok_or(format!("Missing {}", key));
+    let tls = tls(outbound)?;
+
+    let (outbound_settings, transport) = match kind {
+        "vmess" => (
+            Outbound::Vmess {
+                address,
+                port,
+                uuid: required("uuid")?,
+                alter_id: outbound["alter_id"].as_i64().unwrap_or(0),
+                security: string(outbound, "security").unwrap_or_else(|| "auto".to_string()),
+            },
+            transport(outbound, tls.is_some())?,
+        ),
+        "vless" => (
+            Outbound::Vless {
+                address,
+                port,
+                uuid: required("uuid")?,
+                encryption: "none".to_string(),
+                flow: string(outbound, "flow"),
+            },
+            transport(outbound, tls.is_some())?,
+        ),
+        "shadowsocks" => {
+            if string(outbound, "plugin").is_some() {
+                return Err("Shadowsocks plugins are not supported".to_string());
+            }
+            (
+                Outbound::Shadowsocks {
+                    address,
+                    port,
+                    method: required("method")?.to_ascii_lowercase(),
+                    password: required("password")?,
+                },
+                Transport::Tcp {
+                    header_type: "none".to_string(),
+                    host: None,
+                    path: None,
+                },
+            )
+        }
+        "trojan" => (
+            Outbound::Trojan {
+                address,
+                port,
+                password: required("password")?,
+            },
+            transport(outbound, tls.is_some())?,
+        ),
+        "hysteria2" => {
+            if outbound["obfs"].is_object() {
+                return Err("Hysteria2 obfuscation is not supported".to_string());
+            }
+            (
+                Outbound::Hysteria2 {
+                    address,
+                    port,
+                    password: required("password")?,
+                    upload_mbps: outbound["up_mbps"].as_i64().unwrap_or(50),
+                    download_mbps: outbound["down_mbps"].as_i64().unwrap_or(100),
+                },
+                Transport::Hysteria2,
+            )
+        }
+        other => return Err(format!("Unsupported type: {}", other)),
+    };
+    // Hysteria2 always runs over TLS
+    let tls = match (&outbound_settings, tls) {
+        (Outbound::Hysteria2 { .. }, None) => Some(Tls {
+            server_name: String::new(),
+            allow_insecure: false,
+            fingerprint: None,
+        }),
+        (_, tls) => tls,
+    };
+
+    let mut share_link = ShareLink {
+        link: String::new(),
+        remark: share_link::remark_or_address(
+            string(outbound, "tag"),
+            outbound_settings.address(),
+            outbound_settings.port(),
+        ),
+        outbound: outbound_settings,
+        transport,
+        tls,
+    };
+    // Stored like an imported link, so it can be shared and compared later
+    share_link.link = share_link.to_url();
+    Ok(share_link)
+}
+
+/// Parses `text` as a sing-box config. `None` when it is not a JSON object
+/// with an `outbounds` list, so the other subscription formats can be tried.
+pub fn parse(text: &str) -> Option<Profile> {
+    let document: Value = serde_json::from_str(text.trim()).ok()?;
+    let outbounds = document.get("outbounds")?.as_array()?;
+
+    let mut share_links = Vec::new();
+    let mut skipped = Vec::new();
+    for (index, outbound) in outbounds.iter().enumerate() {
+        let name = string(outbound, "tag").unwrap_or_else(|| format!("outbound {}", index + 1));
+        let Some(kind) = outbound["type"].as_str() else {
+            skipped.push(format!("{}: Missing type", name));
+            continue;
+        };
+        if NON_SERVER_TYPES.contains(&kind) {
+            continue;
+        }
+        match parse_outbound(kind, outbound) {
+            Ok(share_link) => share_links.push(share_link),
+            Err(e) => skipped.push(format!("{}: {}", name, e)),
+        }
+    }
+    Some(Profile {
+        share_links,
+        skipped,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../tests/fixtures/sing-box.json");
+
+    fn profile() -> Profile {
+        parse(FIXTURE).expect("sing-box config")
+    }
+
+    #[test]
+    fn maps_the_servers() {
+        let profile = profile();
+        let remarks: Vec<_> = profile
+            .share_links
+            .iter()
+            .map(|share_link| share_link.remark.as_str())
+            .collect();
+        assert_eq!(remarks, ["vmess-ws", "vless-grpc", "ss", "trojan", "hy2"]);
+
+        let vmess = &profile.share_links[0];
+        assert_eq!(
+            vmess.transport,
+            Transport::Ws {
+                host: "cdn.example.com".to_string(),
+                path: "/ray".to_string(),
+            }
+        );
+        assert_eq!(
+            vmess.tls,
+            Some(Tls {
+                server_name: "vmess.example.com".to_string(),
+                allow_insecure: false,
+                fingerprint: None,
+            })
+        );
+
+        let vless = &profile.share_links[1];
+        assert_eq!(
+            vless.transport,
+            Transport::Grpc {
+                service_name: "tunnel".to_string(),
+            }
+        );
+        assert_eq!(
+            vless
+                .tls
+                .as_ref()
+                .and_then(|tls| tls.fingerprint.as_deref()),
+            Some("chrome")
+        );
+
+        assert_eq!(profile.share_links[2].tls, None);
+        assert!(profile.share_links[3]
+            .tls
+            .as_ref()
+            .is_some_and(|tls| tls.allow_insecure));
+        assert_eq!(
+            profile.share_links[4].outbound,
+            Outbound::Hysteria2 {
+                address: "hy2.example.com".to_string(),
+                port: 8443,
+                password: "secret".to_string(),
+                upload_mbps: 20,
+                download_mbps: 200,
+            }
+        );
+        // Hysteria2 always runs over TLS
+        assert!(profile.share_links[4].tls.is_some());
+
+        for share_link in &profile.share_links {
+            assert_eq!(share_link::parse(&share_link.link).as_ref(), Ok(share_link));
+        }
+    }
+
+    #[test]
+    fn reports_each_malformed_outbound() {
+        // Routing outbounds (selector, direct, block, dns, urltest) are not errors
+        assert_eq!(
+            profile().skipped,
+            [
+                "vless-reality: REALITY is not supported",
+                "ss-plugin: Shadowsocks plugins are not supported",
+                "tuic: Unsupported type: tuic",
+                "vmess-no-uuid: Missing uuid",
+                "trojan-bad-port: Invalid server_port",
+                "vless-httpupgrade: Unsupported transport: httpupgrade",
+                "hy2-obfs: Hysteria2 obfuscation is not supported",
+                "outbound 14: Missing type",
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_other_documents_alone() {
+        assert!(parse(include_str!("../tests/fixtures/sip008.json")).is_none());
+        assert!(parse("outbounds:\n  - type: direct\n").is_none());
+        assert!(parse("{\"outbounds\": {}}").is_none());
+    }
+}
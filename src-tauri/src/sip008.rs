@@ -0,0 +1,160 @@
+//! Shadowsocks SIP008 online configuration.
+//!
+//! A JSON document with a `servers` list, each entry holding `server`,
+//! `server_port`, `method` and `password` plus an optional `remarks`.
+//! Entries are checked one by one, so a malformed entry is reported without
+//! dropping the rest. Entries with a `plugin` are skipped, the bundled
+//! v2ray-core has no plugin support.
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::share_link::{self, Outbound, Profile, ShareLink, Transport};
+
+#[derive(Deserialize)]
+struct Server {
+    #[serde(default)]
+    remarks: String,
+    server: String,
+    server_port: u16,
+    method: String,
+    password: String,
+    #[serde(default)]
+    plugin: String,
+}
+
+fn parse_server(server: Value) -> Result<ShareLink, String> {
+    let server: Server =
+        serde_json::from_value(server).map_err(|e| format!("Invalid server: {}", e))?;
+    if !server.plugin.is_empty() {
+        return Err("Shadowsocks plugins are not supported".to_string());
+    }
+    if server.server.is_empty() || server.server_port == 0 {
+        return Err("Missing server address or port".to_string());
+    }
+
+    let mut share_link = ShareLink {
+        link: String::new(),
+        remark: share_link::remark_or_address(
+            Some(server.remarks),
+            &server.server,
+            server.server_port,
+        ),
+        transport: Transport::Tcp {
+            header_type: "none".to_string(),
+            host: None,
+            path: None,
+        },
+        tls: None,
+        outbound: Outbound::Shadowsocks {
+            address: server.server,
+            port: server.server_port,
+            method: server.method.to_ascii_lowercase(),
+            password: server.password,
+        },
+    };
+    // Stored like an imported link, so it can be shared and compared later
+    share_link.link = share_link.to_url();
+    Ok(share_link)
+}
+
+/// Parses `text` as a SIP008 document. `None` when it is not a JSON object
+/// with a `servers` list, so the other subscription formats can be tried.
+pub fn parse(text: &str) -> Option<Profile> {
+    let document: Value = serde_json::from_str(text.trim()).ok()?;
+    let servers = document.get("servers")?.as_array()?;
+
+    let mut share_links = Vec::new();
+    let mut skipped = Vec::new();
+    for (index, server) in servers.iter().enumerate() {
+        let name = server["remarks"]
+            .as_str()
+            .filter(|remarks| !remarks.is_empty())
+            .map(String::from)
+            .unwrap_or_else(|| format!("server {}", index + 1));
+        match parse_server(server.clone()) {
+            Ok(share_link) => share_links.push(share_link),
+            Err(e) => skipped.push(format!("{}: {}", name, e)),
+        }
+    }
+    Some(Profile {
+        share_links,
+        skipped,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../tests/fixtures/sip008.json");
+
+    #[test]
+    fn parses_the_fixture() {
+        let profile = parse(FIXTURE).expect("SIP008 document");
+
+        let servers: Vec<_> = profile
+            .share_links
+            .iter()
+            .map(|share_link| (share_link.remark.as_str(), &share_link.outbound))
+            .collect();
+        assert_eq!(
+            servers,
+            [
+                (
+                    "Tokyo 01",
+                    &Outbound::Shadowsocks {
+                        address: "tokyo.example.com".to_string(),
+                        port: 8388,
+                        method: "aes-256-gcm".to_string(),
+                        password: "secret".to_string(),
+                    }
+                ),
+                (
+                    "2001:db8::1:8389",
+                    &Outbound::Shadowsocks {
+                        address: "2001:db8::1".to_string(),
+                        port: 8389,
+                        method: "2022-blake3-aes-256-gcm".to_string(),
+                        password: "YctPZ6U7xPPcU+gp3u+0tx/tRizJN9K8y+uKlW2qjlI=".to_string(),
+                    }
+                ),
+            ]
+        );
+        for share_link in &profile.share_links {
+            assert_eq!(share_link::parse(&share_link.link).as_ref(), Ok(share_link));
+        }
+    }
+
+    #[test]
+    fn reports_each_malformed_server() {
+        let profile = parse(FIXTURE).expect("SIP008 document");
+        assert_eq!(profile.skipped.len(), 4, "{:?}", profile.skipped);
+        assert_eq!(
+            profile.skipped[0],
+            "With plugin: Shadowsocks plugins are not supported"
+        );
+        assert_eq!(
+            profile.skipped[1],
+            "No port: Missing server address or port"
+        );
+        assert!(
+            profile.skipped[2].starts_with("No password: Invalid server: missing field `password`"),
+            "{}",
+            profile.skipped[2]
+        );
+        assert!(
+            profile.skipped[3].starts_with("server 6: Invalid server: invalid type"),
+            "{}",
+            profile.skipped[3]
+        );
+    }
+
+    #[test]
+    fn leaves_other_documents_alone() {
+        assert!(parse(include_str!("../tests/fixtures/sing-box.json")).is_none());
+        assert!(parse("proxies:\n  - name: a\n").is_none());
+        assert!(parse("ss://YWVzLTI1Ni1nY206c2VjcmV0@example.com:8388").is_none());
+        assert!(parse("{\"servers\": {}}").is_none());
+    }
+}
@@ -0,0 +1,832 @@
+//! Subscription updates.
+//!
+//! A subscription is fetched with the `ETag` and `Last-Modified` of the
+//! previous fetch, so providers that support conditional requests answer
+//! `304 Not Modified` and nothing is re-imported. A changed body is synced
+//! into the subscription's group with [`share_link::sync_group`], which keeps
+//! the EndpointIDs of endpoints that are still listed.
+//!
+//! Subscriptions with an `UpdateInterval` (in minutes) are updated in the
+//! background, counted from `LastCheckedAt` so the schedule survives
+//! restarts.
+//!
+//! Most providers report usage in a `subscription-userinfo` header
+//! (`upload=...; download=...; total=...; expire=...`, bytes and unix
+//! seconds). It is stored on the subscription, shown in the tray and checked
+//! against the user's `QuotaWarningPercent` and `ExpiryWarningDays`.
+//!
+//! Each subscription carries its own [`FetchOptions`]: blocked URLs can be
+//! fetched through the local socks inbound, and providers that pick the
+//! format by user agent can be sent the one they expect.
+
+use log::{info, warn};
+use serde::Serialize;
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+use sqlx::Row;
+use std::collections::HashMap;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_notification::NotificationExt;
+
+use crate::failover;
+use crate::service_state;
+use crate::share_link::{self, ImportResult};
+use crate::sys_tray::SystemTrayManager;
+use crate::utils;
+
+const TICK_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const DAY_SECONDS: i64 = 24 * 60 * 60;
+const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36";
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriptionUpdate {
+    pub subscription_id: String,
+    /// The provider answered 304, the group was left as it was
+    pub not_modified: bool,
+    pub import: Option<ImportResult>,
+}
+
+/// How a subscription is fetched.
+pub struct FetchOptions {
+    /// Through the socks inbound of the subscription's user
+    pub via_proxy: bool,
+    pub user_agent: Option<String>,
+    /// Extra request headers, a JSON object of names to values
+    pub headers: Option<String>,
+    pub timeout: Duration,
+    /// Accept invalid or self-signed certificates
+    pub allow_insecure: bool,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        FetchOptions {
+            via_proxy: false,
+            user_agent: None,
+            headers: None,
+            timeout: DEFAULT_TIMEOUT,
+            allow_insecure: false,
+        }
+    }
+}
+
+struct Subscription {
+    subscription_id: String,
+    user_id: String,
+    remark: String,
+    url: String,
+    group_id: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    options: FetchOptions,
+}
+
+/// A fetched subscription with the validators for the next fetch. `body`
+/// is `None` when the provider reported no change.
+struct Fetched {
+    body: Option<String>,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    userinfo: Option<UserInfo>,
+}
+
+/// Usage reported in a `subscription-userinfo` header.
+#[derive(Default)]
+pub struct UserInfo {
+    pub upload: Option<i64>,
+    pub download: Option<i64>,
+    pub total: Option<i64>,
+    /// Unix seconds, `None` when the subscription does not expire
+    pub expire: Option<i64>,
+}
+
+/// Parses `upload=1; download=2; total=3; expire=4`. Some providers send
+/// floats, and `expire=0` means no expiry.
+pub fn parse_userinfo(header: &str) -> Option<UserInfo> {
+    let mut userinfo = UserInfo::default();
+    let mut found = false;
+    for part in header.split(';') {
+        let Some((key, value)) = part.split_once('=') else {
+            continue;
+        };
+        let Ok(value) = value.trim().parse::<f64>() else {
+            continue;
+        };
+        let value = Some(value as i64);
+        match key.trim().to_ascii_lowercase().as_str() {
+            "upload" => userinfo.upload = value,
+            "download" => userinfo.download = value,
+            "total" => userinfo.total = value,
+            "expire" => userinfo.expire = value.filter(|expire| *expire > 0),
+            _ => continue,
+        }
+        found = true;
+    }
+    found.then_some(userinfo)
+}
+
+/// Stores `userinfo` on every subscription fetched from `url`.
+pub async fn store_userinfo(
+    pool: &SqlitePool,
+    url: &str,
+    userinfo: &UserInfo,
+) -> Result<(), String> {
+    sqlx::query(
+        "UPDATE Subscriptions SET Upload = ?, Download = ?, Total = ?, Expire = ? WHERE Url = ?",
+    )
+    .bind(userinfo.upload)
+    .bind(userinfo.download)
+    .bind(userinfo.total)
+    .bind(userinfo.expire)
+    .bind(url)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to store subscription usage: {}", e))?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriptionUsage {
+    pub subscription_id: String,
+    pub remark: String,
+    pub upload: Option<i64>,
+    pub download: Option<i64>,
+    pub total: Option<i64>,
+    /// Bytes left, when the provider reports a total
+    pub remaining: Option<i64>,
+    pub expire: Option<i64>,
+}
+
+impl SubscriptionUsage {
+    fn used(&self) -> i64 {
+        self.upload.unwrap_or(0) + self.download.unwrap_or(0)
+    }
+
+    /// Label for the tray, e.g. `Provider: 12.3 GB left, expires 2026-11-01`.
+    pub fn label(&self) -> String {
+        let mut parts = Vec::new();
+        match self.remaining {
+            Some(remaining) => parts.push(format!("{} left", format_bytes(remaining))),
+            None if self.upload.is_some() || self.download.is_some() => {
+                parts.push(format!("{} used", format_bytes(self.used())))
+            }
+            None => {}
+        }
+        if let Some(expire) = self.expire {
+            parts.push(format!("expires {}", format_date(expire)));
+        }
+        format!("{}: {}", self.remark, parts.join(", "))
+    }
+}
+
+pub fn format_bytes(bytes: i64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes.max(0) as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{:.0} {}", value, UNITS[unit])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+fn format_date(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .map(|date| {
+            date.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d")
+                .to_string()
+        })
+        .unwrap_or_default()
+}
+
+fn usage_from_row(row: &sqlx::sqlite::SqliteRow) -> SubscriptionUsage {
+    let upload: Option<i64> = row.get("Upload");
+    let download: Option<i64> = row.get("Download");
+    let total: Option<i64> = row
+        .get::<Option<i64>, _>("Total")
+        .filter(|total| *total > 0);
+    SubscriptionUsage {
+        subscription_id: row.get("SubscriptionID"),
+        remark: row.get("Remark"),
+        upload,
+        download,
+        total,
+        remaining: total.map(|total| (total - upload.unwrap_or(0) - download.unwrap_or(0)).max(0)),
+        expire: row.get("Expire"),
+    }
+}
+
+/// Usage of the subscriptions of `user_id` whose provider reported any.
+pub async fn usage(pool: &SqlitePool, user_id: &str) -> Result<Vec<SubscriptionUsage>, String> {
+    let rows = sqlx::query(
+        "SELECT SubscriptionID, Remark, Upload, Download, Total, Expire
+         FROM Subscriptions
+         WHERE UserID = ? AND (Upload IS NOT NULL OR Download IS NOT NULL OR Total IS NOT NULL OR Expire IS NOT NULL)",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to fetch subscription usage: {}", e))?;
+    Ok(rows.iter().map(usage_from_row).collect())
+}
+
+fn notify(app: &AppHandle, body: String) {
+    if let Err(e) = app
+        .notification()
+        .builder()
+        .title("V2rayX")
+        .body(body)
+        .show()
+    {
+        warn!("Failed to show notification: {}", e);
+    }
+}
+
+/// Notifies once when a subscription's remaining quota drops below the
+/// user's `QuotaWarningPercent` or it expires within `ExpiryWarningDays`.
+/// The warnings are re-armed once the subscription recovers, e.g. after a
+/// renewal.
+pub async fn notify_warnings(app: &AppHandle, pool: &SqlitePool) {
+    let rows = match sqlx::query(
+        "SELECT sub.SubscriptionID, sub.Remark, sub.Upload, sub.Download, sub.Total, sub.Expire,
+         sub.QuotaWarned, sub.ExpiryWarned, a.QuotaWarningPercent, a.ExpiryWarningDays
+         FROM Subscriptions sub
+         JOIN AppSettings a ON a.UserID = sub.UserID
+         JOIN AppStatus s ON s.UserID = sub.UserID
+         WHERE s.LoginState = 1",
+    )
+    .fetch_all(pool)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            warn!("Failed to fetch subscription usage: {}", e);
+            return;
+        }
+    };
+
+    let now = chrono::Utc::now().timestamp();
+    for row in rows {
+        let usage = usage_from_row(&row);
+        let quota_warned: bool = row.get("QuotaWarned");
+        let expiry_warned: bool = row.get("ExpiryWarned");
+        let percent: i64 = row.get("QuotaWarningPercent");
+        let days: i64 = row.get("ExpiryWarningDays");
+
+        let quota_low = match (usage.remaining, usage.total) {
+            (Some(remaining), Some(total)) => remaining * 100 < total * percent,
+            _ => false,
+        };
+        let expiring = usage
+            .expire
+            .is_some_and(|expire| expire - now < days * DAY_SECONDS);
+
+        if quota_low != quota_warned || expiring != expiry_warned {
+            if let Err(e) = sqlx::query(
+                "UPDATE Subscriptions SET QuotaWarned = ?, ExpiryWarned = ? WHERE SubscriptionID = ?",
+            )
+            .bind(quota_low)
+            .bind(expiring)
+            .bind(&usage.subscription_id)
+            .execute(pool)
+            .await
+            {
+                warn!("Failed to update subscription warnings: {}", e);
+                continue;
+            }
+        }
+        if quota_low && !quota_warned {
+            notify(
+                app,
+                format!(
+                    "Subscription {} has {} of {} left",
+                    usage.remark,
+                    format_bytes(usage.remaining.unwrap_or(0)),
+                    format_bytes(usage.total.unwrap_or(0))
+                ),
+            );
+        }
+        if expiring && !expiry_warned {
+            let expire = usage.expire.unwrap_or(now);
+            let body = if expire <= now {
+                format!("Subscription {} has expired", usage.remark)
+            } else {
+                format!(
+                    "Subscription {} expires on {}",
+                    usage.remark,
+                    format_date(expire)
+                )
+            };
+            notify(app, body);
+        }
+    }
+}
+
+/// Usage of every subscription of `user_id` that reports it.
+#[tauri::command]
+pub async fn get_subscription_usage(
+    app: AppHandle,
+    user_id: String,
+) -> Result<Vec<SubscriptionUsage>, String> {
+    let database_path = utils::get_database_path(&app).to_string_lossy().to_string();
+    let database_url = format!("sqlite://{}", database_path);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url)
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+
+    usage(&pool, &user_id).await
+}
+
+async fn load(pool: &SqlitePool, subscription_id: &str) -> Result<Subscription, String> {
+    let row = sqlx::query(
+        "SELECT SubscriptionID, UserID, Remark, Url, GroupID, ETag, LastModified,
+         FetchViaProxy, UserAgent, Headers, Timeout, AllowInsecure
+         FROM Subscriptions WHERE SubscriptionID = ?",
+    )
+    .bind(subscription_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("Failed to fetch subscription: {}", e))?
+    .ok_or("Subscription not found")?;
+
+    Ok(Subscription {
+        subscription_id: row.get("SubscriptionID"),
+        user_id: row.get("UserID"),
+        remark: row.get("Remark"),
+        url: row.get("Url"),
+        group_id: row.get("GroupID"),
+        etag: row.get("ETag"),
+        last_modified: row.get("LastModified"),
+        options: FetchOptions {
+            via_proxy: row.get("FetchViaProxy"),
+            user_agent: row
+                .get::<Option<String>, _>("UserAgent")
+                .filter(|user_agent| !user_agent.trim().is_empty()),
+            headers: row
+                .get::<Option<String>, _>("Headers")
+                .filter(|headers| !headers.trim().is_empty()),
+            timeout: match row.get::<i64, _>("Timeout") {
+                seconds if seconds > 0 => Duration::from_secs(seconds as u64),
+                _ => DEFAULT_TIMEOUT,
+            },
+            allow_insecure: row.get("AllowInsecure"),
+        },
+    })
+}
+
+/// The fetch options of a subscription and the user it belongs to.
+pub async fn fetch_options(
+    pool: &SqlitePool,
+    subscription_id: &str,
+) -> Result<(String, FetchOptions), String> {
+    let subscription = load(pool, subscription_id).await?;
+    Ok((subscription.user_id, subscription.options))
+}
+
+/// Builds the HTTP client for `options`. Going through the proxy needs
+/// v2ray-core running, otherwise the socks inbound does not answer.
+pub async fn client(
+    app: &AppHandle,
+    pool: &SqlitePool,
+    user_id: &str,
+    options: &FetchOptions,
+) -> Result<reqwest::Client, String> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    if let Some(extra) = &options.headers {
+        let extra: HashMap<String, String> = serde_json::from_str(extra)
+            .map_err(|e| format!("Invalid subscription headers: {}", e))?;
+        for (name, value) in extra {
+            let name = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| format!("Invalid header name {}: {}", name, e))?;
+            let value = reqwest::header::HeaderValue::from_str(&value)
+                .map_err(|e| format!("Invalid value of header {}: {}", name, e))?;
+            headers.insert(name, value);
+        }
+    }
+
+    let mut builder = reqwest::Client::builder()
+        .timeout(options.timeout)
+        .user_agent(options.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT))
+        .default_headers(headers)
+        .danger_accept_invalid_certs(options.allow_insecure);
+    if options.via_proxy {
+        if !service_state::current(app).is_active() {
+            return Err("Fetching through the proxy needs v2ray-core running".to_string());
+        }
+        let socks_port: i64 = sqlx::query_scalar(
+            "SELECT Port FROM Inbounds WHERE UserID = ? AND Tag = 'socks-inbound'",
+        )
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("Failed to fetch socks port: {}", e))?
+        .ok_or("No socks inbound found for this user")?;
+        // socks5h lets the endpoint resolve the subscription host too
+        builder = builder.proxy(
+            reqwest::Proxy::all(format!("socks5h://127.0.0.1:{}", socks_port))
+                .map_err(|e| format!("Failed to configure proxy: {}", e))?,
+        );
+    }
+    builder
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))
+}
+
+/// Fetches `subscription`, conditionally unless `force` is set.
+async fn fetch(
+    app: &AppHandle,
+    pool: &SqlitePool,
+    subscription: &Subscription,
+    force: bool,
+) -> Result<Fetched, String> {
+    let client = client(app, pool, &subscription.user_id, &subscription.options).await?;
+
+    let mut request = client.get(&subscription.url);
+    if !force {
+        if let Some(etag) = &subscription.etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &subscription.last_modified {
+            request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    let response = request
+        .send()
+        .await
+        .map_err(|e| format!("Failed to fetch subscription: {}", e))?;
+
+    let not_modified = response.status() == reqwest::StatusCode::NOT_MODIFIED;
+    if !not_modified && !response.status().is_success() {
+        return Err(format!(
+            "Failed to fetch subscription: HTTP status {}",
+            response.status()
+        ));
+    }
+
+    let header = |name: reqwest::header::HeaderName| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string())
+    };
+    let etag = header(reqwest::header::ETAG);
+    let last_modified = header(reqwest::header::LAST_MODIFIED);
+    // 304 responses may carry fresh usage too
+    let userinfo = header(reqwest::header::HeaderName::from_static(
+        "subscription-userinfo",
+    ))
+    .and_then(|userinfo| parse_userinfo(&userinfo));
+    if not_modified {
+        return Ok(Fetched {
+            body: None,
+            etag,
+            last_modified,
+            userinfo,
+        });
+    }
+
+    let body = response
+        .text()
+        .await
+        .map_err(|e| format!("Failed to read response body: {}", e))?;
+    if body.trim().is_empty() {
+        return Err("Subscription data is empty".to_string());
+    }
+    Ok(Fetched {
+        body: Some(body),
+        etag,
+        last_modified,
+        userinfo,
+    })
+}
+
+/// Creates the subscription's group when it does not exist yet, the same way
+/// the subscription dialog does.
+async fn ensure_group(pool: &SqlitePool, subscription: &Subscription) -> Result<(), String> {
+    let exists: Option<String> =
+        sqlx::query_scalar("SELECT GroupID FROM EndpointsGroups WHERE GroupID = ?")
+            .bind(&subscription.group_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| format!("Failed to fetch endpoint group: {}", e))?;
+    if exists.is_some() {
+        return Ok(());
+    }
+
+    let name = if subscription.remark.is_empty() {
+        "Subscription Group"
+    } else {
+        subscription.remark.as_str()
+    };
+    sqlx::query(
+        "INSERT INTO EndpointsGroups (GroupID, UserID, GroupName, Remark, Link, SubscriptionID, SpeedTestType)
+         VALUES (?, ?, ?, ?, ?, ?, 'ping')",
+    )
+    .bind(&subscription.group_id)
+    .bind(&subscription.user_id)
+    .bind(name)
+    .bind(name)
+    .bind(&subscription.url)
+    .bind(&subscription.subscription_id)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to create endpoint group: {}", e))?;
+    Ok(())
+}
+
+/// Fetches a subscription and syncs its group. The outcome is recorded on
+/// the subscription; validators are only stored once the import succeeded,
+/// so a failed import is retried with a full fetch.
+pub async fn update(
+    app: &AppHandle,
+    pool: &SqlitePool,
+    subscription_id: &str,
+    force: bool,
+) -> Result<SubscriptionUpdate, String> {
+    let subscription = load(pool, subscription_id).await?;
+
+    let result: Result<(Fetched, Option<ImportResult>), String> = async {
+        let fetched = fetch(app, pool, &subscription, force).await?;
+        if let Some(userinfo) = &fetched.userinfo {
+            store_userinfo(pool, &subscription.url, userinfo).await?;
+        }
+        let Some(body) = &fetched.body else {
+            return Ok((fetched, None));
+        };
+        ensure_group(pool, &subscription).await?;
+        let import = share_link::import_into_group(pool, &subscription.group_id, body).await?;
+        Ok((fetched, Some(import)))
+    }
+    .await;
+
+    let checked_at = chrono::Utc::now().timestamp();
+    let recorded = match &result {
+        Ok((fetched, Some(_))) => {
+            sqlx::query(
+                "UPDATE Subscriptions SET ETag = ?, LastModified = ?, LastCheckedAt = ?, LastError = NULL
+                 WHERE SubscriptionID = ?",
+            )
+            .bind(&fetched.etag)
+            .bind(&fetched.last_modified)
+            .bind(checked_at)
+            .bind(subscription_id)
+            .execute(pool)
+            .await
+        }
+        Ok((_, None)) => {
+            sqlx::query(
+                "UPDATE Subscriptions SET LastCheckedAt = ?, LastError = NULL WHERE SubscriptionID = ?",
+            )
+            .bind(checked_at)
+            .bind(subscription_id)
+            .execute(pool)
+            .await
+        }
+        Err(e) => {
+            sqlx::query(
+                "UPDATE Subscriptions SET LastCheckedAt = ?, LastError = ? WHERE SubscriptionID = ?",
+            )
+            .bind(checked_at)
+            .bind(e)
+            .bind(subscription_id)
+            .execute(pool)
+            .await
+        }
+    };
+    recorded.map_err(|e| format!("Failed to update subscription: {}", e))?;
+
+    let (_, import) = result?;
+    Ok(SubscriptionUpdate {
+        subscription_id: subscription.subscription_id,
+        not_modified: import.is_none(),
+        import,
+    })
+}
+
+/// Switches the core away from an active endpoint that `update` removed.
+async fn follow_active_move(app: &AppHandle, pool: &SqlitePool, update: &SubscriptionUpdate) {
+    if let Some(import) = &update.import {
+        failover::follow_active_move(app, pool, &import.changes).await;
+    }
+}
+
+/// Updates one subscription. With `force`, the provider's validators are
+/// ignored and the subscription is always re-imported.
+#[tauri::command]
+pub async fn update_subscription(
+    app: AppHandle,
+    subscription_id: String,
+    force: bool,
+) -> Result<SubscriptionUpdate, String> {
+    let database_path = utils::get_database_path(&app).to_string_lossy().to_string();
+    let database_url = format!("sqlite://{}", database_path);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url)
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+
+    let update = update(&app, &pool, &subscription_id, force).await;
+    if let Ok(update) = &update {
+        follow_active_move(&app, &pool, update).await;
+    }
+    notify_warnings(&app, &pool).await;
+    update
+}
+
+/// Updates every subscription of `user_id`. Failures are recorded on the
+/// subscriptions and do not stop the others.
+#[tauri::command]
+pub async fn update_all_subscriptions(
+    app: AppHandle,
+    user_id: String,
+    force: bool,
+) -> Result<Vec<SubscriptionUpdate>, String> {
+    let database_path = utils::get_database_path(&app).to_string_lossy().to_string();
+    let database_url = format!("sqlite://{}", database_path);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url)
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+
+    let subscription_ids: Vec<String> =
+        sqlx::query_scalar("SELECT SubscriptionID FROM Subscriptions WHERE UserID = ?")
+            .bind(&user_id)
+            .fetch_all(&pool)
+            .await
+            .map_err(|e| format!("Failed to fetch subscriptions: {}", e))?;
+
+    let mut updates = Vec::new();
+    for subscription_id in subscription_ids {
+        match update(&app, &pool, &subscription_id, force).await {
+            Ok(update) => {
+                follow_active_move(&app, &pool, &update).await;
+                updates.push(update);
+            }
+            Err(e) => warn!("Failed to update subscription {}: {}", subscription_id, e),
+        }
+    }
+    notify_warnings(&app, &pool).await;
+    Ok(updates)
+}
+
+/// Subscriptions of the logged-in user whose update interval has passed.
+async fn due_subscriptions(pool: &SqlitePool) -> Result<Vec<(String, String)>, String> {
+    let now = chrono::Utc::now().timestamp();
+    let rows = sqlx::query(
+        "SELECT sub.SubscriptionID, sub.UserID
+         FROM Subscriptions sub
+         JOIN AppStatus s ON s.UserID = sub.UserID
+         WHERE s.LoginState = 1 AND sub.UpdateInterval > 0
+           AND (sub.LastCheckedAt IS NULL OR sub.LastCheckedAt + sub.UpdateInterval * 60 <= ?)",
+    )
+    .bind(now)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to fetch scheduled subscriptions: {}", e))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.get("SubscriptionID"), row.get("UserID")))
+        .collect())
+}
+
+/// Starts the update scheduler for the lifetime of the app.
+pub fn start(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let database_path = utils::get_database_path(&app).to_string_lossy().to_string();
+        let database_url = format!("sqlite://{}", database_path);
+        let pool = match SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(&database_url)
+            .await
+        {
+            Ok(pool) => pool,
+            Err(e) => {
+                warn!("Failed to start subscription scheduler: {}", e);
+                return;
+            }
+        };
+
+        let mut ticker = tokio::time::interval(TICK_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+
+            let subscriptions = match due_subscriptions(&pool).await {
+                Ok(subscriptions) => subscriptions,
+                Err(e) => {
+                    warn!("{}", e);
+                    continue;
+                }
+            };
+
+            // Usage may change even when the links did not
+            let mut checked_users = Vec::new();
+            let mut changed = false;
+            for (subscription_id, user_id) in subscriptions {
+                match update(&app, &pool, &subscription_id, false).await {
+                    Ok(update) => {
+                        follow_active_move(&app, &pool, &update).await;
+                        if update.not_modified {
+                            info!("Subscription {} not modified", subscription_id);
+                        } else {
+                            info!("Subscription {} updated", subscription_id);
+                            changed = true;
+                        }
+                        if !checked_users.contains(&user_id) {
+                            checked_users.push(user_id);
+                        }
+                    }
+                    Err(e) => warn!("Failed to update subscription {}: {}", subscription_id, e),
+                }
+            }
+
+            // Expiry warnings are due with time alone, so they are checked
+            // on every tick
+            notify_warnings(&app, &pool).await;
+
+            for user_id in checked_users {
+                if let Some(tray_manager) = app.try_state::<SystemTrayManager>() {
+                    tray_manager.update_menu(&app, user_id).await;
+                }
+            }
+            if changed {
+                let _ = app.emit("refresh", "endpoints");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_userinfo() {
+        let userinfo = parse_userinfo(
+            "upload=455727941; download=6174315083; total=1073741824000; expire=1767225600",
+        )
+        .unwrap();
+        assert_eq!(userinfo.upload, Some(455727941));
+        assert_eq!(userinfo.download, Some(6174315083));
+        assert_eq!(userinfo.total, Some(1073741824000));
+        assert_eq!(userinfo.expire, Some(1767225600));
+    }
+
+    #[test]
+    fn parses_float_userinfo() {
+        let userinfo =
+            parse_userinfo("upload=1.5e9; download=2048.75; total=1.073741824E12").unwrap();
+        assert_eq!(userinfo.upload, Some(1_500_000_000));
+        assert_eq!(userinfo.download, Some(2048));
+        assert_eq!(userinfo.total, Some(1_073_741_824_000));
+        assert_eq!(userinfo.expire, None);
+    }
+
+    #[test]
+    fn zero_expire_means_no_expiry() {
+        let userinfo = parse_userinfo("upload=0; download=0; total=0; expire=0").unwrap();
+        assert_eq!(userinfo.upload, Some(0));
+        assert_eq!(userinfo.total, Some(0));
+        assert_eq!(userinfo.expire, None);
+    }
+
+    #[test]
+    fn skips_junk_userinfo_parts() {
+        let userinfo =
+            parse_userinfo("upload=abc; plan=pro; download; =3;  Download = 5 ;total=").unwrap();
+        assert_eq!(userinfo.upload, None);
+        assert_eq!(userinfo.download, Some(5));
+        assert_eq!(userinfo.total, None);
+        assert_eq!(userinfo.expire, None);
+
+        assert!(parse_userinfo("").is_none());
+        assert!(parse_userinfo("plan=pro; upload=none").is_none());
+    }
+
+    #[test]
+    fn formats_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(-5), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1024), "1.0 KB");
+        assert_eq!(format_bytes(1536), "1.5 KB");
+        assert_eq!(format_bytes(10 * 1024 * 1024 * 1024), "10.0 GB");
+        // TB is the largest unit
+        assert_eq!(format_bytes(5 * 1024_i64.pow(5)), "5120.0 TB");
+    }
+}
@@ -0,0 +1,328 @@
+//! Per-subscription filter, rename and dedupe rules.
+//!
+//! Applied at import time to the parsed entries before they are synced into
+//! the group, in this order: entries whose remark or protocol does not pass
+//! the include and exclude patterns are dropped ("Expire: 2026-01-01",
+//! "Traffic left" and the like), then with `Dedupe` the entries whose server
+//! and credentials (see [`share_link::identity`]) already appear earlier in
+//! the list or in another group of the user, and the rest are renamed with
+//! the template. Every dropped entry is reported.
+//!
+//! Templates may use `{remark}`, `{group}`, `{protocol}`, `{address}`,
+//! `{port}` and `{index}`, the 1-based position among the kept entries.
+
+use regex::Regex;
+use sqlx::sqlite::{SqlitePool, SqliteRow};
+use sqlx::Row;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::share_link::{self, ShareLink};
+
+/// Protocol, address, port and credentials, see [`share_link::identity`].
+type Identity = (&'static str, String, u16, String);
+
+static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+
+pub struct Rules {
+    user_id: String,
+    include_remarks: Option<Regex>,
+    exclude_remarks: Option<Regex>,
+    include_protocols: Option<Regex>,
+    exclude_protocols: Option<Regex>,
+    rename_template: Option<String>,
+    dedupe: bool,
+}
+
+fn identity(share_link: &ShareLink) -> Identity {
+    let (protocol, address, port, credential) = share_link::identity(share_link);
+    (
+        protocol,
+        address.to_ascii_lowercase(),
+        port,
+        credential.to_string(),
+    )
+}
+
+fn pattern(row: &SqliteRow, column: &str) -> Result<Option<Regex>, String> {
+    match row.get::<Option<String>, _>(column) {
+        Some(pattern) if !pattern.trim().is_empty() => Regex::new(&pattern)
+            .map(Some)
+            .map_err(|e| format!("Invalid {} pattern: {}", column, e)),
+        _ => Ok(None),
+    }
+}
+
+/// Loads the rules of the subscription that owns `group_id`. `None` for
+/// groups that do not belong to a subscription.
+pub async fn load(pool: &SqlitePool, group_id: &str) -> Result<Option<Rules>, String> {
+    let row = sqlx::query(
+        "SELECT UserID, IncludeRemarks, ExcludeRemarks, IncludeProtocols, ExcludeProtocols,
+         RenameTemplate, Dedupe
+         FROM Subscriptions WHERE GroupID = ?",
+    )
+    .bind(group_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("Failed to fetch subscription rules: {}", e))?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+
+    Ok(Some(Rules {
+        user_id: row.get("UserID"),
+        include_remarks: pattern(&row, "IncludeRemarks")?,
+        exclude_remarks: pattern(&row, "ExcludeRemarks")?,
+        include_protocols: pattern(&row, "IncludeProtocols")?,
+        exclude_protocols: pattern(&row, "ExcludeProtocols")?,
+        rename_template: row
+            .get::<Option<String>, _>("RenameTemplate")
+            .filter(|template| !template.trim().is_empty()),
+        dedupe: row.get("Dedupe"),
+    }))
+}
+
+impl Rules {
+    /// Why `share_link` is filtered out, if it is.
+    fn rejection(&self, share_link: &ShareLink) -> Option<&'static str> {
+        let protocol = share_link.outbound.protocol();
+        if self
+            .include_remarks
+            .as_ref()
+            .is_some_and(|include| !include.is_match(&share_link.remark))
+        {
+            return Some("remark not included");
+        }
+        if self
+            .exclude_remarks
+            .as_ref()
+            .is_some_and(|exclude| exclude.is_match(&share_link.remark))
+        {
+            return Some("remark excluded");
+        }
+        if self
+            .include_protocols
+            .as_ref()
+            .is_some_and(|include| !include.is_match(protocol))
+        {
+            return Some("protocol not included");
+        }
+        if self
+            .exclude_protocols
+            .as_ref()
+            .is_some_and(|exclude| exclude.is_match(protocol))
+        {
+            return Some("protocol excluded");
+        }
+        None
+    }
+
+    /// Servers already present in the other groups of the user, with the
+    /// name of the group they are in.
+    async fn other_groups(
+        &self,
+        pool: &SqlitePool,
+        group_id: &str,
+    ) -> Result<HashMap<Identity, String>, String> {
+        let rows = sqlx::query(
+            "SELECT e.EndpointID, g.GroupName
+             FROM Endpoints e
+             JOIN EndpointsGroups g ON e.GroupID = g.GroupID
+             WHERE g.UserID = ? AND g.GroupID != ?",
+        )
+        .bind(&self.user_id)
+        .bind(group_id)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Failed to fetch endpoints: {}", e))?;
+
+        let mut identities = HashMap::new();
+        for row in rows {
+            let endpoint_id: String = row.get("EndpointID");
+            // Endpoints that cannot be read back cannot be duplicates either
+            if let Ok(share_link) = share_link::load_endpoint(pool, &endpoint_id).await {
+                identities
+                    .entry(identity(&share_link))
+                    .or_insert_with(|| row.get("GroupName"));
+            }
+        }
+        Ok(identities)
+    }
+
+    fn rename(
+        &self,
+        template: &str,
+        share_link: &ShareLink,
+        group_name: &str,
+        index: usize,
+    ) -> String {
+        let placeholder = PLACEHOLDER
+            .get_or_init(|| Regex::new(r"\{(remark|group|protocol|address|port|index)\}").unwrap());
+        // In one pass, so placeholders in a remark or group name stay as they are
+        placeholder
+            .replace_all(template, |captures: &regex::Captures| match &captures[1] {
+                "remark" => share_link.remark.clone(),
+                "group" => group_name.to_string(),
+                "protocol" => share_link.outbound.protocol().to_string(),
+                "address" => share_link.outbound.address().to_string(),
+                "port" => share_link.outbound.port().to_string(),
+                _ => index.to_string(),
+            })
+            .trim()
+            .to_string()
+    }
+
+    /// Applies the rules to the entries of `group_id`, returning the kept
+    /// entries and a message per dropped one.
+    pub async fn apply(
+        &self,
+        pool: &SqlitePool,
+        group_id: &str,
+        group_name: &str,
+        share_links: Vec<ShareLink>,
+    ) -> Result<(Vec<ShareLink>, Vec<String>), String> {
+        let mut seen = if self.dedupe {
+            self.other_groups(pool, group_id).await?
+        } else {
+            HashMap::new()
+        };
+
+        let mut kept = Vec::new();
+        let mut filtered = Vec::new();
+        for mut share_link in share_links {
+            if let Some(reason) = self.rejection(&share_link) {
+                filtered.push(format!("{}: {}", share_link.remark, reason));
+                continue;
+            }
+            if self.dedupe {
+                let key = identity(&share_link);
+                if let Some(other) = seen.get(&key) {
+                    filtered.push(format!(
+                        "{}: duplicate of an endpoint in {}",
+                        share_link.remark, other
+                    ));
+                    continue;
+                }
+                seen.insert(key, group_name.to_string());
+            }
+            if let Some(template) = &self.rename_template {
+                let remark = self.rename(template, &share_link, group_name, kept.len() + 1);
+                if !remark.is_empty() {
+                    share_link.remark = remark;
+                }
+            }
+            kept.push(share_link);
+        }
+        Ok((kept, filtered))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_rules() -> Rules {
+        Rules {
+            user_id: "user".to_string(),
+            include_remarks: None,
+            exclude_remarks: None,
+            include_protocols: None,
+            exclude_protocols: None,
+            rename_template: None,
+            dedupe: false,
+        }
+    }
+
+    fn entry(link: &str) -> ShareLink {
+        share_link::parse(link).unwrap()
+    }
+
+    fn regex(pattern: &str) -> Option<Regex> {
+        Some(Regex::new(pattern).unwrap())
+    }
+
+    #[test]
+    fn filters_on_remark_and_protocol() {
+        let trojan = entry("trojan://secret@jp.example.com:443#Japan%2001");
+        let ss = entry("ss://YWVzLTI1Ni1nY206c2VjcmV0@us.example.com:8388#US%2001");
+        let expiry = entry("trojan://secret@0.0.0.0:1#Expire%3A%202026-01-01");
+
+        let rules = Rules {
+            include_remarks: regex("(?i)japan|us"),
+            ..no_rules()
+        };
+        assert_eq!(rules.rejection(&trojan), None);
+        assert_eq!(rules.rejection(&expiry), Some("remark not included"));
+
+        let rules = Rules {
+            exclude_remarks: regex("^Expire"),
+            ..no_rules()
+        };
+        assert_eq!(rules.rejection(&ss), None);
+        assert_eq!(rules.rejection(&expiry), Some("remark excluded"));
+
+        let rules = Rules {
+            include_protocols: regex("^(trojan|vless)$"),
+            ..no_rules()
+        };
+        assert_eq!(rules.rejection(&trojan), None);
+        assert_eq!(rules.rejection(&ss), Some("protocol not included"));
+
+        let rules = Rules {
+            exclude_protocols: regex("shadowsocks"),
+            ..no_rules()
+        };
+        assert_eq!(rules.rejection(&trojan), None);
+        assert_eq!(rules.rejection(&ss), Some("protocol excluded"));
+    }
+
+    #[test]
+    fn renames_with_every_placeholder() {
+        let share_link = entry("trojan://secret@jp.example.com:443#Japan");
+        let template = "{group} {index} {remark} {protocol}://{address}:{port} {unknown}";
+        assert_eq!(
+            no_rules().rename(template, &share_link, "Provider", 3),
+            "Provider 3 Japan trojan://jp.example.com:443 {unknown}"
+        );
+        assert_eq!(
+            no_rules().rename("  {remark}  ", &share_link, "Provider", 1),
+            "Japan"
+        );
+    }
+
+    #[test]
+    fn does_not_expand_placeholders_in_values() {
+        let share_link = entry("trojan://secret@jp.example.com:443#%7Bgroup%7D%20%7Bindex%7D");
+        assert_eq!(
+            no_rules().rename("{remark} / {group}", &share_link, "{address}", 1),
+            "{group} {index} / {address}"
+        );
+    }
+
+    #[tokio::test]
+    async fn dedupes_within_the_list() {
+        let pool = crate::migrations::memory_pool().await;
+        let rules = Rules {
+            rename_template: Some("{index}. {remark}".to_string()),
+            dedupe: true,
+            ..no_rules()
+        };
+        let share_links = vec![
+            entry("trojan://secret@jp.example.com:443#Japan"),
+            // Same server and password, other remark, transport and address case
+            entry("trojan://secret@JP.example.com:443?type=ws#Japan%20WS"),
+            entry("trojan://other@jp.example.com:443#Japan%202"),
+            entry("trojan://secret@jp.example.com:8443#Japan%203"),
+        ];
+        let (kept, filtered) = rules
+            .apply(&pool, "group", "Provider", share_links)
+            .await
+            .unwrap();
+        let remarks: Vec<_> = kept
+            .iter()
+            .map(|share_link| share_link.remark.as_str())
+            .collect();
+        assert_eq!(remarks, ["1. Japan", "2. Japan 2", "3. Japan 3"]);
+        assert_eq!(filtered, ["Japan WS: duplicate of an endpoint in Provider"]);
+    }
+}
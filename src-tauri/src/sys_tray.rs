@@ -5,11 +5,13 @@ use sqlx::sqlite::SqliteRow;
 use sqlx::Row;
 use std::env;
 use std::sync::Mutex;
+use std::time::Duration;
 use tauri::menu::{
     IsMenuItem, Menu, MenuBuilder, MenuId, MenuItem, MenuItemBuilder, PredefinedMenuItem,
     SubmenuBuilder,
 };
 use tauri::Emitter;
+use tauri::Listener;
 use tauri::WindowEvent;
 use tauri::{
     tray::{TrayIcon, TrayIconBuilder},
@@ -18,10 +20,16 @@ use tauri::{
 use tauri_plugin_clipboard_manager::ClipboardExt;
 use tauri_plugin_opener::OpenerExt;
 use tauri_plugin_os;
+use tokio::sync::{mpsc, oneshot};
 
 use crate::proxy;
 use crate::proxy::unset_global_proxy;
 use crate::proxy::unset_pac_proxy;
+use crate::qr_code;
+use crate::service_state::ServiceState;
+use crate::share_link;
+use crate::subscription;
+use crate::traffic;
 use crate::v2ray_core;
 use crate::v2ray_core::DaemonState;
 use std::sync::Arc;
@@ -46,6 +54,7 @@ pub enum TrayState {
 pub struct SystemTrayManager {
     tray_handle: TrayIcon,
     tray_state: Mutex<TrayState>,
+    speed_monitor_tx: Mutex<Option<oneshot::Sender<()>>>,
 }
 
 impl SystemTrayManager {
@@ -94,25 +103,11 @@ impl SystemTrayManager {
                 tauri::async_runtime::block_on(async {
 	                let tray_manager = app.state::<SystemTrayManager>();
                 	println!("endpoint: {}", &endpoint_id);
-	                let daemon_state = app.state::<Arc<Mutex<DaemonState>>>();
-	                if let Err(e) = v2ray_core::stop_daemon(daemon_state, main_window).await {
-	                    error!("Failed to start daemon: {}", e);
+	                match v2ray_core::switch_endpoint(app.app_handle().clone(), endpoint_id.clone(), user_id.clone()).await {
+	                    Ok(true) => info!("Endpoint {} hot-swapped on the running core", endpoint_id),
+	                    Ok(false) => info!("Endpoint {} selected", endpoint_id),
+	                    Err(e) => error!("Failed to switch endpoint: {}", e),
 	                }
-	                let success = v2ray_core::inject_config(app.app_handle().clone(), endpoint_id.clone(), user_id.clone()).await;
-	                if !success {
-	                    error!("Failed to inject_config");
-	                }
-					sqlx::query("UPDATE AppStatus SET ServiceRunningState = 0 WHERE UserID = ?;")
-						.bind(&user_id)
-						.execute(&pool)
-						.await
-						.expect("Failed to update service running state");
-					sqlx::query("UPDATE Endpoints SET Active = CASE WHEN EndpointID = ? THEN 1 ELSE 0 END WHERE Active = 1 OR EndpointID = ?;")
-				        .bind(&endpoint_id)
-						.bind(&endpoint_id)
-						.execute(&pool)
-						.await
-						.expect("Failed to update service running state");
 					tray_manager
 						.update_menu(&app, user_id.clone())
 						.await;
@@ -156,11 +151,6 @@ impl SystemTrayManager {
 							if let Err(e) = v2ray_core::stop_daemon(daemon_state, main_window).await {
                                 error!("Failed to start daemon: {}", e);
                             }
-                            sqlx::query("UPDATE AppStatus SET ServiceRunningState = 0 WHERE UserID = ?;")
-								.bind(&user_id)
-								.execute(&pool)
-								.await
-								.expect("Failed to update service running state");
 		                    app.exit(0);
 	                    });
                		 }
@@ -179,52 +169,20 @@ impl SystemTrayManager {
                                 TrayState::Running => {
                                     // Stop the daemon
                                     let daemon_state = app.state::<Arc<Mutex<DaemonState>>>();
+                                    // The service state machine updates the database, menu and tray state
                                     if let Err(e) = v2ray_core::stop_daemon(daemon_state, main_window).await {
                                         error!("Failed to stop daemon: {}", e);
                                     }
-                                    
-                                    // Update database first
-                                    sqlx::query("UPDATE AppStatus SET ServiceRunningState = 0 WHERE UserID = ?;")
-                                        .bind(&user_id)
-                                        .execute(&pool)
-                                        .await
-                                        .expect("Failed to update service running state");
-
-                                    // Update the menu (reads from database, so it will show the updated state)
-                                    tray_manager
-                                        .update_menu(&app, user_id.clone())
-                                        .await;
-                                    
-                                    // Update in-memory state after menu update
-                                    let mut tray_state = tray_manager.tray_state.lock().unwrap();
-                                    *tray_state = TrayState::Paused;
-                                    
                                     app.emit("refresh","endpoints").unwrap();
                                 }
                                 TrayState::Paused => {
                                     // Start the daemon
                                     let daemon_state = app.state::<Arc<Mutex<DaemonState>>>();
                                     let window = app.get_webview_window("main").unwrap();
+                                    // The service state machine updates the database, menu and tray state
                                     if let Err(e) = v2ray_core::start_daemon(daemon_state, window).await {
                                         error!("Failed to start daemon: {}", e);
                                     }
-                                    
-                                    // Update database first
-                                    sqlx::query("UPDATE AppStatus SET ServiceRunningState = 1 WHERE UserID = ?;")
-                                        .bind(&user_id)
-                                        .execute(&pool)
-                                        .await
-                                        .expect("Failed to update service running state");
-
-                                    // Update the menu (reads from database, so it will show the updated state)
-                                    tray_manager
-                                        .update_menu(&app, user_id.clone())
-                                        .await;
-                                    
-                                    // Update in-memory state after menu update
-                                    let mut tray_state = tray_manager.tray_state.lock().unwrap();
-                                    *tray_state = TrayState::Running;
-                                    
                                     app.emit("refresh","endpoints").unwrap();
                                 }
                             }
@@ -489,14 +447,59 @@ impl SystemTrayManager {
 						main_window.eval("setTimeout(()=>(document.querySelector('[data-import-link-button=\"true\"]'))?.click(),500);").unwrap();
 					}
 					"scan-qr" => {
-                   	    main_window.emit("navigate", "/endpoints?m=screenshot").unwrap();
-                        if main_window.is_visible().unwrap() {
-                            main_window.set_focus().unwrap();
-                        } else {
-                            main_window.show().unwrap();
-                        }
-						// Trigger import screenshot button click after navigation
-						main_window.eval("setTimeout(()=>(document.querySelector('[data-import-screenshot-buttonn=\"true\"]'))?.click(),500);").unwrap();
+						// Decoded and imported natively, the main window may stay hidden
+						let app = app.clone();
+						let user_id = user_id.clone();
+						tauri::async_runtime::spawn(async move {
+							let scanned = tauri::async_runtime::spawn_blocking(qr_code::scan_screens)
+								.await
+								.map_err(|e| format!("Failed to scan screens: {}", e))
+								.and_then(|scanned| scanned);
+							let links: Vec<String> = match scanned {
+								Ok(scanned) => scanned.into_iter().map(|scanned| scanned.link).collect(),
+								Err(e) => {
+									error!("{}", e);
+									Vec::new()
+								}
+							};
+							let body = if links.is_empty() {
+								"No share link QR code found on screen".to_string()
+							} else {
+								match share_link::add_local_endpoints(&app, &user_id, &links).await {
+									Ok(result) => {
+										if let Some(tray_manager) = app.try_state::<SystemTrayManager>() {
+											tray_manager.update_menu(&app, user_id.clone()).await;
+										}
+										if let Err(e) = app.emit("refresh", "endpoints") {
+											error!("Failed to emit refresh event: {}", e);
+										}
+										format!("Imported {} of {} endpoints from QR codes", result.success, links.len())
+									}
+									Err(e) => {
+										error!("Failed to import scanned endpoints: {}", e);
+										format!("Failed to import scanned endpoints: {}", e)
+									}
+								}
+							};
+							if let Err(e) = app.notification().builder().title("V2rayX").body(body).show() {
+								error!("Failed to show notification: {}", e);
+							}
+						});
+					}
+					"share-link" => {
+						let app = app.clone();
+						tauri::async_runtime::spawn(async move {
+							let body = match share_link::copy_active_endpoint(&app).await {
+								Ok(remark) => format!("Share link of {} copied to clipboard", remark),
+								Err(e) => {
+									error!("Failed to share the active endpoint: {}", e);
+									format!("Failed to copy share link: {}", e)
+								}
+							};
+							if let Err(e) = app.notification().builder().title("V2rayX").body(body).show() {
+								error!("Failed to show notification: {}", e);
+							}
+						});
 					}
 					"copy-proxy-cmd" => {
 						tauri::async_runtime::block_on(async {
@@ -585,6 +588,73 @@ impl SystemTrayManager {
         Self {
             tray_handle,
             tray_state,
+            speed_monitor_tx: Mutex::new(None),
+        }
+    }
+
+    /// Shows the proxied upload/download rate in the tray tooltip (and title
+    /// where supported). The rates come from the `traffic-rate` events of the
+    /// traffic collector and are averaged over `interval`.
+    pub fn start_speed_monitor(&self, app_handle: &AppHandle, interval: Duration) {
+        let (shutdown_tx, mut shutdown_rx) = oneshot::channel::<()>();
+        if let Some(tx) = self.speed_monitor_tx.lock().unwrap().replace(shutdown_tx) {
+            let _ = tx.send(());
+        }
+
+        let (rate_tx, mut rate_rx) = mpsc::unbounded_channel::<traffic::TrafficRate>();
+        let listener = app_handle.listen(traffic::TRAFFIC_RATE_EVENT, move |event| {
+            if let Ok(rate) = serde_json::from_str(event.payload()) {
+                let _ = rate_tx.send(rate);
+            }
+        });
+
+        let app_handle = app_handle.clone();
+        let tray_handle = self.tray_handle.clone();
+        tauri::async_runtime::spawn(async move {
+            let (mut uplink, mut downlink, mut samples) = (0, 0, 0);
+            let mut ticker =
+                tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+            loop {
+                tokio::select! {
+                    _ = &mut shutdown_rx => break,
+                    Some(rate) = rate_rx.recv() => {
+                        uplink += rate.uplink;
+                        downlink += rate.downlink;
+                        samples += 1;
+                    }
+                    _ = ticker.tick() => {
+                        if samples == 0 {
+                            continue;
+                        }
+                        let up_rate = uplink as f64 / samples as f64;
+                        let down_rate = downlink as f64 / samples as f64;
+                        let label = format!("↑ {}  ↓ {}", format_rate(up_rate), format_rate(down_rate));
+                        let _ = tray_handle.set_tooltip(Some(format!("V2rayX\n{}", label)));
+                        #[cfg(any(target_os = "macos", target_os = "linux"))]
+                        let _ = tray_handle.set_title(Some(label));
+                        (uplink, downlink, samples) = (0, 0, 0);
+                    }
+                }
+            }
+
+            app_handle.unlisten(listener);
+            let _ = tray_handle.set_tooltip(Some("V2rayX"));
+            #[cfg(any(target_os = "macos", target_os = "linux"))]
+            let _ = tray_handle.set_title(None::<&str>);
+        });
+    }
+
+    pub fn set_service_state(&self, state: ServiceState) {
+        *self.tray_state.lock().unwrap() = if state.is_active() {
+            TrayState::Running
+        } else {
+            TrayState::Paused
+        };
+    }
+
+    pub fn stop_speed_monitor(&self) {
+        if let Some(tx) = self.speed_monitor_tx.lock().unwrap().take() {
+            let _ = tx.send(());
         }
     }
 
@@ -868,12 +938,37 @@ pub async fn create_tray_menu(app: AppHandle, user_id: &str) -> Menu<Wry> {
             .build(&app)
             .unwrap();
 
-        let endpoint_rows = sqlx::query(
-            "SELECT Endpoints.EndpointID, Endpoints.Remark, Endpoints.Active, Endpoints.Latency
+        // Remaining quota and expiry of subscriptions that report them
+        let subscription_usages = subscription::usage(&pool, user_id)
+            .await
+            .unwrap_or_else(|e| {
+                error!("{}", e);
+                Vec::new()
+            });
+
+        let endpoint_sort: String =
+            sqlx::query_scalar("SELECT TrayEndpointSort FROM AppSettings WHERE UserID = ?")
+                .bind(user_id)
+                .fetch_optional(&pool)
+                .await
+                .unwrap_or(None)
+                .unwrap_or_else(|| "none".to_string());
+        // Untested endpoints go last either way
+        let order_by = match endpoint_sort.as_str() {
+            "latency" => {
+                "ORDER BY CAST(Endpoints.Latency AS INTEGER) IS NULL, CAST(Endpoints.Latency AS INTEGER)"
+            }
+            "throughput" => "ORDER BY Endpoints.Throughput IS NULL, Endpoints.Throughput DESC",
+            _ => "",
+        };
+        let endpoint_rows = sqlx::query(&format!(
+            "SELECT Endpoints.EndpointID, Endpoints.Remark, Endpoints.Active, Endpoints.Latency, Endpoints.Throughput
              FROM Endpoints
              JOIN EndpointsGroups ON Endpoints.GroupID = EndpointsGroups.GroupID
-             WHERE EndpointsGroups.UserID = ?",
-        )
+             WHERE EndpointsGroups.UserID = ?
+             {}",
+            order_by
+        ))
         .bind(&user_id)
         .fetch_all(&pool)
         .await
@@ -896,6 +991,7 @@ pub async fn create_tray_menu(app: AppHandle, user_id: &str) -> Menu<Wry> {
                 let mut remark: String = row.get("Remark");
                 let is_active: i32 = row.get("Active");
                 let latency: Option<String> = row.get("Latency");
+                let throughput: Option<i64> = row.get("Throughput");
                 let is_disabled: bool = is_active != 1;
 
                 if is_active == 1 {
@@ -910,6 +1006,10 @@ pub async fn create_tray_menu(app: AppHandle, user_id: &str) -> Menu<Wry> {
                     }
                 }
 
+                if let Some(throughput) = throughput {
+                    remark = format!("{} [{}]", remark, format_rate(throughput as f64));
+                }
+
                 let menu_item = MenuItemBuilder::new(&remark)
                     .enabled(is_disabled)
                     .id(MenuId::new(&format!("endpoint:{}", endpoint_id)))
@@ -942,6 +1042,24 @@ pub async fn create_tray_menu(app: AppHandle, user_id: &str) -> Menu<Wry> {
             .build(&app)
             .unwrap();
 
+        let subscription_usage: Vec<MenuItem<Wry>> = subscription_usages
+            .iter()
+            .map(|usage| {
+                MenuItemBuilder::new(format!("    {}", usage.label()))
+                    .id(MenuId::new(format!(
+                        "subscription-usage:{}",
+                        usage.subscription_id
+                    )))
+                    .enabled(false)
+                    .build(&app)
+                    .expect("Failed to build subscription usage menu item")
+            })
+            .collect();
+        let subscription_usage_refs: Vec<&dyn IsMenuItem<Wry>> = subscription_usage
+            .iter()
+            .map(|item| item as &dyn IsMenuItem<Wry>)
+            .collect();
+
         let configure_pac_settings = MenuItemBuilder::new("PAC Settings...".to_string())
             .id("configure-pac-settings")
             .build(&app)
@@ -963,7 +1081,6 @@ pub async fn create_tray_menu(app: AppHandle, user_id: &str) -> Menu<Wry> {
             .unwrap();
 
         let share_link = MenuItemBuilder::new("Share Link/QR Code".to_string())
-            .enabled(false)
             .id("share-link")
             .build(&app)
             .unwrap();
@@ -1016,7 +1133,10 @@ pub async fn create_tray_menu(app: AppHandle, user_id: &str) -> Menu<Wry> {
                 &endpoints_submenu,
                 &configure_endpoints,
                 &configure_subscriptions,
-                &configure_pac_settings,
+            ])
+            .items(&subscription_usage_refs)
+            .items(&[
+                &configure_pac_settings as &dyn IsMenuItem<Wry>,
                 &connection_test,
                 &menu_divider as &dyn IsMenuItem<Wry>,
                 &import_endpoint,
@@ -1038,6 +1158,21 @@ pub async fn create_tray_menu(app: AppHandle, user_id: &str) -> Menu<Wry> {
     menu
 }
 
+fn format_rate(bytes_per_sec: f64) -> String {
+    const UNITS: [&str; 4] = ["B/s", "KB/s", "MB/s", "GB/s"];
+    let mut value = bytes_per_sec;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{:.0} {}", value, UNITS[unit])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
 #[tauri::command]
 pub async fn tray_update(app: AppHandle, user_id: String) -> Result<(), String> {
     // Check if SystemTrayManager is available
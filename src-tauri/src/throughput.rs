@@ -0,0 +1,342 @@
+//! Download throughput per endpoint.
+//!
+//! Latency says little about how fast a server moves a large download. This
+//! test fetches `ThroughputTestUrl` through each endpoint via a probe core
+//! (see [`ProbeCore`]) until `ThroughputTestDuration` seconds have passed or
+//! `ThroughputTestMaxBytes` have arrived. Endpoints are tested one after the
+//! other so they do not compete for the same link. Results are stored in
+//! `Endpoints.Throughput` as bytes per second, which the tray can sort by.
+//! Like real URL latency tests, endpoints share one probe core per batch of
+//! [`PROBE_BATCH_SIZE`] so large groups do not open one inbound each at once.
+
+use chrono::Utc;
+use log::{info, warn};
+use reqwest::Client;
+use serde::Serialize;
+use sqlx::sqlite::SqlitePoolOptions;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::sync::watch;
+
+use crate::sys_tray::SystemTrayManager;
+use crate::utils;
+use crate::v2ray_core::probe::ProbeCore;
+
+pub const THROUGHPUT_PROGRESS_EVENT: &str = "throughput-progress";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// Endpoints sharing one probe core, as for real URL latency tests
+const PROBE_BATCH_SIZE: usize = 64;
+
+pub struct ThroughputTestState {
+    cancel_tx: Mutex<Option<watch::Sender<bool>>>,
+}
+
+impl ThroughputTestState {
+    pub fn new() -> Self {
+        ThroughputTestState {
+            cancel_tx: Mutex::new(None),
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThroughputResult {
+    pub endpoint_id: String,
+    /// Bytes per second, `None` when the download failed
+    pub throughput: Option<i64>,
+    pub bytes: u64,
+    pub elapsed_ms: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThroughputProgress {
+    pub result: ThroughputResult,
+    pub completed: usize,
+    pub total: usize,
+}
+
+/// Builds an HTTP client that connects through the socks port of a probe.
+fn proxied_client(socks_port: u16) -> Result<Client, String> {
+    let proxy = reqwest::Proxy::all(format!("socks5h://127.0.0.1:{}", socks_port))
+        .map_err(|e| format!("Failed to configure proxy: {}", e))?;
+    Client::builder()
+        .proxy(proxy)
+        .connect_timeout(CONNECT_TIMEOUT)
+        .build()
+        .map_err(|e| format!("Failed to build HTTP client: {}", e))
+}
+
+/// Downloads `url` with `client` until `duration` has passed or `max_bytes`
+/// have arrived. Returns the bytes received and the time taken, measured
+/// from the response headers so connection setup does not count.
+async fn download(
+    client: &Client,
+    url: &str,
+    duration: Duration,
+    max_bytes: u64,
+    cancel_rx: &mut watch::Receiver<bool>,
+) -> Result<(u64, Duration), String> {
+    let mut response = tokio::time::timeout(CONNECT_TIMEOUT, client.get(url).send())
+        .await
+        .map_err(|_| "Download request timed out".to_string())?
+        .map_err(|e| format!("Download request failed: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("Download returned status {}", response.status()));
+    }
+
+    let start = Instant::now();
+    let deadline = tokio::time::Instant::now() + duration;
+    let mut bytes = 0;
+    while bytes < max_bytes {
+        tokio::select! {
+            chunk = response.chunk() => match chunk {
+                Ok(Some(chunk)) => bytes += chunk.len() as u64,
+                // The whole file arrived before the limits
+                Ok(None) => break,
+                Err(e) => return Err(format!("Download failed: {}", e)),
+            },
+            _ = tokio::time::sleep_until(deadline) => break,
+            _ = cancel_rx.changed() => return Err("Throughput test cancelled".to_string()),
+        }
+    }
+    Ok((bytes, start.elapsed()))
+}
+
+/// Tests the download throughput of `endpoint_ids` one at a time. Only one
+/// test runs at once.
+#[tauri::command]
+pub async fn test_endpoints_throughput(
+    app: AppHandle,
+    state: State<'_, ThroughputTestState>,
+    user_id: String,
+    endpoint_ids: Vec<String>,
+) -> Result<Vec<ThroughputResult>, String> {
+    let (cancel_tx, mut cancel_rx) = watch::channel(false);
+    {
+        let mut current = state.cancel_tx.lock().unwrap();
+        if current.is_some() {
+            return Err("A throughput test is already running".to_string());
+        }
+        *current = Some(cancel_tx);
+    }
+
+    let results = run(&app, &user_id, &endpoint_ids, &mut cancel_rx).await;
+    state.cancel_tx.lock().unwrap().take();
+
+    if let Some(tray_manager) = app.try_state::<SystemTrayManager>() {
+        tray_manager.update_menu(&app, user_id).await;
+    }
+    results
+}
+
+async fn run(
+    app: &AppHandle,
+    user_id: &str,
+    endpoint_ids: &[String],
+    cancel_rx: &mut watch::Receiver<bool>,
+) -> Result<Vec<ThroughputResult>, String> {
+    let database_path = utils::get_database_path(app).to_string_lossy().to_string();
+    let database_url = format!("sqlite://{}", database_path);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url)
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+
+    let (url, duration, max_bytes): (String, i64, i64) = sqlx::query_as(
+        "SELECT ThroughputTestUrl, ThroughputTestDuration, ThroughputTestMaxBytes FROM AppSettings WHERE UserID = ?",
+    )
+    .bind(user_id)
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| format!("Failed to fetch throughput test settings: {}", e))?;
+    let duration = Duration::from_secs(duration.max(1) as u64);
+    // Zero or less means no byte limit
+    let max_bytes = if max_bytes > 0 {
+        max_bytes as u64
+    } else {
+        u64::MAX
+    };
+
+    let total = endpoint_ids.len();
+    let mut results = Vec::with_capacity(total);
+    for batch in endpoint_ids.chunks(PROBE_BATCH_SIZE) {
+        let probe = ProbeCore::start(app, user_id, batch).await;
+        for endpoint_id in batch {
+            let outcome = match &probe {
+                Ok(probe) => match probe.socks_port(endpoint_id).and_then(proxied_client) {
+                    Ok(client) => download(&client, &url, duration, max_bytes, cancel_rx).await,
+                    Err(e) => Err(e),
+                },
+                Err(e) => Err(e.clone()),
+            };
+            if *cancel_rx.borrow() {
+                info!("Throughput test cancelled");
+                return Err("Throughput test cancelled".to_string());
+            }
+
+            let result = match outcome {
+                Ok((bytes, elapsed)) => ThroughputResult {
+                    endpoint_id: endpoint_id.clone(),
+                    throughput: Some((bytes as f64 / elapsed.as_secs_f64().max(0.001)) as i64),
+                    bytes,
+                    elapsed_ms: elapsed.as_millis() as u64,
+                    error: None,
+                },
+                Err(e) => ThroughputResult {
+                    endpoint_id: endpoint_id.clone(),
+                    throughput: None,
+                    bytes: 0,
+                    elapsed_ms: 0,
+                    error: Some(e),
+                },
+            };
+
+            sqlx::query(
+                "UPDATE Endpoints SET Throughput = ?, ThroughputTestedAt = ? WHERE EndpointID = ?",
+            )
+            .bind(result.throughput)
+            .bind(Utc::now().timestamp())
+            .bind(endpoint_id)
+            .execute(&pool)
+            .await
+            .map_err(|e| format!("Failed to update endpoint throughput: {}", e))?;
+
+            results.push(result.clone());
+            if let Err(e) = app.emit(
+                THROUGHPUT_PROGRESS_EVENT,
+                ThroughputProgress {
+                    result,
+                    completed: results.len(),
+                    total,
+                },
+            ) {
+                warn!("Failed to emit throughput progress: {}", e);
+            }
+        }
+    }
+    Ok(results)
+}
+
+#[tauri::command]
+pub fn cancel_throughput_test(state: State<'_, ThroughputTestState>) {
+    if let Some(tx) = state.cancel_tx.lock().unwrap().as_ref() {
+        let _ = tx.send(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::hyper::body::{Body, Bytes};
+    use warp::Filter;
+
+    const BLOB_SIZE: usize = 4 * 1024 * 1024;
+    const CHUNK_INTERVAL: Duration = Duration::from_millis(20);
+
+    /// Serves `/blob` as a fixed-size file, `/trickle` as an endless body
+    /// that sends a chunk every [`CHUNK_INTERVAL`], and 404s for the rest.
+    async fn serve() -> String {
+        let blob = warp::path("blob").map(|| vec![0u8; BLOB_SIZE]);
+        let trickle = warp::path("trickle").map(|| {
+            let (mut sender, body) = Body::channel();
+            tokio::spawn(async move {
+                while sender.send_data(Bytes::from(vec![0u8; 1024])).await.is_ok() {
+                    tokio::time::sleep(CHUNK_INTERVAL).await;
+                }
+            });
+            warp::reply::Response::new(body)
+        });
+        let (address, server) =
+            warp::serve(warp::get().and(blob.or(trickle))).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        format!("http://{}", address)
+    }
+
+    async fn fetch(
+        url: &str,
+        duration: Duration,
+        max_bytes: u64,
+    ) -> Result<(u64, Duration), String> {
+        let (_cancel_tx, mut cancel_rx) = watch::channel(false);
+        download(&Client::new(), url, duration, max_bytes, &mut cancel_rx).await
+    }
+
+    #[tokio::test]
+    async fn downloads_the_whole_file() {
+        let server = serve().await;
+        let (bytes, _) = fetch(
+            &format!("{}/blob", server),
+            Duration::from_secs(10),
+            u64::MAX,
+        )
+        .await
+        .unwrap();
+        assert_eq!(bytes, BLOB_SIZE as u64);
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_byte_limit() {
+        let server = serve().await;
+        let max_bytes = 64 * 1024;
+        let (bytes, _) = fetch(
+            &format!("{}/blob", server),
+            Duration::from_secs(10),
+            max_bytes,
+        )
+        .await
+        .unwrap();
+        assert!(bytes >= max_bytes, "{}", bytes);
+        assert!(bytes < BLOB_SIZE as u64, "{}", bytes);
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_deadline() {
+        let server = serve().await;
+        let duration = Duration::from_millis(300);
+        let (bytes, elapsed) = fetch(&format!("{}/trickle", server), duration, u64::MAX)
+            .await
+            .unwrap();
+        assert!(bytes > 0);
+        assert!(elapsed >= duration, "{:?}", elapsed);
+        assert!(elapsed < duration * 5, "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn reports_failed_responses() {
+        let server = serve().await;
+        let error = fetch(
+            &format!("{}/missing", server),
+            Duration::from_secs(1),
+            u64::MAX,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error, "Download returned status 404 Not Found");
+    }
+
+    #[tokio::test]
+    async fn stops_when_cancelled() {
+        let server = serve().await;
+        let (cancel_tx, mut cancel_rx) = watch::channel(false);
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let _ = cancel_tx.send(true);
+        });
+        let error = download(
+            &Client::new(),
+            &format!("{}/trickle", server),
+            Duration::from_secs(10),
+            u64::MAX,
+            &mut cancel_rx,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error, "Throughput test cancelled");
+    }
+}
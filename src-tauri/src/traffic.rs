@@ -0,0 +1,454 @@
+//! Traffic accounting backed by the core's StatsService.
+//!
+//! While the daemon runs, a collector polls the per-inbound and per-outbound
+//! byte counters, emits the current rates as a `traffic-rate` event and folds
+//! the deltas into daily totals in the `TrafficStats` table. Traffic leaving
+//! through the proxy outbound is attributed to the endpoint active at the time.
+
+use chrono::{Days, Local};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+use sqlx::Row;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::sync::oneshot;
+
+use crate::sys_tray::SystemTrayManager;
+use crate::utils;
+use crate::v2ray_core::api::{self, CoreApiClient};
+
+pub const TRAFFIC_RATE_EVENT: &str = "traffic-rate";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+// Outbounds that never carry traffic for a specific endpoint
+const LOCAL_OUTBOUND_TAGS: [&str; 2] = ["direct", "block"];
+
+pub struct TrafficCollectorHandle {
+    pub shutdown_tx: Option<oneshot::Sender<()>>,
+}
+
+/// Bytes per second through a single inbound or outbound.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TagRate {
+    pub kind: String,
+    pub tag: String,
+    pub uplink: u64,
+    pub downlink: u64,
+}
+
+/// Payload of the `traffic-rate` event. The top-level rates cover every
+/// outbound that goes through an endpoint, i.e. the actual proxied traffic.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrafficRate {
+    pub uplink: u64,
+    pub downlink: u64,
+    pub tags: Vec<TagRate>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TrafficRange {
+    Today,
+    Last7Days,
+    Last30Days,
+    All,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrafficStatsEntry {
+    pub day: String,
+    pub kind: String,
+    pub tag: String,
+    pub endpoint_id: Option<String>,
+    pub remark: Option<String>,
+    pub uplink: i64,
+    pub downlink: i64,
+}
+
+/// Splits `inbound>>>tag>>>traffic>>>uplink` style counter names.
+pub(crate) fn parse_stat_name(name: &str) -> Option<(&str, &str, &str)> {
+    let mut parts = name.split(">>>");
+    let kind = parts.next()?;
+    let tag = parts.next()?;
+    if parts.next()? != "traffic" {
+        return None;
+    }
+    let direction = parts.next()?;
+    match (kind, direction) {
+        ("inbound" | "outbound", "uplink" | "downlink") => Some((kind, tag, direction)),
+        _ => None,
+    }
+}
+
+pub(crate) fn is_endpoint_traffic(kind: &str, tag: &str) -> bool {
+    kind == "outbound" && !LOCAL_OUTBOUND_TAGS.contains(&tag)
+}
+
+struct TrafficCollector {
+    pool: SqlitePool,
+    user_id: String,
+    api_host: String,
+    api_port: u16,
+    client: Option<CoreApiClient>,
+    // Last absolute value seen per counter name
+    totals: HashMap<String, i64>,
+    // Deltas not yet written, keyed by (kind, tag, endpoint id)
+    pending: HashMap<(String, String, String), (i64, i64)>,
+    last_poll: Option<Instant>,
+    last_flush: Instant,
+}
+
+impl TrafficCollector {
+    async fn new(app: &AppHandle) -> Result<Self, String> {
+        let database_path = utils::get_database_path(app).to_string_lossy().to_string();
+        let database_url = format!("sqlite://{}", database_path);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(&database_url)
+            .await
+            .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+
+        let user_id: String = sqlx::query("SELECT UserID FROM AppStatus WHERE LoginState = 1")
+            .fetch_optional(&pool)
+            .await
+            .map_err(|e| format!("Failed to fetch logged in user: {}", e))?
+            .ok_or("No logged in user")?
+            .get("UserID");
+        let (api_host, api_port) = api::api_address(&database_path, &user_id).await?;
+
+        Ok(TrafficCollector {
+            pool,
+            user_id,
+            api_host,
+            api_port,
+            client: None,
+            totals: HashMap::new(),
+            pending: HashMap::new(),
+            last_poll: None,
+            last_flush: Instant::now(),
+        })
+    }
+
+    /// How often the tray shows the rates, `None` when it is turned off.
+    async fn tray_speed_interval(&self) -> Option<Duration> {
+        let seconds: i64 =
+            sqlx::query_scalar("SELECT TraySpeedInterval FROM AppSettings WHERE UserID = ?")
+                .bind(&self.user_id)
+                .fetch_one(&self.pool)
+                .await
+                .unwrap_or(2);
+        (seconds > 0).then(|| Duration::from_secs(seconds as u64))
+    }
+
+    async fn active_endpoint(&self) -> String {
+        sqlx::query("SELECT EndpointID FROM Endpoints WHERE Active = 1 LIMIT 1")
+            .fetch_optional(&self.pool)
+            .await
+            .ok()
+            .flatten()
+            .map(|row| row.get("EndpointID"))
+            .unwrap_or_default()
+    }
+
+    async fn poll(&mut self, app: &AppHandle) -> Result<(), String> {
+        if self.client.is_none() {
+            self.client = Some(CoreApiClient::connect(&self.api_host, self.api_port).await?);
+        }
+        let client = self.client.as_mut().ok_or("Core API client unavailable")?;
+        let stats = match client.query_stats("", false).await {
+            Ok(stats) => stats,
+            Err(e) => {
+                // Reconnect on the next tick, the core may have been restarted
+                self.client = None;
+                return Err(e);
+            }
+        };
+
+        let now = Instant::now();
+        let elapsed = self
+            .last_poll
+            .map(|last| now.duration_since(last).as_secs_f64());
+        self.last_poll = Some(now);
+
+        let mut deltas: HashMap<(String, String), (i64, i64)> = HashMap::new();
+        for stat in stats {
+            let Some((kind, tag, direction)) = parse_stat_name(&stat.name) else {
+                continue;
+            };
+            if tag == "api" {
+                continue;
+            }
+            let previous = self
+                .totals
+                .insert(stat.name.clone(), stat.value)
+                .unwrap_or(0);
+            // Counters start over from zero whenever the core restarts
+            let delta = if stat.value >= previous {
+                stat.value - previous
+            } else {
+                stat.value
+            };
+            let entry = deltas
+                .entry((kind.to_string(), tag.to_string()))
+                .or_default();
+            if direction == "uplink" {
+                entry.0 += delta;
+            } else {
+                entry.1 += delta;
+            }
+        }
+
+        let endpoint_id = self.active_endpoint().await;
+        let mut rate = TrafficRate {
+            uplink: 0,
+            downlink: 0,
+            tags: Vec::new(),
+        };
+        for ((kind, tag), (uplink, downlink)) in deltas {
+            if let Some(seconds) = elapsed.filter(|seconds| *seconds > 0.0) {
+                let tag_rate = TagRate {
+                    kind: kind.clone(),
+                    tag: tag.clone(),
+                    uplink: (uplink as f64 / seconds) as u64,
+                    downlink: (downlink as f64 / seconds) as u64,
+                };
+                if is_endpoint_traffic(&kind, &tag) {
+                    rate.uplink += tag_rate.uplink;
+                    rate.downlink += tag_rate.downlink;
+                }
+                rate.tags.push(tag_rate);
+            }
+
+            if uplink == 0 && downlink == 0 {
+                continue;
+            }
+            let endpoint = if is_endpoint_traffic(&kind, &tag) {
+                endpoint_id.clone()
+            } else {
+                String::new()
+            };
+            let entry = self.pending.entry((kind, tag, endpoint)).or_default();
+            entry.0 += uplink;
+            entry.1 += downlink;
+        }
+
+        if elapsed.is_some() {
+            rate.tags
+                .sort_by(|a, b| (&a.kind, &a.tag).cmp(&(&b.kind, &b.tag)));
+            if let Err(e) = app.emit(TRAFFIC_RATE_EVENT, rate) {
+                warn!("Failed to emit traffic rate: {}", e);
+            }
+        }
+
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Adds the pending deltas to today's totals.
+    async fn flush(&mut self) -> Result<(), String> {
+        self.last_flush = Instant::now();
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let day = Local::now().format("%Y-%m-%d").to_string();
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| format!("Failed to begin transaction: {}", e))?;
+        for ((kind, tag, endpoint_id), (uplink, downlink)) in &self.pending {
+            sqlx::query(
+                "INSERT INTO TrafficStats (UserID, Day, Kind, Tag, EndpointID, Uplink, Downlink)
+                 VALUES (?, ?, ?, ?, ?, ?, ?)
+                 ON CONFLICT(UserID, Day, Kind, Tag, EndpointID)
+                 DO UPDATE SET Uplink = Uplink + excluded.Uplink, Downlink = Downlink + excluded.Downlink;",
+            )
+            .bind(&self.user_id)
+            .bind(&day)
+            .bind(kind)
+            .bind(tag)
+            .bind(endpoint_id)
+            .bind(uplink)
+            .bind(downlink)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| format!("Failed to store traffic stats: {}", e))?;
+        }
+        tx.commit()
+            .await
+            .map_err(|e| format!("Failed to commit traffic stats: {}", e))?;
+
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+/// Starts polling the core's traffic counters, replacing any running collector.
+pub fn start_collector(app: &AppHandle) {
+    let (shutdown_tx, mut shutdown_rx) = oneshot::channel::<()>();
+    {
+        let state = app.state::<Mutex<TrafficCollectorHandle>>();
+        let mut guard = state.lock().unwrap();
+        if let Some(tx) = guard.shutdown_tx.replace(shutdown_tx) {
+            let _ = tx.send(());
+        }
+    }
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut collector = match TrafficCollector::new(&app).await {
+            Ok(collector) => collector,
+            Err(e) => {
+                error!("Failed to start traffic collector: {}", e);
+                return;
+            }
+        };
+        info!("Traffic collector started");
+
+        // The tray shows the rates this collector emits
+        if let Some(interval) = collector.tray_speed_interval().await {
+            if let Some(tray_manager) = app.try_state::<SystemTrayManager>() {
+                tray_manager.start_speed_monitor(&app, interval);
+            }
+        }
+
+        let mut ticker = tokio::time::interval(POLL_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = &mut shutdown_rx => break,
+                _ = ticker.tick() => {
+                    // The API inbound is not up for the first moments after a start
+                    if let Err(e) = collector.poll(&app).await {
+                        log::debug!("Traffic poll failed: {}", e);
+                    }
+                }
+            }
+        }
+
+        if let Err(e) = collector.flush().await {
+            error!("Failed to flush traffic stats: {}", e);
+        }
+        info!("Traffic collector stopped");
+    });
+}
+
+/// Stops the collector after it has written its pending totals.
+pub fn stop_collector(app: &AppHandle) {
+    let state = app.state::<Mutex<TrafficCollectorHandle>>();
+    let mut guard = state.lock().unwrap();
+    if let Some(tx) = guard.shutdown_tx.take() {
+        let _ = tx.send(());
+    }
+}
+
+/// Returns the daily traffic totals of `user_id` within `range`, newest first.
+#[tauri::command]
+pub async fn get_traffic_stats(
+    app: AppHandle,
+    user_id: String,
+    range: TrafficRange,
+) -> Result<Vec<TrafficStatsEntry>, String> {
+    let today = Local::now().date_naive();
+    let since = match range {
+        TrafficRange::Today => Some(today),
+        TrafficRange::Last7Days => today.checked_sub_days(Days::new(6)),
+        TrafficRange::Last30Days => today.checked_sub_days(Days::new(29)),
+        TrafficRange::All => None,
+    }
+    .map(|day| day.format("%Y-%m-%d").to_string())
+    .unwrap_or_default();
+
+    let database_path = utils::get_database_path(&app).to_string_lossy().to_string();
+    let database_url = format!("sqlite://{}", database_path);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url)
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+
+    let rows = sqlx::query(
+        "SELECT t.Day, t.Kind, t.Tag, t.EndpointID, e.Remark, t.Uplink, t.Downlink
+         FROM TrafficStats t
+         LEFT JOIN Endpoints e ON e.EndpointID = t.EndpointID
+         WHERE t.UserID = ? AND t.Day >= ?
+         ORDER BY t.Day DESC, t.Kind, t.Tag",
+    )
+    .bind(&user_id)
+    .bind(&since)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| format!("Failed to fetch traffic stats: {}", e))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let endpoint_id: String = row.get("EndpointID");
+            TrafficStatsEntry {
+                day: row.get("Day"),
+                kind: row.get("Kind"),
+                tag: row.get("Tag"),
+                endpoint_id: (!endpoint_id.is_empty()).then_some(endpoint_id),
+                remark: row.get("Remark"),
+                uplink: row.get("Uplink"),
+                downlink: row.get("Downlink"),
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_inbound_and_outbound_counters() {
+        assert_eq!(
+            parse_stat_name("inbound>>>socks-inbound>>>traffic>>>uplink"),
+            Some(("inbound", "socks-inbound", "uplink"))
+        );
+        assert_eq!(
+            parse_stat_name("outbound>>>proxy>>>traffic>>>downlink"),
+            Some(("outbound", "proxy", "downlink"))
+        );
+        // Tags may contain anything but the separator
+        assert_eq!(
+            parse_stat_name("outbound>>>proxy-01 [JP]>>>traffic>>>uplink"),
+            Some(("outbound", "proxy-01 [JP]", "uplink"))
+        );
+    }
+
+    #[test]
+    fn ignores_other_counters() {
+        for name in [
+            "user>>>love@v2ray.com>>>traffic>>>uplink",
+            "inbound>>>api>>>traffic",
+            "inbound>>>api>>>connections>>>uplink",
+            "outbound>>>proxy>>>traffic>>>sideways",
+            "outbound>>>proxy",
+            "outbound",
+            "",
+        ] {
+            assert_eq!(parse_stat_name(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn only_proxy_outbounds_count_as_endpoint_traffic() {
+        assert!(is_endpoint_traffic("outbound", "proxy"));
+        for tag in LOCAL_OUTBOUND_TAGS {
+            assert!(!is_endpoint_traffic("outbound", tag), "{}", tag);
+        }
+        assert!(!is_endpoint_traffic("inbound", "proxy"));
+    }
+}
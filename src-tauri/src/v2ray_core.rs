@@ -1,27 +1,39 @@
 use anyhow::Result; // You can still use anyhow for internal error handling
 use lazy_static::lazy_static;
-use log::{error, info};
+use log::{error, info, warn};
 use sqlx::sqlite::SqlitePoolOptions;
 use std::fs::File;
 use std::io::Write;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tauri::State;
 use tauri::WebviewWindow;
-use tauri::{path, Manager};
+use tauri::{path, Emitter, Manager};
 use tauri_plugin_notification::NotificationExt;
 
 use tauri::AppHandle;
 use tauri_plugin_shell::process::{CommandChild, CommandEvent};
 use tauri_plugin_shell::ShellExt;
+use tokio::net::TcpStream;
+pub mod api;
+pub mod probe;
 pub mod v2ray_config;
+use crate::access_log;
+use crate::failover;
+use crate::port_check;
+use crate::service_state::{self, ServiceState};
 use crate::sys_tray;
 use crate::telemetry;
+use crate::traffic;
 use crate::utils;
 
 lazy_static! {
     static ref SERVICE_LOCK: Mutex<()> = Mutex::new(());
 }
 
+const READY_TIMEOUT: Duration = Duration::from_secs(10);
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 pub struct DaemonState {
     pub child: Option<CommandChild>,
     // pub rx: Option<Receiver<CommandEvent>>,
@@ -101,49 +113,134 @@ pub async fn start_daemon(
 
     let args = vec!["run", "-c", config_path.to_str().expect("REASON")];
 
-    let mut daemon = state.lock().unwrap();
-    {
-        if daemon.child.is_some() {
-            info!("v2ray-core daemon is already running");
-            return Ok(false);
+    let app_handle = window.app_handle().clone();
+
+    if state.lock().unwrap().child.is_some() {
+        info!("v2ray-core daemon is already running");
+        return Ok(false);
+    }
+
+    // The core exits right away on a taken port, catch that before spawning it
+    let conflicts = port_check::probe_config_inbounds(&config_path);
+    if !conflicts.is_empty() {
+        let reason = format!("Inbound ports are in use: {}", port_check::describe(&conflicts));
+        error!("{}", reason);
+        if let Err(e) = app_handle.emit(port_check::PORT_CONFLICT_EVENT, &conflicts) {
+            warn!("Failed to emit port conflicts: {}", e);
+        }
+        if service_state::transition(&app_handle, ServiceState::Starting, None)
+            .await
+            .is_ok()
+        {
+            let _ = service_state::transition(&app_handle, ServiceState::Failed, Some(reason.clone()))
+                .await;
         }
+        return Err(reason);
     }
 
-    let sidecar = window
-        .app_handle()
+    let sidecar = app_handle
         .shell()
         .sidecar("v2ray")
         .map_err(|e| e.to_string())?
         .args(args);
 
-    let success = true;
+    // Check and spawn under one lock so concurrent starts cannot both spawn
+    let spawned = {
+        let mut daemon = state.lock().unwrap();
+        if daemon.child.is_some() {
+            info!("v2ray-core daemon is already running");
+            return Ok(false);
+        }
+        match sidecar.spawn() {
+            Ok((rx, child)) => {
+                let pid = child.pid();
+                daemon.child = Some(child);
+                Ok((rx, pid))
+            }
+            Err(e) => Err(e.to_string()),
+        }
+    };
+    if let Err(e) = service_state::transition(&app_handle, ServiceState::Starting, None).await {
+        warn!("{}", e);
+    }
+
+    let (mut rx, pid) = match spawned {
+        Ok(spawned) => spawned,
+        Err(e) => {
+            let _ = service_state::transition(&app_handle, ServiceState::Failed, Some(e.clone()))
+                .await;
+            return Err(format!("Failed to start v2ray-core: {}", e));
+        }
+    };
 
-    match sidecar.spawn() {
-        Ok((mut rx, _child)) => {
-            daemon.child = Some(_child);
-            tauri::async_runtime::spawn(async move {
-                while let Some(event) = rx.recv().await {
-                    match event {
-                        CommandEvent::Stdout(line) => {
-                            println!("[v2ray] {:?}", String::from_utf8(line));
-                        }
-                        CommandEvent::Stderr(line) => {
-                            println!("[v2ray] {:?}", String::from_utf8(line));
+    let event_app_handle = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            match event {
+                CommandEvent::Stdout(line) => {
+                    access_log::ingest_line(&event_app_handle, &String::from_utf8_lossy(&line));
+                    println!("[v2ray] {:?}", String::from_utf8(line));
+                }
+                CommandEvent::Stderr(line) => {
+                    println!("[v2ray] {:?}", String::from_utf8(line));
+                }
+                CommandEvent::Error(line) => eprintln!("error: {:?}", line),
+                CommandEvent::Terminated(status) => {
+                    // A child that stop_daemon killed is no longer tracked
+                    let exited_on_its_own = {
+                        let daemon_state = event_app_handle.state::<Arc<Mutex<DaemonState>>>();
+                        let mut daemon = daemon_state.lock().unwrap();
+                        if daemon.child.as_ref().is_some_and(|child| child.pid() == pid) {
+                            daemon.child = None;
+                            true
+                        } else {
+                            false
                         }
-                        CommandEvent::Error(line) => eprintln!("error: {:?}", line),
-                        CommandEvent::Terminated(status) => {
-                            if status.code.unwrap_or(1) == 0 {
-                                true
-                            } else {
-                                false
-                            };
+                    };
+                    if exited_on_its_own {
+                        error!("v2ray-core exited unexpectedly: {:?}", status.code);
+                        stop_monitors(&event_app_handle);
+                        let reason = format!("v2ray-core exited with code {:?}", status.code);
+                        if let Err(e) = service_state::transition(
+                            &event_app_handle,
+                            ServiceState::Failed,
+                            Some(reason),
+                        )
+                        .await
+                        {
+                            warn!("{}", e);
                         }
-                        _ => {}
                     }
                 }
-            });
+                _ => {}
+            }
+        }
+    });
+
+    if let Err(reason) = wait_until_ready(&app_handle, &config_path, pid).await {
+        error!("{}", reason);
+        // Still ours unless it already exited, which the event loop handles
+        let child = {
+            let mut daemon = state.lock().unwrap();
+            if daemon.child.as_ref().is_some_and(|child| child.pid() == pid) {
+                daemon.child.take()
+            } else {
+                None
+            }
+        };
+        if let Some(child) = child {
+            if let Err(e) = child.kill() {
+                error!("The daemon cannot be terminated: {}", e);
+            }
         }
-        Err(err) => panic!("{err}"),
+        let _ = service_state::transition(&app_handle, ServiceState::Failed, Some(reason.clone()))
+            .await;
+        return Err(reason);
+    }
+
+    start_monitors(&app_handle);
+    if let Err(e) = service_state::transition(&app_handle, ServiceState::Running, None).await {
+        warn!("{}", e);
     }
     // Track proxy start (spawn to avoid blocking)
     telemetry::track_feature_usage("proxy_start");
@@ -164,8 +261,72 @@ pub async fn start_daemon(
         .show()
         .unwrap();
 
-    println!("success {:?}", success);
-    Ok(success)
+    Ok(true)
+}
+
+/// The first inbound of a generated config file, as an address to connect
+/// to. Wildcard listen addresses are reached through the loopback.
+fn first_inbound(config_path: &std::path::Path) -> Option<(String, u16)> {
+    let content = std::fs::read_to_string(config_path).ok()?;
+    let config: serde_json::Value = serde_json::from_str(&content).ok()?;
+    config["inbounds"].as_array()?.iter().find_map(|inbound| {
+        let port = u16::try_from(inbound["port"].as_u64()?).ok()?;
+        let host = match inbound["listen"].as_str().unwrap_or_default() {
+            "" | "0.0.0.0" | "::" => "127.0.0.1",
+            listen => listen,
+        };
+        Some((host.to_string(), port))
+    })
+}
+
+/// Waits until the core spawned as `pid` accepts connections. All inbounds
+/// come up together, so one accepting means the core is ready.
+async fn wait_until_ready(
+    app: &AppHandle,
+    config_path: &std::path::Path,
+    pid: u32,
+) -> Result<(), String> {
+    let Some((host, port)) = first_inbound(config_path) else {
+        // Nothing to connect to, the spawn is all we can check
+        return Ok(());
+    };
+    let deadline = Instant::now() + READY_TIMEOUT;
+    loop {
+        if TcpStream::connect((host.as_str(), port)).await.is_ok() {
+            return Ok(());
+        }
+        let alive = {
+            let daemon_state = app.state::<Arc<Mutex<DaemonState>>>();
+            let daemon = daemon_state.lock().unwrap();
+            daemon.child.as_ref().is_some_and(|child| child.pid() == pid)
+        };
+        if !alive {
+            return Err("v2ray-core exited during startup".to_string());
+        }
+        if Instant::now() >= deadline {
+            return Err(format!(
+                "v2ray-core did not accept connections on {}:{} in time",
+                host, port
+            ));
+        }
+        tokio::time::sleep(READY_POLL_INTERVAL).await;
+    }
+}
+
+/// Starts the background tasks that watch a running core.
+fn start_monitors(app: &AppHandle) {
+    traffic::start_collector(app);
+    access_log::start_tail(app);
+    failover::start_health_checker(app);
+}
+
+fn stop_monitors(app: &AppHandle) {
+    traffic::stop_collector(app);
+    access_log::stop_tail(app);
+    failover::stop_health_checker(app);
+    if let Some(tray_manager) = app.try_state::<sys_tray::SystemTrayManager>() {
+        tray_manager.stop_speed_monitor();
+    }
 }
 
 #[tauri::command]
@@ -173,15 +334,26 @@ pub async fn stop_daemon(
     state: State<'_, Arc<Mutex<DaemonState>>>,
     window: WebviewWindow,
 ) -> Result<bool, String> {
-    let mut daemon = state.lock().unwrap();
+    let app_handle = window.app_handle().clone();
+    let child = state.lock().unwrap().child.take();
+    if service_state::current(&app_handle) != ServiceState::Stopped {
+        if let Err(e) = service_state::transition(&app_handle, ServiceState::Stopping, None).await {
+            warn!("{}", e);
+        }
+    }
 
-    if let Some(child) = daemon.child.take() {
+    if let Some(child) = child {
         // Take ownership of CommandChild
-        child
-            .kill()
-            .map_err(|e| format!("Failed to kill daemon: {}", e))?;
+        if let Err(e) = child.kill() {
+            let reason = format!("Failed to kill daemon: {}", e);
+            let _ = service_state::transition(&app_handle, ServiceState::Failed, Some(reason.clone()))
+                .await;
+            return Err(reason);
+        }
         // rx = None;
         info!("v2ray-core daemon stopped");
+        stop_monitors(&app_handle);
+        let _ = service_state::transition(&app_handle, ServiceState::Stopped, None).await;
 
         // Track proxy stop (spawn to avoid blocking)
         telemetry::track_feature_usage("proxy_stop");
@@ -204,6 +376,7 @@ pub async fn stop_daemon(
         Ok(true)
     } else {
         info!("v2ray-core daemon is not running");
+        let _ = service_state::transition(&app_handle, ServiceState::Stopped, None).await;
         Ok(true)
     }
 }
@@ -216,23 +389,6 @@ pub async fn stop_v2ray_daemon(
     user_id: String,
 ) -> Result<bool, String> {
     let _ = stop_daemon(state, window).await;
-
-    let database_path = utils::get_database_path(&app_handle)
-        .to_string_lossy()
-        .to_string();
-
-    let database_url = format!("sqlite://{}", database_path);
-    let pool = SqlitePoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
-        .await
-        .map_err(|e| format!("Failed to connect to the database: {}", e))
-        .unwrap();
-    sqlx::query("UPDATE AppStatus SET ServiceRunningState = 0 WHERE UserID = ?;")
-        .bind(&user_id)
-        .execute(&pool)
-        .await
-        .expect("Failed to update service running state");
     let _ = sys_tray::tray_update(app_handle.clone(), user_id).await;
     Ok(true)
 }
@@ -244,3 +400,80 @@ pub async fn check_daemon_status(
     let daemon = state.lock().unwrap();
     Ok(daemon.child.is_some())
 }
+
+/// Reads the proxy outbound (always the first one) from a generated config file.
+fn read_proxy_outbound(config_path: &std::path::Path) -> Option<serde_json::Value> {
+    let content = std::fs::read_to_string(config_path).ok()?;
+    let config: serde_json::Value = serde_json::from_str(&content).ok()?;
+    config["outbounds"].get(0).cloned()
+}
+
+/// Makes `endpoint_id` the active endpoint.
+///
+/// When the daemon is running the proxy outbound is swapped on the live core
+/// through the HandlerService API, so connections are not dropped. If the API
+/// is unreachable or cannot express the endpoint, the daemon is restarted with
+/// the new config instead. Returns whether the switch happened without a restart.
+#[tauri::command]
+pub async fn switch_endpoint(
+    app: AppHandle,
+    endpoint_id: String,
+    user_id: String,
+) -> Result<bool, String> {
+    let config_path = app
+        .path()
+        .resolve("config.json", path::BaseDirectory::AppData)
+        .map_err(|e| format!("Failed to resolve config path: {}", e))?;
+
+    // The config on disk is what the running core was started with
+    let previous_tag = read_proxy_outbound(&config_path)
+        .and_then(|outbound| outbound["tag"].as_str().map(|tag| tag.to_string()))
+        .unwrap_or_else(|| v2ray_config::PROXY_OUTBOUND_TAG.to_string());
+
+    if !inject_config(app.clone(), endpoint_id.clone(), user_id.clone()).await {
+        return Err("Failed to generate the config for the selected endpoint".to_string());
+    }
+
+    let database_path = utils::get_database_path(&app).to_string_lossy().to_string();
+    let database_url = format!("sqlite://{}", database_path);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url)
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+    sqlx::query("UPDATE Endpoints SET Active = CASE WHEN EndpointID = ? THEN 1 ELSE 0 END WHERE Active = 1 OR EndpointID = ?;")
+        .bind(&endpoint_id)
+        .bind(&endpoint_id)
+        .execute(&pool)
+        .await
+        .map_err(|e| format!("Failed to update active endpoint: {}", e))?;
+
+    let daemon_state = app.state::<Arc<Mutex<DaemonState>>>();
+    let is_running = daemon_state.lock().unwrap().child.is_some();
+    if !is_running {
+        return Ok(false);
+    }
+
+    let outbound = read_proxy_outbound(&config_path)
+        .ok_or("Generated config has no proxy outbound".to_string())?;
+    let hot_swap_result = match api::api_address(&database_path, &user_id).await {
+        Ok((host, port)) => api::hot_swap_outbound(&host, port, &previous_tag, &outbound).await,
+        Err(e) => Err(e),
+    };
+
+    match hot_swap_result {
+        Ok(()) => {
+            info!("Switched to endpoint {} without restarting the core", endpoint_id);
+            Ok(true)
+        }
+        Err(e) => {
+            warn!("Hot swap failed, restarting v2ray-core instead: {}", e);
+            let window = app
+                .get_webview_window("main")
+                .ok_or("Main window not found")?;
+            stop_daemon(daemon_state.clone(), window.clone()).await?;
+            start_daemon(daemon_state, window).await?;
+            Ok(false)
+        }
+    }
+}
@@ -0,0 +1,628 @@
+use log::info;
+use serde_json::Value;
+use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::Row;
+use std::time::Duration;
+use tonic::codec::ProstCodec;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::transport::{Channel, Endpoint};
+
+pub mod proto;
+
+const ADD_OUTBOUND_PATH: &str = "/v2ray.core.app.proxyman.command.HandlerService/AddOutbound";
+const REMOVE_OUTBOUND_PATH: &str = "/v2ray.core.app.proxyman.command.HandlerService/RemoveOutbound";
+const QUERY_STATS_PATH: &str = "/v2ray.core.app.stats.command.StatsService/QueryStats";
+
+/// Thin gRPC client for the services exposed on the core's `api` inbound.
+pub struct CoreApiClient {
+    grpc: tonic::client::Grpc<Channel>,
+}
+
+impl CoreApiClient {
+    pub async fn connect(host: &str, port: u16) -> Result<Self, String> {
+        let channel = Endpoint::from_shared(format!("http://{}:{}", host, port))
+            .map_err(|e| format!("Invalid core API address: {}", e))?
+            .connect_timeout(Duration::from_secs(2))
+            .timeout(Duration::from_secs(5))
+            .connect()
+            .await
+            .map_err(|e| format!("Failed to connect to the core API: {}", e))?;
+
+        Ok(CoreApiClient {
+            grpc: tonic::client::Grpc::new(channel),
+        })
+    }
+
+    async fn unary<Req, Resp>(&mut self, path: &'static str, request: Req) -> Result<Resp, String>
+    where
+        Req: prost::Message + 'static,
+        Resp: prost::Message + Default + 'static,
+    {
+        self.grpc
+            .ready()
+            .await
+            .map_err(|e| format!("Core API is not ready: {}", e))?;
+
+        let codec: ProstCodec<Req, Resp> = ProstCodec::default();
+        let response = self
+            .grpc
+            .unary(
+                tonic::Request::new(request),
+                PathAndQuery::from_static(path),
+                codec,
+            )
+            .await
+            .map_err(|status| format!("{} failed: {}", path, status.message()))?;
+
+        Ok(response.into_inner())
+    }
+
+    pub async fn remove_outbound(&mut self, tag: &str) -> Result<(), String> {
+        let _: proto::RemoveOutboundResponse = self
+            .unary(
+                REMOVE_OUTBOUND_PATH,
+                proto::RemoveOutboundRequest {
+                    tag: tag.to_string(),
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Returns every counter whose name contains `pattern` (all counters for
+    /// an empty pattern). With `reset` the counters are zeroed after reading.
+    pub async fn query_stats(
+        &mut self,
+        pattern: &str,
+        reset: bool,
+    ) -> Result<Vec<proto::Stat>, String> {
+        let response: proto::QueryStatsResponse = self
+            .unary(
+                QUERY_STATS_PATH,
+                proto::QueryStatsRequest {
+                    pattern: pattern.to_string(),
+                    reset,
+                },
+            )
+            .await?;
+        Ok(response.stat)
+    }
+
+    pub async fn add_outbound(
+        &mut self,
+        outbound: proto::OutboundHandlerConfig,
+    ) -> Result<(), String> {
+        let _: proto::AddOutboundResponse = self
+            .unary(
+                ADD_OUTBOUND_PATH,
+                proto::AddOutboundRequest {
+                    outbound: Some(outbound),
+                },
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+/// Looks up where the `api` inbound of the given user listens.
+pub async fn api_address(db_path: &str, user_id: &str) -> Result<(String, u16), String> {
+    let database_url = format!("sqlite://{}", db_path);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect(&database_url)
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+
+    let row = sqlx::query("SELECT Listen, Port FROM Inbounds WHERE UserID = ? AND Tag = 'api'")
+        .bind(user_id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| format!("Failed to fetch api inbound: {}", e))?
+        .ok_or("No api inbound configured for this user")?;
+
+    let listen: String = row.get("Listen");
+    let port: i64 = row.get("Port");
+    // The API is only ever dialled locally, even if it listens on every interface
+    let host = if listen == "0.0.0.0" || listen == "::" || listen.is_empty() {
+        "127.0.0.1".to_string()
+    } else {
+        listen
+    };
+
+    Ok((host, port as u16))
+}
+
+/// Replaces the outbound `previous_tag` on the running core with `outbound`,
+/// a proxy outbound in the JSON shape produced by `generate_config`.
+///
+/// The old handler is removed before the new one is added: the core only
+/// promotes a handler to default outbound when none is set, so this order
+/// keeps the new endpoint as the default route.
+pub async fn hot_swap_outbound(
+    host: &str,
+    port: u16,
+    previous_tag: &str,
+    outbound: &Value,
+) -> Result<(), String> {
+    // Convert first so an unsupported endpoint never leaves the core without an outbound
+    let handler = outbound_handler_config(outbound)?;
+    let mut client = CoreApiClient::connect(host, port).await?;
+
+    client.remove_outbound(previous_tag).await?;
+    info!("Removed outbound '{}' from the running core", previous_tag);
+    client.add_outbound(handler).await?;
+    info!("Added outbound '{}' to the running core", outbound["tag"]);
+
+    Ok(())
+}
+
+/// Translates a generated JSON outbound into the protobuf handler config the
+/// HandlerService expects. Only the protocols and transports the app can
+/// produce today are covered; anything else is reported as unsupported so
+/// the caller can fall back to a restart.
+pub fn outbound_handler_config(outbound: &Value) -> Result<proto::OutboundHandlerConfig, String> {
+    let protocol = outbound["protocol"].as_str().unwrap_or_default();
+    let tag = outbound["tag"].as_str().unwrap_or("proxy").to_string();
+    let settings = &outbound["settings"];
+
+    let proxy_settings = match protocol {
+        "vmess" => {
+            let mut receivers = Vec::new();
+            for vnext in settings["vnext"].as_array().into_iter().flatten() {
+                let users = vnext["users"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|user| {
+                        let account = proto::VmessAccount {
+                            id: user["id"].as_str().unwrap_or_default().to_string(),
+                            alter_id: user["alterId"].as_u64().unwrap_or(0) as u32,
+                            security_settings: Some(proto::SecurityConfig {
+                                r#type: vmess_security_type(
+                                    user["security"].as_str().unwrap_or("auto"),
+                                ),
+                            }),
+                        };
+                        proto::User {
+                            level: user["level"].as_u64().unwrap_or(0) as u32,
+                            email: String::new(),
+                            account: Some(proto::TypedMessage::pack(
+                                "v2ray.core.proxy.vmess.Account",
+                                &account,
+                            )),
+                        }
+                    })
+                    .collect();
+                receivers.push(server_endpoint(vnext, users)?);
+            }
+            proto::TypedMessage::pack(
+                "v2ray.core.proxy.vmess.outbound.Config",
+                &proto::VmessOutboundConfig {
+                    receiver: receivers,
+                },
+            )
+        }
+        "shadowsocks" => {
+            let mut servers = Vec::new();
+            for server in settings["servers"].as_array().into_iter().flatten() {
+                let method = server["method"].as_str().unwrap_or_default();
+                let account = proto::ShadowsocksAccount {
+                    password: server["password"].as_str().unwrap_or_default().to_string(),
+                    cipher_type: shadowsocks_cipher_type(method)?,
+                };
+                let user = proto::User {
+                    level: server["level"].as_u64().unwrap_or(0) as u32,
+                    email: server["email"].as_str().unwrap_or_default().to_string(),
+                    account: Some(proto::TypedMessage::pack(
+                        "v2ray.core.proxy.shadowsocks.Account",
+                        &account,
+                    )),
+                };
+                servers.push(server_endpoint(server, vec![user])?);
+            }
+            proto::TypedMessage::pack(
+                "v2ray.core.proxy.shadowsocks.ClientConfig",
+                &proto::ServerListConfig { server: servers },
+            )
+        }
+        "trojan" => {
+            let mut servers = Vec::new();
+            for server in settings["servers"].as_array().into_iter().flatten() {
+                let account = proto::TrojanAccount {
+                    password: server["password"].as_str().unwrap_or_default().to_string(),
+                };
+                let user = proto::User {
+                    level: server["level"].as_u64().unwrap_or(0) as u32,
+                    email: server["email"].as_str().unwrap_or_default().to_string(),
+                    account: Some(proto::TypedMessage::pack(
+                        "v2ray.core.proxy.trojan.Account",
+                        &account,
+                    )),
+                };
+                servers.push(server_endpoint(server, vec![user])?);
+            }
+            proto::TypedMessage::pack(
+                "v2ray.core.proxy.trojan.ClientConfig",
+                &proto::ServerListConfig { server: servers },
+            )
+        }
+        other => {
+            return Err(format!(
+                "Hot swap is not supported for protocol '{}'",
+                other
+            ))
+        }
+    };
+
+    let sender = proto::SenderConfig {
+        stream_settings: Some(stream_config(&outbound["streamSettings"])?),
+        multiplex_settings: Some(proto::MultiplexingConfig {
+            enabled: outbound["mux"]["enabled"].as_bool().unwrap_or(false),
+            concurrency: outbound["mux"]["concurrency"].as_u64().unwrap_or(8) as u32,
+        }),
+    };
+
+    Ok(proto::OutboundHandlerConfig {
+        tag,
+        sender_settings: Some(proto::TypedMessage::pack(
+            "v2ray.core.app.proxyman.SenderConfig",
+            &sender,
+        )),
+        proxy_settings: Some(proxy_settings),
+    })
+}
+
+fn server_endpoint(
+    server: &Value,
+    users: Vec<proto::User>,
+) -> Result<proto::ServerEndpoint, String> {
+    let address = server["address"]
+        .as_str()
+        .filter(|address| !address.is_empty())
+        .ok_or("Outbound server has no address")?;
+    let port = server["port"]
+        .as_u64()
+        .ok_or("Outbound server has no port")?;
+
+    Ok(proto::ServerEndpoint {
+        address: Some(proto::IpOrDomain::from_host(address)),
+        port: port as u32,
+        user: users,
+    })
+}
+
+fn stream_config(stream: &Value) -> Result<proto::StreamConfig, String> {
+    let network = stream["network"].as_str().unwrap_or("tcp");
+    let mut config = proto::StreamConfig::default();
+
+    match network {
+        "tcp" => {
+            let header_type = stream["tcpSettings"]["header"]["type"]
+                .as_str()
+                .unwrap_or("none");
+            if header_type != "none" {
+                return Err(format!(
+                    "Hot swap is not supported for tcp header '{}'",
+                    header_type
+                ));
+            }
+            config.protocol_name = "tcp".to_string();
+        }
+        "ws" => {
+            let ws = &stream["wsSettings"];
+            let header = ws["headers"]
+                .as_object()
+                .into_iter()
+                .flatten()
+                .filter_map(|(key, value)| {
+                    value
+                        .as_str()
+                        .filter(|v| !v.is_empty())
+                        .map(|v| proto::WebsocketHeader {
+                            key: key.clone(),
+                            value: v.to_string(),
+                        })
+                })
+                .collect();
+            let settings = proto::WebsocketConfig {
+                path: ws["path"].as_str().unwrap_or("/").to_string(),
+                header,
+            };
+            config.protocol_name = "websocket".to_string();
+            config.transport_settings.push(proto::TransportConfig {
+                protocol_name: "websocket".to_string(),
+                settings: Some(proto::TypedMessage::pack(
+                    "v2ray.core.transport.internet.websocket.Config",
+                    &settings,
+                )),
+            });
+        }
+        "grpc" => {
+            let settings = proto::GrpcConfig {
+                host: String::new(),
+                service_name: stream["grpcSettings"]["serviceName"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+            };
+            config.protocol_name = "gun".to_string();
+            config.transport_settings.push(proto::TransportConfig {
+                protocol_name: "gun".to_string(),
+                settings: Some(proto::TypedMessage::pack(
+                    "v2ray.core.transport.internet.grpc.encoding.Config",
+                    &settings,
+                )),
+            });
+        }
+        other => return Err(format!("Hot swap is not supported for network '{}'", other)),
+    }
+
+    match stream["security"].as_str().unwrap_or("none") {
+        "none" | "" => {}
+        "tls" => {
+            let tls = &stream["tlsSettings"];
+            let settings = proto::TlsConfig {
+                allow_insecure: tls["allowInsecure"].as_bool().unwrap_or(false),
+                server_name: tls["serverName"].as_str().unwrap_or_default().to_string(),
+                next_protocol: Vec::new(),
+            };
+            config.security_type = "v2ray.core.transport.internet.tls.Config".to_string();
+            config.security_settings.push(proto::TypedMessage::pack(
+                "v2ray.core.transport.internet.tls.Config",
+                &settings,
+            ));
+        }
+        other => {
+            return Err(format!(
+                "Hot swap is not supported for security '{}'",
+                other
+            ))
+        }
+    }
+
+    Ok(config)
+}
+
+// Values of v2ray.core.common.protocol.SecurityType
+fn vmess_security_type(security: &str) -> i32 {
+    match security.to_lowercase().as_str() {
+        "aes-128-gcm" => 3,
+        "chacha20-poly1305" => 4,
+        "none" => 5,
+        "zero" => 6,
+        _ => 2, // auto
+    }
+}
+
+// Values of v2ray.core.proxy.shadowsocks.CipherType
+fn shadowsocks_cipher_type(method: &str) -> Result<i32, String> {
+    match method.to_lowercase().as_str() {
+        "aes-128-gcm" => Ok(5),
+        "aes-256-gcm" => Ok(6),
+        "chacha20-poly1305" | "chacha20-ietf-poly1305" => Ok(7),
+        "none" | "plain" => Ok(8),
+        other => Err(format!("Hot swap is not supported for cipher '{}'", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+    use tonic::body::BoxBody;
+    use tonic::codegen::{http, BoxFuture, Context, Poll, Service};
+    use tonic::server::{NamedService, UnaryService};
+    use tonic::transport::server::TcpIncoming;
+    use tonic::transport::Server;
+
+    #[derive(Debug, PartialEq)]
+    enum Call {
+        Remove(String),
+        Add(proto::OutboundHandlerConfig),
+    }
+
+    /// Records the calls made to it; removing an unknown tag fails like it
+    /// does on the core.
+    #[derive(Clone)]
+    struct MockHandlerService {
+        tags: Arc<Mutex<Vec<String>>>,
+        calls: Arc<Mutex<Vec<Call>>>,
+    }
+
+    impl NamedService for MockHandlerService {
+        const NAME: &'static str = "v2ray.core.app.proxyman.command.HandlerService";
+    }
+
+    struct RemoveOutbound(MockHandlerService);
+
+    impl UnaryService<proto::RemoveOutboundRequest> for RemoveOutbound {
+        type Response = proto::RemoveOutboundResponse;
+        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+        fn call(&mut self, request: tonic::Request<proto::RemoveOutboundRequest>) -> Self::Future {
+            let tag = request.into_inner().tag;
+            let mock = self.0.clone();
+            Box::pin(async move {
+                mock.calls.lock().unwrap().push(Call::Remove(tag.clone()));
+                let mut tags = mock.tags.lock().unwrap();
+                let index = tags.iter().position(|known| *known == tag).ok_or_else(|| {
+                    tonic::Status::not_found(format!("handler not found: {}", tag))
+                })?;
+                tags.remove(index);
+                Ok(tonic::Response::new(proto::RemoveOutboundResponse {}))
+            })
+        }
+    }
+
+    struct AddOutbound(MockHandlerService);
+
+    impl UnaryService<proto::AddOutboundRequest> for AddOutbound {
+        type Response = proto::AddOutboundResponse;
+        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+        fn call(&mut self, request: tonic::Request<proto::AddOutboundRequest>) -> Self::Future {
+            let outbound = request.into_inner().outbound.unwrap_or_default();
+            let mock = self.0.clone();
+            Box::pin(async move {
+                mock.tags.lock().unwrap().push(outbound.tag.clone());
+                mock.calls.lock().unwrap().push(Call::Add(outbound));
+                Ok(tonic::Response::new(proto::AddOutboundResponse {}))
+            })
+        }
+    }
+
+    impl Service<http::Request<BoxBody>> for MockHandlerService {
+        type Response = http::Response<BoxBody>;
+        type Error = Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+            let mock = self.clone();
+            Box::pin(async move {
+                let response = match request.uri().path() {
+                    REMOVE_OUTBOUND_PATH => {
+                        tonic::server::Grpc::new(ProstCodec::default())
+                            .unary(RemoveOutbound(mock), request)
+                            .await
+                    }
+                    ADD_OUTBOUND_PATH => {
+                        tonic::server::Grpc::new(ProstCodec::default())
+                            .unary(AddOutbound(mock), request)
+                            .await
+                    }
+                    _ => tonic::Status::unimplemented("").into_http(),
+                };
+                Ok(response)
+            })
+        }
+    }
+
+    /// Serves the mock on an ephemeral local port, with `tags` as the
+    /// outbounds the core was started with.
+    async fn serve(tags: &[&str]) -> (MockHandlerService, u16) {
+        let mock = MockHandlerService {
+            tags: Arc::new(Mutex::new(tags.iter().map(|tag| tag.to_string()).collect())),
+            calls: Arc::new(Mutex::new(Vec::new())),
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        let service = mock.clone();
+        tokio::spawn(async move {
+            Server::builder()
+                .add_service(service)
+                .serve_with_incoming(incoming)
+                .await
+                .unwrap();
+        });
+        (mock, port)
+    }
+
+    fn outbound(protocol: &str, settings: Value) -> Value {
+        json!({
+            "tag": "proxy",
+            "protocol": protocol,
+            "settings": settings,
+            "streamSettings": {
+                "network": "ws",
+                "security": "tls",
+                "wsSettings": { "path": "/ray", "headers": { "Host": "cdn.example.com" } },
+                "tlsSettings": { "serverName": "vmess.example.com", "allowInsecure": false },
+            },
+            "mux": { "enabled": false, "concurrency": 8 },
+        })
+    }
+
+    fn vmess() -> Value {
+        outbound(
+            "vmess",
+            json!({ "vnext": [{
+                "address": "vmess.example.com",
+                "port": 443,
+                "users": [{ "id": "b831381d-6324-4d53-ad4f-8cda48b30811", "alterId": 0, "security": "auto" }],
+            }]}),
+        )
+    }
+
+    #[tokio::test]
+    async fn removes_the_old_outbound_before_adding_the_new_one() {
+        let (mock, port) = serve(&["proxy", "direct", "block"]).await;
+
+        hot_swap_outbound("127.0.0.1", port, "proxy", &vmess())
+            .await
+            .unwrap();
+
+        let calls = mock.calls.lock().unwrap();
+        assert_eq!(calls.len(), 2, "{:?}", calls);
+        assert_eq!(calls[0], Call::Remove("proxy".to_string()));
+        let Call::Add(added) = &calls[1] else {
+            panic!("expected an add, got {:?}", calls[1]);
+        };
+        assert_eq!(added, &outbound_handler_config(&vmess()).unwrap());
+        assert_eq!(added.tag, "proxy");
+        assert_eq!(
+            added
+                .proxy_settings
+                .as_ref()
+                .map(|settings| settings.r#type.as_str()),
+            Some("v2ray.core.proxy.vmess.outbound.Config")
+        );
+        assert_eq!(*mock.tags.lock().unwrap(), ["direct", "block", "proxy"]);
+    }
+
+    #[tokio::test]
+    async fn does_not_add_when_the_removal_fails() {
+        let (mock, port) = serve(&["direct"]).await;
+
+        let error = hot_swap_outbound("127.0.0.1", port, "proxy", &vmess())
+            .await
+            .unwrap_err();
+
+        assert!(error.contains("handler not found: proxy"), "{}", error);
+        assert_eq!(
+            *mock.calls.lock().unwrap(),
+            [Call::Remove("proxy".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn leaves_the_core_alone_for_unsupported_endpoints() {
+        let (mock, port) = serve(&["proxy"]).await;
+        let vless = outbound(
+            "vless",
+            json!({ "vnext": [{
+                "address": "vless.example.com",
+                "port": 443,
+                "users": [{ "id": "b831381d-6324-4d53-ad4f-8cda48b30811", "encryption": "none" }],
+            }]}),
+        );
+
+        // switch_endpoint restarts the core on this error
+        let error = hot_swap_outbound("127.0.0.1", port, "proxy", &vless)
+            .await
+            .unwrap_err();
+
+        assert_eq!(error, "Hot swap is not supported for protocol 'vless'");
+        assert!(mock.calls.lock().unwrap().is_empty());
+        assert_eq!(*mock.tags.lock().unwrap(), ["proxy"]);
+    }
+
+    #[tokio::test]
+    async fn reports_an_unreachable_api() {
+        let port = portpicker::pick_unused_port().unwrap();
+        let error = hot_swap_outbound("127.0.0.1", port, "proxy", &vmess())
+            .await
+            .unwrap_err();
+        assert!(
+            error.starts_with("Failed to connect to the core API"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,255 @@
+// Hand-written prost messages for the subset of the v2ray-core v5 protobuf
+// API that the app talks to. Field numbers mirror the upstream .proto files
+// (https://github.com/v2fly/v2ray-core); enums are carried as plain int32
+// which is wire-compatible with the upstream enum fields.
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TypedMessage {
+    #[prost(string, tag = "1")]
+    pub r#type: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub value: Vec<u8>,
+}
+
+impl TypedMessage {
+    pub fn pack<M: prost::Message>(type_name: &str, message: &M) -> Self {
+        TypedMessage {
+            r#type: type_name.to_string(),
+            value: message.encode_to_vec(),
+        }
+    }
+}
+
+// v2ray.core.common.net.IPOrDomain
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct IpOrDomain {
+    #[prost(oneof = "ip_or_domain::Address", tags = "1, 2")]
+    pub address: Option<ip_or_domain::Address>,
+}
+
+pub mod ip_or_domain {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Address {
+        #[prost(bytes, tag = "1")]
+        Ip(Vec<u8>),
+        #[prost(string, tag = "2")]
+        Domain(String),
+    }
+}
+
+impl IpOrDomain {
+    pub fn from_host(host: &str) -> Self {
+        let address = match host.parse::<std::net::IpAddr>() {
+            Ok(std::net::IpAddr::V4(ip)) => ip_or_domain::Address::Ip(ip.octets().to_vec()),
+            Ok(std::net::IpAddr::V6(ip)) => ip_or_domain::Address::Ip(ip.octets().to_vec()),
+            Err(_) => ip_or_domain::Address::Domain(host.to_string()),
+        };
+        IpOrDomain {
+            address: Some(address),
+        }
+    }
+}
+
+// v2ray.core.common.protocol.User
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct User {
+    #[prost(uint32, tag = "1")]
+    pub level: u32,
+    #[prost(string, tag = "2")]
+    pub email: String,
+    #[prost(message, optional, tag = "3")]
+    pub account: Option<TypedMessage>,
+}
+
+// v2ray.core.common.protocol.ServerEndpoint
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ServerEndpoint {
+    #[prost(message, optional, tag = "1")]
+    pub address: Option<IpOrDomain>,
+    #[prost(uint32, tag = "2")]
+    pub port: u32,
+    #[prost(message, repeated, tag = "3")]
+    pub user: Vec<User>,
+}
+
+// v2ray.core.common.protocol.SecurityConfig
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SecurityConfig {
+    #[prost(int32, tag = "1")]
+    pub r#type: i32,
+}
+
+// v2ray.core.proxy.vmess.Account
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct VmessAccount {
+    #[prost(string, tag = "1")]
+    pub id: String,
+    #[prost(uint32, tag = "2")]
+    pub alter_id: u32,
+    #[prost(message, optional, tag = "3")]
+    pub security_settings: Option<SecurityConfig>,
+}
+
+// v2ray.core.proxy.vmess.outbound.Config
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct VmessOutboundConfig {
+    #[prost(message, repeated, tag = "1")]
+    pub receiver: Vec<ServerEndpoint>,
+}
+
+// v2ray.core.proxy.shadowsocks.Account
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ShadowsocksAccount {
+    #[prost(string, tag = "1")]
+    pub password: String,
+    #[prost(int32, tag = "2")]
+    pub cipher_type: i32,
+}
+
+// v2ray.core.proxy.trojan.Account
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TrojanAccount {
+    #[prost(string, tag = "1")]
+    pub password: String,
+}
+
+// v2ray.core.proxy.shadowsocks.ClientConfig and v2ray.core.proxy.trojan.ClientConfig
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ServerListConfig {
+    #[prost(message, repeated, tag = "1")]
+    pub server: Vec<ServerEndpoint>,
+}
+
+// v2ray.core.transport.internet.TransportConfig
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TransportConfig {
+    #[prost(message, optional, tag = "2")]
+    pub settings: Option<TypedMessage>,
+    #[prost(string, tag = "3")]
+    pub protocol_name: String,
+}
+
+// v2ray.core.transport.internet.StreamConfig
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StreamConfig {
+    #[prost(message, repeated, tag = "2")]
+    pub transport_settings: Vec<TransportConfig>,
+    #[prost(string, tag = "3")]
+    pub security_type: String,
+    #[prost(message, repeated, tag = "4")]
+    pub security_settings: Vec<TypedMessage>,
+    #[prost(string, tag = "5")]
+    pub protocol_name: String,
+}
+
+// v2ray.core.transport.internet.tls.Config
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TlsConfig {
+    #[prost(bool, tag = "1")]
+    pub allow_insecure: bool,
+    #[prost(string, tag = "3")]
+    pub server_name: String,
+    #[prost(string, repeated, tag = "4")]
+    pub next_protocol: Vec<String>,
+}
+
+// v2ray.core.transport.internet.websocket.Header
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WebsocketHeader {
+    #[prost(string, tag = "1")]
+    pub key: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+// v2ray.core.transport.internet.websocket.Config
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WebsocketConfig {
+    #[prost(string, tag = "2")]
+    pub path: String,
+    #[prost(message, repeated, tag = "3")]
+    pub header: Vec<WebsocketHeader>,
+}
+
+// v2ray.core.transport.internet.grpc.encoding.Config
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GrpcConfig {
+    #[prost(string, tag = "1")]
+    pub host: String,
+    #[prost(string, tag = "2")]
+    pub service_name: String,
+}
+
+// v2ray.core.app.proxyman.MultiplexingConfig
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MultiplexingConfig {
+    #[prost(bool, tag = "1")]
+    pub enabled: bool,
+    #[prost(uint32, tag = "2")]
+    pub concurrency: u32,
+}
+
+// v2ray.core.app.proxyman.SenderConfig
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SenderConfig {
+    #[prost(message, optional, tag = "2")]
+    pub stream_settings: Option<StreamConfig>,
+    #[prost(message, optional, tag = "4")]
+    pub multiplex_settings: Option<MultiplexingConfig>,
+}
+
+// v2ray.core.OutboundHandlerConfig
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct OutboundHandlerConfig {
+    #[prost(string, tag = "1")]
+    pub tag: String,
+    #[prost(message, optional, tag = "2")]
+    pub sender_settings: Option<TypedMessage>,
+    #[prost(message, optional, tag = "3")]
+    pub proxy_settings: Option<TypedMessage>,
+}
+
+// v2ray.core.app.proxyman.command.AddOutboundRequest
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AddOutboundRequest {
+    #[prost(message, optional, tag = "1")]
+    pub outbound: Option<OutboundHandlerConfig>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AddOutboundResponse {}
+
+// v2ray.core.app.proxyman.command.RemoveOutboundRequest
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RemoveOutboundRequest {
+    #[prost(string, tag = "1")]
+    pub tag: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RemoveOutboundResponse {}
+
+// v2ray.core.app.stats.command.QueryStatsRequest
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct QueryStatsRequest {
+    #[prost(string, tag = "1")]
+    pub pattern: String,
+    #[prost(bool, tag = "2")]
+    pub reset: bool,
+}
+
+// v2ray.core.app.stats.command.Stat
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Stat {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(int64, tag = "2")]
+    pub value: i64,
+}
+
+// v2ray.core.app.stats.command.QueryStatsResponse
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct QueryStatsResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub stat: Vec<Stat>,
+}
@@ -0,0 +1,200 @@
+//! Throwaway core instances for testing endpoints through the actual server,
+//! independent of the daemon the user is running.
+//!
+//! One instance serves a whole batch of endpoints: each endpoint gets its own
+//! socks inbound on an ephemeral local port, routed to that endpoint's
+//! outbound as produced by `generate_config`.
+
+use log::{error, warn};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tauri::{path, AppHandle, Manager};
+use tauri_plugin_shell::process::{CommandChild, CommandEvent};
+use tauri_plugin_shell::ShellExt;
+use tokio::net::TcpStream;
+
+use super::v2ray_config;
+use crate::utils;
+
+const READY_TIMEOUT: Duration = Duration::from_secs(5);
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct ProbeCore {
+    child: Option<CommandChild>,
+    config_path: Option<PathBuf>,
+    routes: HashMap<String, Result<u16, String>>,
+}
+
+impl ProbeCore {
+    /// Starts a core serving `endpoint_ids`. Endpoints whose outbound cannot
+    /// be generated are left out and report the reason from [`socks_port`].
+    ///
+    /// [`socks_port`]: ProbeCore::socks_port
+    pub async fn start(
+        app: &AppHandle,
+        user_id: &str,
+        endpoint_ids: &[String],
+    ) -> Result<Self, String> {
+        let database_path = utils::get_database_path(app).to_string_lossy().to_string();
+
+        let mut inbounds = Vec::new();
+        let mut outbounds = Vec::new();
+        let mut rules = Vec::new();
+        let mut routes = HashMap::new();
+        let mut used_ports = HashSet::new();
+        for (index, endpoint_id) in endpoint_ids.iter().enumerate() {
+            let outbound = v2ray_config::generate_config(
+                user_id.to_string(),
+                endpoint_id.clone(),
+                database_path.clone(),
+            )
+            .await
+            .and_then(|config| {
+                serde_json::from_str::<Value>(&config)
+                    .map_err(|e| format!("Failed to parse generated config: {}", e))
+            })
+            .and_then(|config| {
+                config["outbounds"]
+                    .get(0)
+                    .cloned()
+                    .ok_or_else(|| "Generated config has no proxy outbound".to_string())
+            });
+            let mut outbound = match outbound {
+                Ok(outbound) => outbound,
+                Err(e) => {
+                    routes.insert(endpoint_id.clone(), Err(e));
+                    continue;
+                }
+            };
+
+            let port = (0..20)
+                .filter_map(|_| portpicker::pick_unused_port())
+                .find(|port| used_ports.insert(*port))
+                .ok_or("Could not find a free TCP port")?;
+            let inbound_tag = format!("probe-in-{}", index);
+            let outbound_tag = format!("probe-out-{}", index);
+            outbound["tag"] = json!(outbound_tag);
+
+            inbounds.push(json!({
+                "listen": "127.0.0.1",
+                "port": port,
+                "protocol": "socks",
+                "tag": inbound_tag,
+                "settings": { "auth": "noauth", "udp": false }
+            }));
+            outbounds.push(outbound);
+            rules.push(json!({
+                "type": "field",
+                "inboundTag": [inbound_tag],
+                "outboundTag": outbound_tag
+            }));
+            routes.insert(endpoint_id.clone(), Ok(port));
+        }
+
+        let Some(ready_port) = used_ports.iter().next().copied() else {
+            return Ok(ProbeCore {
+                child: None,
+                config_path: None,
+                routes,
+            });
+        };
+
+        let config = json!({
+            "log": { "loglevel": "warning" },
+            "inbounds": inbounds,
+            "outbounds": outbounds,
+            "routing": {
+                "settings": { "domainStrategy": "AsIs", "rules": rules }
+            }
+        });
+        // Named after one of its ports, which no other running probe can hold
+        let config_path = app
+            .path()
+            .resolve(
+                format!("probe-{}.json", ready_port),
+                path::BaseDirectory::AppData,
+            )
+            .map_err(|e| format!("Failed to resolve probe config path: {}", e))?;
+        std::fs::write(&config_path, config.to_string())
+            .map_err(|e| format!("Failed to write probe config: {}", e))?;
+
+        let spawned = app
+            .shell()
+            .sidecar("v2ray")
+            .map_err(|e| e.to_string())
+            .and_then(|sidecar| {
+                sidecar
+                    .args(["run", "-c", &config_path.to_string_lossy()])
+                    .spawn()
+                    .map_err(|e| e.to_string())
+            });
+        let (mut rx, child) = match spawned {
+            Ok(spawned) => spawned,
+            Err(e) => {
+                let _ = std::fs::remove_file(&config_path);
+                return Err(format!("Failed to start probe core: {}", e));
+            }
+        };
+        let probe = ProbeCore {
+            child: Some(child),
+            config_path: Some(config_path),
+            routes,
+        };
+
+        let (exit_tx, mut exit_rx) = tokio::sync::oneshot::channel::<()>();
+        tauri::async_runtime::spawn(async move {
+            let mut exit_tx = Some(exit_tx);
+            while let Some(event) = rx.recv().await {
+                match event {
+                    CommandEvent::Stderr(line) => {
+                        warn!("[probe] {}", String::from_utf8_lossy(&line).trim_end());
+                    }
+                    CommandEvent::Terminated(_) => {
+                        if let Some(tx) = exit_tx.take() {
+                            let _ = tx.send(());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        // All inbounds come up together, one accepting means the core is ready
+        let deadline = Instant::now() + READY_TIMEOUT;
+        loop {
+            if TcpStream::connect(("127.0.0.1", ready_port)).await.is_ok() {
+                return Ok(probe);
+            }
+            if exit_rx.try_recv().is_ok() {
+                return Err("Probe core exited during startup".to_string());
+            }
+            if Instant::now() >= deadline {
+                return Err("Probe core did not start in time".to_string());
+            }
+            tokio::time::sleep(READY_POLL_INTERVAL).await;
+        }
+    }
+
+    /// The local socks port routed through `endpoint_id`.
+    pub fn socks_port(&self, endpoint_id: &str) -> Result<u16, String> {
+        self.routes
+            .get(endpoint_id)
+            .cloned()
+            .unwrap_or_else(|| Err("Endpoint is not part of this probe".to_string()))
+    }
+}
+
+impl Drop for ProbeCore {
+    fn drop(&mut self) {
+        if let Some(child) = self.child.take() {
+            if let Err(e) = child.kill() {
+                error!("Failed to stop probe core: {}", e);
+            }
+        }
+        if let Some(config_path) = self.config_path.take() {
+            let _ = std::fs::remove_file(config_path);
+        }
+    }
+}
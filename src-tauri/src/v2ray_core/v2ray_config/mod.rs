@@ -4,6 +4,9 @@ use sqlx::sqlite::SqlitePoolOptions;
 use sqlx::Row;
 use std::collections::HashMap;
 
+/// Tag used for the proxy outbound when the endpoint row does not set one.
+pub const PROXY_OUTBOUND_TAG: &str = "proxy";
+
 // Define your configuration structures based on the provided JSON structure.
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -214,7 +217,14 @@ struct OutboundSettings {
 struct VNext {
     address: String,
     port: u16,
-    users: Vec<User>,
+    users: Users,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum Users {
+    Vmess(Vec<User>),
+    Vless(Vec<VlessUser>),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -226,6 +236,15 @@ struct User {
     security: String,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+struct VlessUser {
+    id: String,
+    encryption: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    flow: Option<String>,
+    level: u32,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct ShadowsocksServer {
     method: String,
@@ -398,7 +417,11 @@ pub async fn generate_config(
     let mux_enabled: bool = outbound_row.get::<i64, &str>("MuxEnabled") == 1;
     let mux_concurrency: u32 = outbound_row.get::<i64, &str>("MuxConcurrency") as u32;
     let protocol: String = outbound_row.get("Protocol");
-    let tag: String = outbound_row.get("Tag");
+    // Endpoints created by the UI leave Tag NULL; the tag must be stable so the
+    // outbound can be replaced on a running core
+    let tag: String = outbound_row
+        .get::<Option<String>, &str>("Tag")
+        .unwrap_or_else(|| PROXY_OUTBOUND_TAG.to_string());
     let _send_through: String = outbound_row.get("SendThrough"); // Not used in current config
 
     // Fetch StreamSettings
@@ -501,7 +524,12 @@ pub async fn generate_config(
             let method: Option<String> = http_row.get("Method");
 
             let http_settings = Http2Settings {
-                host: vec![host],
+                // An empty list makes the core send the server address
+                host: if host.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![host]
+                },
                 path: path.unwrap_or_else(|| "/".to_string()),
                 method: method.unwrap_or_else(|| "PUT".to_string()),
             };
@@ -617,7 +645,7 @@ pub async fn generate_config(
                 vnext_list.push(VNext {
                     address,
                     port,
-                    users,
+                    users: Users::Vmess(users),
                 });
             }
 
@@ -628,6 +656,35 @@ pub async fn generate_config(
                 blackhole: None,
             }
         }
+        "vless" => {
+            let vless_row = sqlx::query(
+                "SELECT Address, Port, UUID, Encryption, Flow, Level FROM VlessServers WHERE EndpointID = ?",
+            )
+            .bind(&endpoint_id)
+            .fetch_one(&pool)
+            .await
+            .map_err(|e| format!("Failed to fetch VlessServers: {}", e))?;
+
+            let user = VlessUser {
+                id: vless_row.get("UUID"),
+                encryption: vless_row.get("Encryption"),
+                flow: vless_row
+                    .get::<Option<String>, &str>("Flow")
+                    .filter(|flow| !flow.is_empty()),
+                level: vless_row.get::<i64, &str>("Level") as u32,
+            };
+
+            OutboundSettings {
+                vnext: Some(vec![VNext {
+                    address: vless_row.get("Address"),
+                    port: vless_row.get::<i64, &str>("Port") as u16,
+                    users: Users::Vless(vec![user]),
+                }]),
+                servers: None,
+                freedom: None,
+                blackhole: None,
+            }
+        }
         "shadowsocks" => {
             // Fetch Shadowsocks configurations
             let ss_rows = sqlx::query(